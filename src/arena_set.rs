@@ -0,0 +1,84 @@
+//! Set of address spaces behind one allocator front, usable without std
+//!
+//! Kernels usually discover several memory regions at boot: each one gets its own buddy tree
+//! and allocations spill from one region to the next (first fit).
+
+use super::inner_allocator::{BuddyError, InnerAllocator};
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+/// Fixed capacity set of up to N address spaces
+pub struct ArenaSet<'a, const N: usize, const M: usize> {
+    regions: [Option<InnerAllocator<'a, M>>; N],
+}
+
+impl<'a, const N: usize, const M: usize> ArenaSet<'a, N, M> {
+    /// Create an empty set
+    pub fn new() -> Self {
+        Self {
+            regions: core::array::from_fn(|_| None),
+        }
+    }
+    /// Add a region (metadata are written inside), return its index into the set
+    /// A region with a bad length or alignment is an InvalidAddressSpace error
+    pub fn add_region(&mut self, region: &'a mut [u8]) -> Result<usize, BuddyError> {
+        let (index, slot) = self
+            .regions
+            .iter_mut()
+            .enumerate()
+            .find(|(_, slot)| slot.is_none())
+            .ok_or(BuddyError::NoMoreSpace)?;
+        *slot = Some(InnerAllocator::try_new_from_refs(region, None)?);
+        Ok(index)
+    }
+    /// Allocate memory from the first region which can satisfy the request
+    pub fn allocate(&mut self, layout: Layout) -> Result<NonNull<[u8]>, BuddyError> {
+        self.allocate_located(layout).map(|(non_null, _)| non_null)
+    }
+    /// Allocate memory like allocate(), and tell the index of the region used
+    pub fn allocate_located(
+        &mut self,
+        layout: Layout,
+    ) -> Result<(NonNull<[u8]>, usize), BuddyError> {
+        let mut error = BuddyError::CannotFit;
+        let regions = self.regions.iter_mut().enumerate();
+        for (index, region) in regions.filter_map(|(i, r)| r.as_mut().map(|r| (i, r))) {
+            match region.alloc(layout) {
+                Ok(non_null) => return Ok((non_null, index)),
+                // ___ Keep NoMoreSpace if at least one region may fit later ___
+                Err(BuddyError::NoMoreSpace) => error = BuddyError::NoMoreSpace,
+                Err(_) => {}
+            }
+        }
+        Err(error)
+    }
+    /// Deallocate memory into the region which owns it
+    pub fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) -> Result<(), BuddyError> {
+        self.regions
+            .iter_mut()
+            .flatten()
+            .find(|region| region.owns(ptr))
+            .ok_or(BuddyError::DoubleFreeOrCorruption)?
+            .dealloc(ptr, layout)
+    }
+    /// Deallocate memory into the region given by allocate_located(), without searching it
+    pub fn deallocate_located(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        region: usize,
+    ) -> Result<(), BuddyError> {
+        self.regions
+            .get_mut(region)
+            .and_then(Option::as_mut)
+            .ok_or(BuddyError::DoubleFreeOrCorruption)?
+            .dealloc(ptr, layout)
+    }
+}
+
+impl<'a, const N: usize, const M: usize> Default for ArenaSet<'a, N, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
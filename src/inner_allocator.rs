@@ -2,26 +2,93 @@ mod math;
 #[macro_use]
 mod macros;
 
-use math::{round_up_2, trailing_zero_right};
+use math::{round_up_2, round_up_2_checked, trailing_zero_right};
 
 use core::alloc::Layout;
-use core::ptr::NonNull;
+#[cfg(feature = "count-accesses")]
+use core::cell::Cell;
+use core::fmt;
+use core::ops::{Deref, DerefMut, Range};
+use core::panic::Location;
+use core::ptr::{self, NonNull};
 
 /// Allowed size of the smallest buddy
 pub const MIN_CELL_LEN: usize = 8; // arbitrary choice
 /// TODO: The alignment constraint must be reviewed
 pub const MAX_SUPPORTED_ALIGN: usize = 4096; // unix standard page size
+/// Default alignment of metadata outside of the address space: a cache line
+pub const METADATA_ALIGN: usize = 64;
 /// Minimum number of buddy allowed
 pub const MIN_BUDDY_NB: usize = 4; // arbitrary choice
+/// Byte written on each new block with the poison feature
+#[cfg(feature = "poison")]
+pub const POISON_ALLOC: u8 = 0xab;
+/// Byte written on each freed block with the poison feature
+#[cfg(feature = "poison")]
+pub const POISON_FREE: u8 = 0xdd;
+/// Value of the first metadata byte until the tree is written (on the first use)
+pub const METADATA_UNINIT: u8 = 0x42;
+/// Value of the first metadata byte once the tree is written
+pub const METADATA_READY: u8 = 0xff;
+/// Bit set on the order byte of an occupied node (unless the BITMAP mode is used)
+pub const OCCUPIED_FLAG: u8 = 0x80;
+
+/// Number of orders which may retain freed blocks, see InnerAllocator::set_retain_per_order()
+pub const RETAIN_ORDERS: usize = 32;
+/// Blocks kept ready by the eager split mode, see InnerAllocator::set_split_mode()
+pub const EAGER_BLOCKS: usize = 16;
 
 const FIRST_INDEX: usize = 1; // index 0 is never used
 
 /// Reference a valid Address Space
 /// Inner part of BuddyAllocator and StaticBuddyAllocator
-pub struct InnerAllocator<'a, const M: usize> {
+///
+/// `M` (min cell len) must be a power of two, at least `MIN_CELL_LEN`:
+/// ```compile_fail
+/// # use night_buddy_allocator::InnerAllocator;
+/// let mut memory = [0_u8; 4096];
+/// let _ = InnerAllocator::<48>::new_from_refs(&mut memory, None);
+/// ```
+/// ```compile_fail
+/// # use night_buddy_allocator::InnerAllocator;
+/// let mut memory = [0_u8; 4096];
+/// let _ = InnerAllocator::<4>::new_from_refs(&mut memory, None);
+/// ```
+///
+/// When `BITMAP` is set, the occupied flag is not packed into bit 7 of the order byte but
+/// stored into a separate bit array placed right after the order bytes. The metadata area
+/// then needs `allocable_len / M / 4` more bytes (see `metadata_len()`)
+pub struct InnerAllocator<'a, const M: usize, const BITMAP: bool = false> {
     arena: &'a mut [u8],
     meta: &'a mut [u8],
     allocable_len: usize,
+    ring_cursor: Option<usize>,
+    prefer_high: bool,
+    idempotent_free: bool,
+    dirty: bool,
+    retention: Retention,
+    split_mode: SplitMode,
+    eager: EagerList,
+    splits: usize,
+    last_error: Option<BuddyError>,
+    locations: Option<&'a mut [Option<&'static Location<'static>>]>,
+    requested: Option<&'a mut [usize]>,
+    last_use: Option<&'a mut [u64]>,
+    clock: u64,
+    #[cfg(feature = "histogram")]
+    align_histogram: [u64; 10],
+    #[cfg(feature = "count-accesses")]
+    accesses: (Cell<usize>, Cell<usize>),
+    #[cfg(feature = "stats")]
+    used: Option<usize>,
+    #[cfg(feature = "stats")]
+    peak: usize,
+    #[cfg(feature = "pmem")]
+    flush: Option<fn(*const u8, usize)>,
+    #[cfg(feature = "pmem")]
+    journal: Option<fn(usize, u8, u8)>,
+    #[cfg(feature = "relax-overalignment")]
+    max_useful_align: Option<usize>,
 }
 
 /// Use only for static allocation
@@ -42,20 +109,301 @@ where
     pub const fn new() -> Self {
         let mut meta: [u8; SIZE / M * 2] = [0; SIZE / M * 2];
         let arena: [u8; SIZE] = [0; SIZE];
-        meta[0] = 0x42; // Tell metadata must be writed
+        meta[0] = METADATA_UNINIT; // Tell metadata must be writed
         Self { arena, meta }
     }
 }
+impl<const SIZE: usize, const M: usize> Default for StaticAddressSpace<SIZE, M>
+where
+    [(); SIZE / M * 2]:,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Check every parameter of a static allocator before wiring it, with clear messages instead
+/// of errors deep into trait resolution
+/// ```
+/// # #![allow(incomplete_features)]
+/// # #![feature(generic_const_exprs)]
+/// use night_buddy_allocator::{InnerAllocator, ProtectedAllocator, StaticAddressSpace};
+/// use night_buddy_allocator::StaticBuddyBuilder;
+/// type Builder = StaticBuddyBuilder<{ 1 << 16 }, 64>;
+/// static mut SPACE: StaticAddressSpace<{ 1 << 16 }, 64> = Builder::space();
+/// // std::sync::Mutex is a RwMutex without the no-std feature only
+/// # #[cfg(not(feature = "no-std"))]
+/// {
+///     use std::sync::Mutex;
+///     static ALLOCATOR: ProtectedAllocator<Mutex<InnerAllocator<64>>, 64> =
+///         ProtectedAllocator::new(Mutex::new(Builder::build(unsafe { &mut SPACE })), None);
+///     assert!(ALLOCATOR.total_free().unwrap() == 1 << 16);
+/// }
+/// ```
+/// SIZE must be a power of two:
+/// ```compile_fail
+/// # #![allow(incomplete_features)]
+/// # #![feature(generic_const_exprs)]
+/// # use night_buddy_allocator::{StaticAddressSpace, StaticBuddyBuilder};
+/// static mut SPACE: StaticAddressSpace<3000, 64> = StaticBuddyBuilder::<3000, 64>::space();
+/// ```
+/// It must hold MIN_BUDDY_NB cells at least:
+/// ```compile_fail
+/// # #![allow(incomplete_features)]
+/// # #![feature(generic_const_exprs)]
+/// # use night_buddy_allocator::{StaticAddressSpace, StaticBuddyBuilder};
+/// static mut SPACE: StaticAddressSpace<128, 64> = StaticBuddyBuilder::<128, 64>::space();
+/// ```
+/// And M must be a power of two, at least MIN_CELL_LEN:
+/// ```compile_fail
+/// # #![allow(incomplete_features)]
+/// # #![feature(generic_const_exprs)]
+/// # use night_buddy_allocator::{StaticAddressSpace, StaticBuddyBuilder};
+/// static mut SPACE: StaticAddressSpace<4096, 48> = StaticBuddyBuilder::<4096, 48>::space();
+/// ```
+pub struct StaticBuddyBuilder<const SIZE: usize, const M: usize>;
+
+impl<const SIZE: usize, const M: usize> StaticBuddyBuilder<SIZE, M>
+where
+    [(); SIZE / M * 2]:,
+{
+    const VALID: () = {
+        #[allow(clippy::let_unit_value)]
+        let _ = CellLen::<M>::VALID;
+        assert!(
+            SIZE.is_power_of_two(),
+            "SIZE (address space len) must be a power of two"
+        );
+        assert!(
+            SIZE / M >= MIN_BUDDY_NB,
+            "SIZE (address space len) must hold MIN_BUDDY_NB cells of M bytes at least"
+        );
+    };
+
+    /// Address space for the static allocator, its metadata array is sized for the tree of SIZE
+    pub const fn space() -> StaticAddressSpace<SIZE, M> {
+        #[allow(clippy::let_unit_value)]
+        let _ = Self::VALID;
+        StaticAddressSpace::new()
+    }
+
+    /// Inner allocator over 'address_space', ready to be put into a mutex
+    pub const fn build(
+        address_space: &'static mut StaticAddressSpace<SIZE, M>,
+    ) -> InnerAllocator<'static, M> {
+        #[allow(clippy::let_unit_value)]
+        let _ = Self::VALID;
+        InnerAllocator::new_from_static(address_space)
+    }
+}
+
+/// Metadata of a shard, alone on its cache lines (METADATA_ALIGN)
+///
+/// Every allocation updates the metadata from a leaf up to the root. When the metadata of
+/// several shards share a cache line, the cores working on different shards keep invalidating
+/// it for each other (false sharing) although they never touch the same bytes
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C, align(64))]
+pub struct CacheAligned<T>(pub T);
+
+impl<T> Deref for CacheAligned<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for CacheAligned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+/// Total size of a StaticAddressSpace (datas and metadata), to check it fits in a section
+/// ```
+/// # #![allow(incomplete_features)]
+/// # #![feature(generic_const_exprs)]
+/// use night_buddy_allocator::{static_space_size, StaticAddressSpace};
+/// const SIZE: usize = 1 << 16;
+/// const SECTION_LIMIT: usize = 1 << 17;
+/// assert!(static_space_size::<SIZE, 64>() <= SECTION_LIMIT);
+/// assert_eq!(
+///     static_space_size::<SIZE, 64>(),
+///     core::mem::size_of::<StaticAddressSpace<SIZE, 64>>()
+/// );
+/// ```
+pub const fn static_space_size<const SIZE: usize, const M: usize>() -> usize
+where
+    [(); SIZE / M * 2]:,
+{
+    core::mem::size_of::<StaticAddressSpace<SIZE, M>>()
+}
+
+/// Suggest M for a workload: a power of two of at least MIN_CELL_LEN, min_object_size and
+/// max_align (capped at MAX_SUPPORTED_ALIGN), as BuddySize::try_from() would round
+/// ```
+/// use night_buddy_allocator::recommended_min_cell_len;
+/// assert_eq!(recommended_min_cell_len(16, 64), 64);
+/// assert_eq!(recommended_min_cell_len(1, 24), 32);
+/// ```
+pub const fn recommended_min_cell_len(max_align: usize, min_object_size: usize) -> usize {
+    let align = if max_align > MAX_SUPPORTED_ALIGN {
+        MAX_SUPPORTED_ALIGN
+    } else {
+        max_align
+    };
+    let mut cell_len = MIN_CELL_LEN;
+    if min_object_size > cell_len {
+        cell_len = min_object_size;
+    }
+    if align > cell_len {
+        cell_len = align;
+    }
+    round_up_2(cell_len)
+}
+
+/// Allocate from a StaticAddressSpace during const evaluation, return the offset of the block
+/// into the arena, None if it cannot be allocated. The block stays allocated for the
+/// InnerAllocator made later by new_from_static(). Same rules as InnerAllocator::alloc()
+pub const fn const_alloc<const M: usize, const SIZE: usize>(
+    space: &mut StaticAddressSpace<SIZE, M>,
+    layout: Layout,
+) -> Option<usize>
+where
+    [(); SIZE / M * 2]:,
+{
+    let meta = &mut space.meta;
+    // ___ Cannot use Iterator, min!, max! or TryFrom in const fn: C style ___
+    let mut size = M;
+    if layout.size() > size {
+        size = layout.size();
+    }
+    if layout.align() > size {
+        size = layout.align();
+    }
+    if layout.align() > MAX_SUPPORTED_ALIGN || size > SIZE {
+        return None;
+    }
+    let buddy_size = round_up_2(size);
+    let max_order = trailing_zero_right(SIZE / M) as u8;
+    let order = trailing_zero_right(SIZE / buddy_size) as u8;
+    // ___ Write original metadatas ___
+    if meta[0] == METADATA_UNINIT {
+        let (mut current_order, mut members, mut index) = (0, 2, 0);
+        while index < meta.len() {
+            members -= 1;
+            meta[index] = current_order;
+            if members == 0 {
+                current_order += 1;
+                members = 1 << current_order;
+            }
+            index += 1;
+        }
+        meta[0] = METADATA_READY;
+    }
+    if order < meta[FIRST_INDEX] {
+        return None;
+    }
+    // ___ Find the best fited block ___
+    let (mut index, mut current_order) = (FIRST_INDEX, 0);
+    while current_order < order {
+        index = if meta[2 * index] <= order {
+            2 * index
+        } else {
+            2 * index + 1
+        };
+        current_order += 1;
+    }
+    let offset = (SIZE >> order) * (index - (1 << order));
+    // ___ Mark as occupied then report changes on parents ___
+    meta[index] = OCCUPIED_FLAG + max_order + 1;
+    while index > FIRST_INDEX {
+        let parent = index / 2;
+        let left = meta[2 * parent] & !OCCUPIED_FLAG;
+        let right = meta[2 * parent + 1] & !OCCUPIED_FLAG;
+        let new_order = if left < right { left } else { right };
+        if meta[parent] == new_order {
+            break;
+        }
+        meta[parent] = new_order;
+        index = parent;
+    }
+    Some(offset)
+}
+
+/// Tell if dealloc() needs a double width multiplication to find the block index, for an
+/// address space of 'arena_len' bytes: offset * 2^order can reach arena_len * arena_len / M
+pub const fn needs_wide_math<const M: usize>(arena_len: usize) -> bool {
+    arena_len.checked_mul(arena_len / M).is_none()
+}
+
+/// Heap index of the block at 'offset' of 'order', when offset * 2^order fits into usize
+#[inline(always)]
+pub(crate) const fn block_index_narrow(offset: usize, order: Order, len: usize) -> usize {
+    (1 << order.0) + offset * (1 << order.0) / len
+}
+
+/// Heap index of the block at 'offset' of 'order', for any address space
+#[inline(always)]
+pub(crate) const fn block_index_wide(offset: usize, order: Order, len: usize) -> usize {
+    // Cast as u64 to avoid mul overflow on 32bits target
+    #[cfg(target_pointer_width = "32")]
+    let index = (offset as u64 * (1 << order.0) as u64 / len as u64) as usize;
+    // Cast as u128 to avoid mul overflow on 64bits target
+    #[cfg(target_pointer_width = "64")]
+    let index = (offset as u128 * (1 << order.0) as u128 / len as u128) as usize;
+    (1 << order.0) + index
+}
+
+/// Metadata bytes needed for an address space of 'len' bytes
+const fn metadata_len<const M: usize, const BITMAP: bool>(len: usize) -> usize {
+    if BITMAP {
+        // One order byte plus one occupied bit per node
+        len / M * 2 + len / M / 4
+    } else {
+        len / M * 2
+    }
+}
+
+/// Compile time validation of the min cell len
+struct CellLen<const M: usize>;
+impl<const M: usize> CellLen<M> {
+    const VALID: () = {
+        assert!(
+            M.is_power_of_two(),
+            "M (min cell len) must be a power of two"
+        );
+        assert!(
+            M >= MIN_CELL_LEN,
+            "M (min cell len) must be at least MIN_CELL_LEN"
+        );
+    };
+}
 
 /// Initialisation, organise l'espace memoire en inscrivant les metadonnees necessaires.
-const fn check<const M: usize>(input: &mut [u8]) -> usize {
+#[cfg(not(feature = "no-panic"))]
+const fn check<const M: usize, const BITMAP: bool>(input: &mut [u8]) -> usize {
+    match try_check::<M, BITMAP>(input) {
+        Ok(metadata_len) => metadata_len,
+        Err(_) => panic!("Address space len must be a power of two of M * MIN_BUDDY_NB or more"),
+    }
+}
+
+/// Same as check(), with an error instead of a panic
+const fn try_check<const M: usize, const BITMAP: bool>(
+    input: &mut [u8],
+) -> Result<usize, BuddyError> {
+    // ___ M is a const generic, so bad values are rejected at compile time ___
+    #[allow(clippy::let_unit_value)]
+    let _ = CellLen::<M>::VALID;
     // ___ MAX LEN OF ADDRESS SPACE IS CONSTRAINED BY USIZE BIT SCHEME, DEPENDS OF ARCH ___
-    assert!(M >= MIN_CELL_LEN);
     // ___ Four Buddy minimum are allowed but is not optimal at all ___
-    assert!(M <= usize::MAX / MIN_BUDDY_NB + 1);
-    assert!(input.len() == usize::MAX || input.len() >= M * MIN_BUDDY_NB);
-    assert!(input.len() == usize::MAX || round_up_2(input.len()) == input.len());
-    assert!(round_up_2(M) == M);
+    if M > usize::MAX / MIN_BUDDY_NB + 1
+        || input.len() != usize::MAX && input.len() < M * MIN_BUDDY_NB
+        || input.len() != usize::MAX && !round_up_2_checked(input.len()).1
+    {
+        return Err(BuddyError::InvalidAddressSpace);
+    }
     let current_align = if input.len() > MAX_SUPPORTED_ALIGN {
         MAX_SUPPORTED_ALIGN
     } else {
@@ -64,13 +412,144 @@ const fn check<const M: usize>(input: &mut [u8]) -> usize {
     let ptr_offset = input.as_mut_ptr().align_offset(current_align);
     // IMPORTANT: On compile time with const fn feature, align_offset() doesn't works
     // and returns USIZE::MAX. Trust on you. Can't be sure...
-    assert!(ptr_offset == 0 || ptr_offset == usize::MAX); // Check pointer alignement
-    input.len() / M * 2
+    if ptr_offset != 0 && ptr_offset != usize::MAX {
+        return Err(BuddyError::InvalidAddressSpace); // Check pointer alignement
+    }
+    Ok(metadata_len::<M, BITMAP>(input.len()))
+}
+
+/// Bytes needed by the metadata tree: 2^(max_order) * 2, TooBigSize if it overflows usize
+#[inline(always)]
+pub fn tree_len(max_order: Order) -> Result<usize, BuddyError> {
+    1_usize
+        .checked_shl(max_order.0 as u32 + 1)
+        .ok_or(BuddyError::TooBigSize)
+}
+
+/// How a Layout would be allocated, see InnerAllocator::explain()
+#[derive(Debug, Clone)]
+pub struct AllocationPlan {
+    /// Size of the buddy, the request rounded up
+    pub buddy_size: usize,
+    /// Depth of the buddy into the tree, 0 is the whole address space
+    pub order: u8,
+    /// Heap indexes which can be consulted during the descent
+    pub descent: Range<usize>,
+    /// Heap index of the block that would be taken, None if there is no space
+    pub index: Option<usize>,
+}
+
+/// Pairs of free buddies kept uncoalesced: the limit and the count by order
+struct Retention {
+    limits: [u8; RETAIN_ORDERS],
+    retained: [u8; RETAIN_ORDERS],
+}
+
+impl Retention {
+    #[inline(always)]
+    fn is_empty(&self) -> bool {
+        self.retained.iter().all(|retained| *retained == 0)
+    }
+}
+
+/// How a free block is found for an allocation, see InnerAllocator::set_split_mode()
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum SplitMode {
+    /// Descent of the tree on each allocation, a bigger block is split on the way
+    #[default]
+    Lazy,
+    /// Blocks of this size (rounded up to a buddy size) are split by EAGER_BLOCKS from a
+    /// bigger one and the freed ones are kept ready: an allocation pops one
+    Eager(usize),
+}
+
+/// Free blocks of one order kept ready by the eager split mode. Another path may take them
+/// meanwhile, they are checked before use
+struct EagerList {
+    order: Option<Order>,
+    blocks: [usize; EAGER_BLOCKS],
+    len: usize,
+}
+
+/// Parameters of an allocator without its state, see InnerAllocator::config()
+#[derive(Debug, Copy, Clone, Default)]
+pub struct AllocatorConfig {
+    /// Error hook of the ProtectedAllocator, unused by InnerAllocator
+    pub error_hook: Option<fn(BuddyError)>,
+    /// Wipe of the freed blocks by the ProtectedAllocator, unused by InnerAllocator
+    pub zero_on_free: bool,
+    /// See InnerAllocator::set_ring_mode()
+    pub ring_mode: bool,
+    /// See InnerAllocator::set_prefer_high()
+    pub prefer_high: bool,
+    /// See InnerAllocator::set_idempotent_free()
+    pub idempotent_free: bool,
+    /// See InnerAllocator::set_retain_per_order()
+    pub retain_per_order: [u8; RETAIN_ORDERS],
+    /// See InnerAllocator::set_split_mode()
+    pub split_mode: SplitMode,
+    /// See InnerAllocator::set_flush()
+    #[cfg(feature = "pmem")]
+    pub flush: Option<fn(*const u8, usize)>,
+    /// See InnerAllocator::set_journal()
+    #[cfg(feature = "pmem")]
+    pub journal: Option<fn(usize, u8, u8)>,
+    /// See InnerAllocator::set_max_useful_align()
+    #[cfg(feature = "relax-overalignment")]
+    pub max_useful_align: Option<usize>,
+}
+
+/// Point in time view of the allocator, see InnerAllocator::snapshot_stats()
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Stats {
+    /// Free bytes, maybe scattered into many blocks
+    pub free: usize,
+    /// Bytes held by live blocks, including the metadata block when it is inside the arena
+    pub used: usize,
+    /// Size of the biggest block that can be allocated right now
+    pub largest_free: usize,
+    /// 0.0 when all the free space is contiguous, near 1.0 when it is scattered
+    pub fragmentation: f32,
+    /// Number of live blocks
+    pub live_allocations: usize,
+}
+
+impl Stats {
+    /// Write the statistics as Prometheus gauges named 'prefix'_free_bytes etc.
+    pub fn write_prometheus(&self, f: &mut impl fmt::Write, prefix: &str) -> fmt::Result {
+        let gauges: [(&str, &str, &dyn fmt::Display); 5] = [
+            ("free_bytes", "Free bytes, maybe scattered", &self.free),
+            ("used_bytes", "Bytes held by live blocks", &self.used),
+            (
+                "largest_free_block",
+                "Biggest allocable block in bytes",
+                &self.largest_free,
+            ),
+            (
+                "fragmentation",
+                "0 when the free space is contiguous",
+                &self.fragmentation,
+            ),
+            (
+                "live_allocations",
+                "Number of live blocks",
+                &self.live_allocations,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            writeln!(f, "# HELP {}_{} {}", prefix, name, help)?;
+            writeln!(f, "# TYPE {}_{} gauge", prefix, name)?;
+            writeln!(f, "{}_{} {}", prefix, name, value)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct BuddySize<const M: usize>(pub usize);
-#[derive(Debug, Copy, Clone)]
+/// Depth of a block into the tree: a block of order N is 2^N times smaller than the address
+/// space
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Order(pub u8);
 
 enum Op {
@@ -79,27 +558,6 @@ enum Op {
 }
 
 impl<'a, const M: usize> InnerAllocator<'a, M> {
-    /// TODO
-    pub fn new_from_refs(ref_arena: &'a mut [u8], ref_meta: Option<&'a mut [u8]>) -> Self {
-        let allocable_len = ref_arena.len();
-        let metadata_size = check::<M>(ref_arena);
-        let out = if let Some(meta) = ref_meta {
-            Self {
-                arena: ref_arena,
-                meta,
-                allocable_len,
-            }
-        } else {
-            let (meta, arena) = ref_arena.split_at_mut(max!(metadata_size, M));
-            Self {
-                arena,
-                meta,
-                allocable_len,
-            }
-        };
-        out.meta[0] = 0x42; // Tell metadata must be writed
-        out
-    }
     /// TODO
     pub const fn new_from_static<const SIZE: usize>(
         address_space: &'static mut StaticAddressSpace<SIZE, M>,
@@ -108,158 +566,1549 @@ impl<'a, const M: usize> InnerAllocator<'a, M> {
         [(); SIZE / M * 2]:,
     {
         let allocable_len = address_space.arena.len();
-        let out = Self {
-            meta: &mut address_space.meta,
-            arena: &mut address_space.arena,
+        let out = Self::from_parts(
+            &mut address_space.arena,
+            &mut address_space.meta,
             allocable_len,
+        );
+        #[cfg(not(feature = "no-panic"))]
+        {
+            let metadata_size = check::<M, false>(out.arena);
+            assert!(metadata_size == out.meta.len());
+        }
+        // ___ The space is aligned and its metadata array fits SIZE: only SIZE is checked, at
+        // compile time ___
+        #[cfg(feature = "no-panic")]
+        #[allow(clippy::let_unit_value)]
+        let _ = StaticBuddyBuilder::<SIZE, M>::VALID;
+        out
+    }
+    /// Release the upper half of the address space when it is entirely free: the metadata tree
+    /// then covers the lower half only. Return the released tail (base, len), for the caller
+    /// to unmap it
+    pub fn try_shrink_arena(&mut self) -> Option<(*mut u8, usize)> {
+        if !self.check_metadata() {
+            return None;
+        }
+        self.coalesce_retained();
+        let half = self.allocable_len / 2;
+        if half < M * MIN_BUDDY_NB || !self.is_free_block(2 * FIRST_INDEX + 1, Order(1)) {
+            return None;
+        }
+        // ___ The left subtree becomes the tree: node i at depth d was node i + 2^d ___
+        // All orders lose one, and the occupied value is still 'max order' + 1
+        for index in FIRST_INDEX..half / M * 2 {
+            let depth = usize::BITS - 1 - index.leading_zeros();
+            self.meta[index] = self.meta[index + (1 << depth)] - 1;
+            if let Some(table) = self.locations.as_deref_mut() {
+                table[index] = table[index + (1 << depth)];
+            }
+            if let Some(table) = self.requested.as_deref_mut() {
+                table[index] = table[index + (1 << depth)];
+            }
+            if let Some(table) = self.last_use.as_deref_mut() {
+                table[index] = table[index + (1 << depth)];
+            }
+        }
+        #[cfg(feature = "pmem")]
+        self.flush_meta(0, half / M * 2);
+        self.allocable_len = half;
+        // ___ The orders and the indexes of the ready blocks changed ___
+        self.set_split_mode(self.split_mode);
+        if let Some(cursor) = self.ring_cursor {
+            self.ring_cursor = Some(if cursor >= half { 0 } else { cursor });
+        }
+        let arena = core::mem::take(&mut self.arena);
+        let (arena, tail) = arena.split_at_mut(arena.len() - half);
+        self.arena = arena;
+        Some((tail.as_mut_ptr(), tail.len()))
+    }
+    /// Check an external metadata buffer before trusting it: the sentinel, the length and the
+    /// orders of every node which is not inside an occupied block. A wrong order is reported
+    /// as CorruptedNode with the index of the first bad node, depth first
+    pub fn validate_metadata(meta: &[u8], allocable_len: usize) -> Result<(), BuddyError> {
+        if !allocable_len.is_power_of_two() || allocable_len < M * MIN_BUDDY_NB {
+            return Err(BuddyError::DoubleFreeOrCorruption);
+        }
+        let max_order = Order::try_from((BuddySize::<M>(M), BuddySize(allocable_len)))?;
+        if meta.len() < tree_len(max_order)? {
+            return Err(BuddyError::DoubleFreeOrCorruption);
+        }
+        match meta[0] {
+            METADATA_UNINIT => Ok(()),
+            METADATA_READY => Self::validate_node(meta, FIRST_INDEX, 0, max_order),
+            _ => Err(BuddyError::DoubleFreeOrCorruption),
+        }
+    }
+    /// Walk the metadata tree of this allocator: every free node must tell the smallest order
+    /// of its children, as modify_parents() keeps it. CorruptedNode on a mismatch
+    pub fn check_integrity(&self) -> Result<(), BuddyError> {
+        Self::validate_metadata(self.meta, self.allocable_len)
+    }
+    fn validate_node(
+        meta: &[u8],
+        index: usize,
+        depth: u8,
+        max_order: Order,
+    ) -> Result<(), BuddyError> {
+        let value = meta[index];
+        let expected = if value & OCCUPIED_FLAG != 0 {
+            // ___ The content of an occupied block is meaningless, reserved ones get one more ___
+            if value == OCCUPIED_FLAG + max_order.0 + 2 {
+                value
+            } else {
+                OCCUPIED_FLAG + max_order.0 + 1
+            }
+        } else if depth == max_order.0 {
+            depth
+        } else {
+            Self::validate_node(meta, 2 * index, depth + 1, max_order)?;
+            Self::validate_node(meta, 2 * index + 1, depth + 1, max_order)?;
+            let left = meta[2 * index] & !OCCUPIED_FLAG;
+            let right = meta[2 * index + 1] & !OCCUPIED_FLAG;
+            if left == depth + 1 && right == depth + 1 {
+                // ___ Two free buddies may be retained uncoalesced ___
+                if value == depth + 1 {
+                    value
+                } else {
+                    depth
+                }
+            } else {
+                min!(left, right)
+            }
+        };
+        if value == expected {
+            Ok(())
+        } else {
+            Err(BuddyError::CorruptedNode(index))
+        }
+    }
+}
+
+impl<'a, const M: usize, const BITMAP: bool> InnerAllocator<'a, M, BITMAP> {
+    /// TODO
+    #[cfg(not(feature = "no-panic"))]
+    pub fn new_from_refs(ref_arena: &'a mut [u8], ref_meta: Option<&'a mut [u8]>) -> Self {
+        let allocable_len = ref_arena.len();
+        let metadata_size = check::<M, BITMAP>(ref_arena);
+        let out = if let Some(meta) = ref_meta {
+            Self::from_parts(ref_arena, meta, allocable_len)
+        } else {
+            let (meta, arena) = ref_arena.split_at_mut(max!(metadata_size, M));
+            Self::from_parts(arena, meta, allocable_len)
         };
-        let metadata_size = check::<M>(out.arena);
-        assert!(metadata_size == out.meta.len());
+        out.meta[0] = METADATA_UNINIT; // Tell metadata must be writed
+        out
+    }
+    /// Without panic, a bad address space or a too short metadata buffer gives an allocator
+    /// without memory: lazy_init() and the allocations fail with InvalidAddressSpace and the
+    /// introspection paths see no block. See try_new_from_refs() to get the error at once
+    #[cfg(feature = "no-panic")]
+    pub fn new_from_refs(ref_arena: &'a mut [u8], ref_meta: Option<&'a mut [u8]>) -> Self {
+        Self::try_from_refs(ref_arena, ref_meta)
+            .unwrap_or_else(|_| Self::from_parts(&mut [], &mut [], 0))
+    }
+    /// Create an allocator over 'address' (metadata are written inside) with the policies of
+    /// 'config'
+    pub fn new_with_config(address: &'a mut [u8], config: AllocatorConfig) -> Self {
+        let mut out = Self::new_from_refs(address, None);
+        out.set_ring_mode(config.ring_mode);
+        out.set_prefer_high(config.prefer_high);
+        out.set_idempotent_free(config.idempotent_free);
+        out.set_retain_per_order(config.retain_per_order);
+        out.set_split_mode(config.split_mode);
+        #[cfg(feature = "pmem")]
+        out.set_flush(config.flush);
+        #[cfg(feature = "pmem")]
+        out.set_journal(config.journal);
+        #[cfg(feature = "relax-overalignment")]
+        out.set_max_useful_align(config.max_useful_align);
         out
     }
-    /// Check if metadata are already writed
+    /// Policies of this allocator, to build another one with new_with_config()
+    pub fn config(&self) -> AllocatorConfig {
+        AllocatorConfig {
+            error_hook: None,
+            zero_on_free: false,
+            ring_mode: self.ring_cursor.is_some(),
+            prefer_high: self.prefer_high,
+            idempotent_free: self.idempotent_free,
+            retain_per_order: self.retention.limits,
+            split_mode: self.split_mode,
+            #[cfg(feature = "pmem")]
+            flush: self.flush,
+            #[cfg(feature = "pmem")]
+            journal: self.journal,
+            #[cfg(feature = "relax-overalignment")]
+            max_useful_align: self.max_useful_align,
+        }
+    }
+    /// Give back the references given to new_from_refs(), to reuse the memory
+    /// All the outstanding allocations become invalid
+    pub fn into_raw_parts(self) -> (&'a mut [u8], Option<&'a mut [u8]>) {
+        if self.allocable_len != self.arena.len() {
+            // case metadata into allocated memory area: meta and arena are the two contiguous
+            // halves of the original reference
+            let len = self.meta.len() + self.arena.len();
+            // SAFETY: split_at_mut() made them from the same slice, which lives for 'a
+            let arena = unsafe { core::slice::from_raw_parts_mut(self.meta.as_mut_ptr(), len) };
+            (arena, None)
+        } else {
+            (self.arena, Some(self.meta))
+        }
+    }
+    /// Same as new_from_refs(), but a bad address space or a too short metadata buffer is an
+    /// InvalidAddressSpace error instead of a panic. The metadata are written at once, so
+    /// lazy_init() cannot fail later
+    pub fn try_new_from_refs(
+        ref_arena: &'a mut [u8],
+        ref_meta: Option<&'a mut [u8]>,
+    ) -> Result<Self, BuddyError> {
+        let mut out = Self::try_from_refs(ref_arena, ref_meta)?;
+        out.lazy_init()?;
+        Ok(out)
+    }
+    /// Check the address space and the metadata buffer, the metadata are written on first use
+    fn try_from_refs(
+        ref_arena: &'a mut [u8],
+        ref_meta: Option<&'a mut [u8]>,
+    ) -> Result<Self, BuddyError> {
+        let allocable_len = ref_arena.len();
+        let metadata_size = try_check::<M, BITMAP>(ref_arena)?;
+        let out = match ref_meta {
+            Some(meta) if meta.len() < metadata_size => {
+                return Err(BuddyError::InvalidAddressSpace)
+            }
+            Some(meta) => Self::from_parts(ref_arena, meta, allocable_len),
+            None => {
+                let (meta, arena) = ref_arena.split_at_mut(max!(metadata_size, M));
+                Self::from_parts(arena, meta, allocable_len)
+            }
+        };
+        out.meta[0] = METADATA_UNINIT; // Tell metadata must be writed
+        Ok(out)
+    }
+    /// Assemble an allocator with all modes disabled
+    const fn from_parts(arena: &'a mut [u8], meta: &'a mut [u8], allocable_len: usize) -> Self {
+        Self {
+            arena,
+            meta,
+            allocable_len,
+            ring_cursor: None,
+            prefer_high: false,
+            idempotent_free: false,
+            dirty: false,
+            retention: Retention {
+                limits: [0; RETAIN_ORDERS],
+                retained: [0; RETAIN_ORDERS],
+            },
+            split_mode: SplitMode::Lazy,
+            eager: EagerList {
+                order: None,
+                blocks: [0; EAGER_BLOCKS],
+                len: 0,
+            },
+            splits: 0,
+            last_error: None,
+            locations: None,
+            requested: None,
+            last_use: None,
+            clock: 0,
+            #[cfg(feature = "histogram")]
+            align_histogram: [0; 10],
+            #[cfg(feature = "count-accesses")]
+            accesses: (Cell::new(0), Cell::new(0)),
+            #[cfg(feature = "stats")]
+            used: None,
+            #[cfg(feature = "stats")]
+            peak: 0,
+            #[cfg(feature = "pmem")]
+            flush: None,
+            #[cfg(feature = "pmem")]
+            journal: None,
+            #[cfg(feature = "relax-overalignment")]
+            max_useful_align: None,
+        }
+    }
+    /// Same as new_from_refs() with metadata outside of the address space, but ensure that the
+    /// metadata are aligned on 'meta_align' bytes (METADATA_ALIGN is a cache line)
+    ///
+    /// Metadata are read and writed during every alloc and free: placing them in fast local
+    /// memory (NUMA) and aligned on cache lines avoid to share the first lines with other datas
+    pub fn new_from_refs_aligned(
+        ref_arena: &'a mut [u8],
+        ref_meta: &'a mut [u8],
+        meta_align: usize,
+    ) -> Result<Self, BuddyError> {
+        if ref_meta.as_ptr().align_offset(meta_align) != 0 {
+            Err(BuddyError::MisalignedMetadata)
+        } else {
+            Ok(Self::new_from_refs(ref_arena, Some(ref_meta)))
+        }
+    }
+    /// Same as new_from_refs() with metadata inside the address space, but the (offset, size)
+    /// regions of 'reservations' are blocked out and will never be allocated
+    pub fn new_with_reservations(
+        address: &'a mut [u8],
+        reservations: &[(usize, usize)],
+    ) -> Result<Self, BuddyError> {
+        let mut out = Self::new_from_refs(address, None);
+        out.lazy_init()?;
+        for (offset, size) in reservations.iter() {
+            out.reserve_region(*offset, *size)?;
+        }
+        Ok(out)
+    }
+    /// Write the metadata if they are not already: done on the first use. It fails only when
+    /// the address space given to an unchecked constructor is not valid
+    #[inline(always)]
+    pub fn lazy_init(&mut self) -> Result<(), BuddyError> {
+        match self.meta.first() {
+            Some(&METADATA_UNINIT) => self.write_metadata()?,
+            Some(sentinel) => debug_assert!(*sentinel == METADATA_READY),
+            // ___ new_from_refs() without panic was given a bad address space ___
+            None => return Err(BuddyError::InvalidAddressSpace),
+        }
+        Ok(())
+    }
+    /// Check if metadata are already writed, for the paths which cannot return an error. They
+    /// give an empty answer when it is false, which happens only with no-panic
+    #[inline(always)]
+    fn check_metadata(&mut self) -> bool {
+        let result = self.lazy_init();
+        #[cfg(not(feature = "no-panic"))]
+        result.expect("Cannot write the metadata: invalid address space");
+        #[cfg(feature = "no-panic")]
+        let _ = result;
+        self.is_ready()
+    }
+    /// Tell if the metadata tree is written: it must be before the tree is walked
     #[inline(always)]
-    fn check_metadata(&mut self) {
-        if self.meta[0] == 0x42 {
-            self.write_metadata();
+    fn is_ready(&self) -> bool {
+        self.meta.first() == Some(&METADATA_READY)
+    }
+    fn write_metadata(&mut self) -> Result<(), BuddyError> {
+        let max_order = self.max_order();
+        // Bytes needed:       2^(order) * 2
+        // order 0.  2o        o X
+        // order 1.  4o        o X + X X
+        // order 2.  8o        o X + X X + X X X X
+        // order 3. 16o        o X + X X + X X X X + X X X X X X X X
+        // [..]
+        let bytes_needed = tree_len(max_order)?;
+        if self.meta.len() < bytes_needed {
+            return Err(BuddyError::InvalidAddressSpace);
+        }
+        // Cannot use Iterator or IntoIterator in const fn, so we use the C style loop
+        // IMPORTANT: A huge problem is that 'bytes_needed' depends of inputs params on const fn
+        // it derives from <const SIZE: usize> so space.len(). So We have to hack the compiler to
+        // allow 'infinite' eval limit. #![feature(const_eval_limit)] && #![const_eval_limit = "0"]
+        // ___ Write original metadatas ___
+        let (mut current_order, mut members, mut index) = (0, 2, 0);
+        while index < bytes_needed {
+            members -= 1;
+            self.meta[index] = current_order;
+            if members == 0 {
+                current_order += 1;
+                members = 1 << current_order;
+            }
+            index += 1;
+        }
+        // ___ Clear the occupied bits ___
+        if BITMAP {
+            let bitmap_len = bytes_needed / 8;
+            self.meta[bytes_needed..bytes_needed + bitmap_len].fill(0);
+        }
+        #[cfg(feature = "stats")]
+        {
+            self.used = Some(0);
+        }
+        // ___ Bootstrap memory for metadata ___
+        if self.allocable_len != self.arena.len() {
+            let metadata_chunk_size = round_up_2(max!(self.meta.len(), M));
+            let order = Order::try_from((
+                BuddySize::<M>(metadata_chunk_size),
+                BuddySize(self.allocable_len),
+            ))?;
+            self.set_mark(order)?;
+        }
+        // ___ The whole tree must be persistent before the done mark ___
+        #[cfg(feature = "pmem")]
+        self.flush_meta(0, self.meta.len());
+        self.meta[0] = METADATA_READY; // Mark metadata done
+        #[cfg(feature = "pmem")]
+        self.flush_meta(0, 1);
+        Ok(())
+    }
+    /// TODO
+    #[inline(always)]
+    pub fn alloc(&mut self, layout: Layout) -> Result<NonNull<[u8]>, BuddyError> {
+        self.alloc_by(layout, Self::set_mark)
+    }
+    /// Allocate a block of exactly 'huge_page_size' bytes aligned on it, beyond
+    /// MAX_SUPPORTED_ALIGN, for the OS to back it with a huge page. A buddy is aligned on its
+    /// size from the base of the tree: the base must be aligned on 'huge_page_size' too, or
+    /// TooBigAlignment. The block is freed with the layout (huge_page_size, 1)
+    pub fn alloc_hugepage(&mut self, huge_page_size: usize) -> Result<NonNull<[u8]>, BuddyError> {
+        self.lazy_init()?;
+        if !huge_page_size.is_power_of_two() {
+            return Err(BuddyError::TooBigSize);
+        }
+        if self.base_ptr().addr().get() & (huge_page_size - 1) != 0 {
+            return Err(BuddyError::TooBigAlignment);
+        }
+        let layout =
+            Layout::from_size_align(huge_page_size, 1).map_err(|_| BuddyError::TooBigSize)?;
+        self.alloc(layout)
+    }
+    /// Same as alloc(), but remember 'location' as the call site of the block until it is
+    /// freed. Nothing is recorded without a location table (see set_location_table())
+    pub fn alloc_tracked(
+        &mut self,
+        layout: Layout,
+        location: &'static Location<'static>,
+    ) -> Result<NonNull<[u8]>, BuddyError> {
+        self.alloc_by(layout, |s, order| {
+            let index = s.set_mark(order)?;
+            if let Some(table) = s.locations.as_deref_mut() {
+                table[index] = Some(location);
+            }
+            Ok(index)
+        })
+    }
+    /// Allocate only from a free block of the exact order whose buddy is already in use,
+    /// never splitting a larger free block. Return NoMoreSpace when there is none
+    pub fn alloc_avoid_splitting(&mut self, layout: Layout) -> Result<NonNull<[u8]>, BuddyError> {
+        self.alloc_by(layout, |s, order| {
+            let index = s
+                .find_split_free(FIRST_INDEX, 0, order)
+                .ok_or(BuddyError::NoMoreSpace)?;
+            s.mark_at(index, order);
+            Ok(index)
+        })
+    }
+    /// Allocate 'size' bytes which do not straddle any multiple of 'boundary' (a power of two
+    /// at least equal to size), for DMA engines. Blocks are aligned on their size only from
+    /// the begining of the address space, so the real address is checked
+    pub fn alloc_no_cross(
+        &mut self,
+        size: usize,
+        boundary: usize,
+    ) -> Result<NonNull<[u8]>, BuddyError> {
+        if !boundary.is_power_of_two() || size > boundary {
+            return Err(BuddyError::CannotFit);
+        }
+        let layout = Layout::from_size_align(size, 1).map_err(|_| BuddyError::TooBigSize)?;
+        self.alloc_by(layout, |s, order| {
+            let index = s
+                .find_no_cross(order, size, boundary)
+                .ok_or(BuddyError::NoMoreSpace)?;
+            s.mark_at(index, order);
+            Ok(index)
+        })
+    }
+    fn alloc_by(
+        &mut self,
+        layout: Layout,
+        mark: impl FnOnce(&mut Self, Order) -> Result<usize, BuddyError>,
+    ) -> Result<NonNull<[u8]>, BuddyError> {
+        let block = self.place_by(layout, mark)?;
+        // SAFETY: The block is new, nobody else references it
+        #[cfg(feature = "poison")]
+        unsafe {
+            block.as_mut_ptr().write_bytes(POISON_ALLOC, block.len())
+        };
+        Ok(block)
+    }
+    /// Same as alloc_by(), the content of the block is left as is: to resize a block in place
+    fn place_by(
+        &mut self,
+        layout: Layout,
+        mark: impl FnOnce(&mut Self, Order) -> Result<usize, BuddyError>,
+    ) -> Result<NonNull<[u8]>, BuddyError> {
+        self.lazy_init()?;
+        #[cfg(feature = "histogram")]
+        {
+            let class = trailing_zero_right(layout.align()).saturating_sub(3);
+            self.align_histogram[min!(class, 9)] += 1;
+        }
+        let (buddy_size, order) = self.order_of(layout)?;
+        self.make_room(order);
+        let index = mark(self, order)?;
+        self.dirty = true;
+        if let Some(table) = self.requested.as_deref_mut() {
+            table[index] = layout.size();
+        }
+        if let Some(table) = self.last_use.as_deref_mut() {
+            self.clock += 1;
+            table[index] = self.clock;
+        }
+        // ___ Calculate the pointer offset of the coresponding memory chunk ___
+        let mut alloc_offset = self.allocable_len / (1 << order.0) * (index & ((1 << order.0) - 1));
+        if self.ring_cursor.is_some() {
+            self.ring_cursor = Some(alloc_offset + buddy_size.0);
+        }
+        if self.allocable_len != self.arena.len() {
+            // case metadata into allocated memory area
+            debug_assert!(
+                alloc_offset >= self.meta.len(),
+                "Woot ? The block [{:#x}, {:#x}) overlaps the metadata",
+                alloc_offset,
+                alloc_offset + buddy_size.0
+            );
+            alloc_offset -= self.meta.len();
+        }
+        // ___ Report changes on parents ___
+        Ok(NonNull::from(
+            self.arena
+                .get_mut(alloc_offset..alloc_offset + buddy_size.0)
+                .ok_or(BuddyError::DoubleFreeOrCorruption)?,
+        ))
+    }
+    /// TODO
+    #[inline(always)]
+    pub fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) -> Result<(), BuddyError> {
+        self.lazy_init()?;
+        if !self.owns(ptr) {
+            return Err(BuddyError::DoubleFreeOrCorruption);
+        }
+        let order = Order::try_from((
+            BuddySize::try_from(self.relaxed(layout))?,
+            BuddySize::<M>(self.allocable_len),
+        ))?;
+        let index = self.block_index(ptr, order);
+        #[cfg(feature = "paranoid")]
+        if !self.parents_consistent(index) {
+            return Err(BuddyError::DoubleFreeOrCorruption);
+        }
+        self.unset_mark(order, index)?;
+        #[cfg(feature = "poison")]
+        self.poison_free(index);
+        Ok(())
+    }
+    /// Same as dealloc(), but the whole block is zeroed before it is marked as free, not only
+    /// the requested size
+    pub fn dealloc_zeroed(&mut self, ptr: NonNull<u8>, layout: Layout) -> Result<(), BuddyError> {
+        let (index, order) = self.live_block(ptr, layout)?;
+        #[cfg(feature = "paranoid")]
+        if !self.parents_consistent(index) {
+            return Err(BuddyError::DoubleFreeOrCorruption);
+        }
+        let range = self.arena_range(index);
+        self.arena[range].fill(0);
+        self.unset_mark(order, index)
+    }
+    /// Allocate the block of the same size right after 'prev', which must be a live block of
+    /// 'prev_layout' (this is its buddy when 'prev' is a left one). The two blocks then form a
+    /// contiguous region. Return NoMoreSpace when the block is taken or 'prev' is the last one
+    pub fn alloc_adjacent(
+        &mut self,
+        prev: NonNull<u8>,
+        prev_layout: Layout,
+    ) -> Result<NonNull<[u8]>, BuddyError> {
+        self.lazy_init()?;
+        if !self.owns(prev) {
+            return Err(BuddyError::DoubleFreeOrCorruption);
+        }
+        let (_, order) = self.order_of(prev_layout)?;
+        let index = self.block_index(prev, order);
+        if !self.is_occupied(index) {
+            return Err(BuddyError::DoubleFreeOrCorruption);
+        }
+        let next = index + 1;
+        if next.is_power_of_two() || !self.is_free_block(next, order) {
+            return Err(BuddyError::NoMoreSpace);
+        }
+        self.alloc_by(prev_layout, |s, order| {
+            s.mark_at(next, order);
+            Ok(next)
+        })
+    }
+    /// Shrink a live block: it stays in place, split down to the buddy size of 'new_layout',
+    /// unless a stronger alignment asks for a bigger block
+    pub fn shrink(
+        &mut self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, BuddyError> {
+        let (index, old_order) = self.live_block(ptr, old_layout)?;
+        let (buddy_size, order) = self.order_of(new_layout)?;
+        if order.0 == old_order.0 {
+            Ok(NonNull::slice_from_raw_parts(ptr, buddy_size.0))
+        } else if order.0 > old_order.0 {
+            self.place_by(new_layout, |s, order| {
+                let new_index = s.resize_in_place(index, old_order, order);
+                // ___ The tail past the new block is free now ___
+                #[cfg(feature = "poison")]
+                {
+                    let range = s.arena_range(index);
+                    let kept = s.arena_range(new_index).len();
+                    s.arena[range.start + kept..range.end].fill(POISON_FREE);
+                }
+                Ok(new_index)
+            })
+        } else {
+            self.move_block(ptr, index, old_order, new_layout, new_layout.size())
+        }
+    }
+    /// Grow a live block: it stays in place when the buddy size does not change or when its
+    /// buddies up to the new size are free, otherwise it is moved. With 'zeroed', only the new
+    /// bytes after 'old_layout.size()' are cleared
+    pub fn grow(
+        &mut self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        zeroed: bool,
+    ) -> Result<NonNull<[u8]>, BuddyError> {
+        let (index, old_order) = self.live_block(ptr, old_layout)?;
+        let (buddy_size, order) = self.order_of(new_layout)?;
+        let block = if order.0 == old_order.0 {
+            NonNull::slice_from_raw_parts(ptr, buddy_size.0)
+        } else if order.0 < old_order.0 && self.can_grow_in_place(index, old_order, order) {
+            self.place_by(new_layout, |s, order| {
+                Ok(s.resize_in_place(index, old_order, order))
+            })?
+        } else {
+            self.move_block(ptr, index, old_order, new_layout, old_layout.size())?
+        };
+        if zeroed {
+            let tail = new_layout.size().saturating_sub(old_layout.size());
+            // SAFETY: The block holds at least new_layout.size() bytes
+            unsafe {
+                block
+                    .as_mut_ptr()
+                    .add(old_layout.size())
+                    .write_bytes(0, tail)
+            };
+        }
+        Ok(block)
+    }
+    /// Permanently mark as occupied the block which covers [index * M, index * M + size). It
+    /// cannot be deallocated, only given back by unreserve(). The block may begin before
+    /// 'index' when the region crosses a buddy boundary: its first cell is returned
+    pub fn reserve(&mut self, index: usize, size: usize) -> Result<usize, BuddyError> {
+        self.lazy_init()?;
+        let offset = index.checked_mul(M).ok_or(BuddyError::CannotFit)?;
+        self.reserve_region(offset, size)
+            .map(|node| self.block_of(node).0 / M)
+    }
+    /// Give back the block reserved by reserve(), 'index' is the first cell it returned
+    pub fn unreserve(&mut self, index: usize) -> Result<(), BuddyError> {
+        self.lazy_init()?;
+        if index >= self.allocable_len / M {
+            return Err(BuddyError::DoubleFreeOrCorruption);
+        }
+        // ___ Climb from the cell while the blocks begin at the same offset ___
+        let max_order = self.max_order();
+        let (mut node, mut order) = ((1 << max_order.0) + index, max_order);
+        loop {
+            if self.is_occupied(node) {
+                if !self.is_reserved(node) {
+                    return Err(BuddyError::DoubleFreeOrCorruption);
+                }
+                self.mark_free(node, order);
+                self.modify_parents(node, order, Op::Deallocate);
+                return Ok(());
+            }
+            if node % 2 != 0 || node == FIRST_INDEX {
+                return Err(BuddyError::DoubleFreeOrCorruption);
+            }
+            node /= 2;
+            order.0 -= 1;
+        }
+    }
+    /// Metadata tree reads and writes since construction or the last reset (reads, writes)
+    #[cfg(feature = "count-accesses")]
+    pub fn meta_accesses(&self) -> (usize, usize) {
+        (self.accesses.0.get(), self.accesses.1.get())
+    }
+    /// Reset the metadata tree access counters
+    #[cfg(feature = "count-accesses")]
+    pub fn reset_meta_accesses(&self) {
+        self.accesses.0.set(0);
+        self.accesses.1.set(0);
+    }
+    /// High-water mark of the used bytes (rounded up to their buddy size, the metadata block
+    /// counts when it is inside the address space) since construction or reset_peak(). It is
+    /// kept across reset()
+    #[cfg(feature = "stats")]
+    pub fn peak_used(&self) -> usize {
+        self.peak
+    }
+    /// Start a new high-water mark from the current usage
+    #[cfg(feature = "stats")]
+    pub fn reset_peak(&mut self) {
+        if !self.check_metadata() {
+            return;
+        }
+        self.peak = match self.used {
+            Some(used) => used,
+            None => self.occupied_len(),
+        };
+    }
+    /// Ring mode: allocations are placed at the first fitting block after the previous one
+    /// (wrapping at the end of the address space). When buffers are freed in allocation order
+    /// (FIFO), the free space stays contiguous behind the live ones. Out-of-order frees are
+    /// handled by the buddy tree as usual
+    pub fn set_ring_mode(&mut self, enabled: bool) {
+        self.ring_cursor = if enabled { Some(0) } else { None };
+    }
+    /// High mode: the search of a free block takes the right child first, so the allocations
+    /// come from the top of the free space and the low addresses stay contiguous for the
+    /// special ones (a DMA reachable region for example). The ring mode comes first
+    pub fn set_prefer_high(&mut self, enabled: bool) {
+        self.prefer_high = enabled;
+    }
+    /// Allocations requested by alignment class: 8 (or less), 16, 32 [..] 4096 (or more)
+    #[cfg(feature = "histogram")]
+    pub fn align_histogram(&self) -> [u64; 10] {
+        self.align_histogram
+    }
+    /// Idempotent free: freeing a block which is already free is a no-op instead of a
+    /// DoubleFreeOrCorruption error. A free with a wrong layout or a foreign pointer is still
+    /// an error
+    pub fn set_idempotent_free(&mut self, enabled: bool) {
+        self.idempotent_free = enabled;
+    }
+    /// Keep up to 'limits[order]' pairs of free buddies of each order uncoalesced, to serve the
+    /// next allocations of that order without splitting their parent again. Order 'o' blocks
+    /// are 'allocable len' >> 'o' bytes. The retained pairs are coalesced when a bigger
+    /// allocation would fail without them
+    pub fn set_retain_per_order(&mut self, limits: [u8; RETAIN_ORDERS]) {
+        self.coalesce_retained();
+        self.retention.limits = limits;
+    }
+    /// Eager mode: the blocks of one size are split by batch and the freed ones are kept
+    /// ready, an allocation of that size pops one instead of descending the tree. It costs a
+    /// list of EAGER_BLOCKS indexes, and the placement policies do not apply to that size.
+    /// A size which cannot fit in the address space stays lazy
+    pub fn set_split_mode(&mut self, mode: SplitMode) {
+        self.split_mode = mode;
+        self.eager.len = 0;
+        self.eager.order = match mode {
+            SplitMode::Lazy => None,
+            SplitMode::Eager(block_len) => Layout::from_size_align(block_len, 1)
+                .ok()
+                .and_then(|layout| self.order_of(layout).ok())
+                .map(|(_, order)| order),
+        };
+    }
+    /// Heap indexes of the parents of the retained pairs: both children are free at the same
+    /// order but were not merged. This is the pending coalescing work
+    pub fn uncoalesced_pairs(&self) -> impl Iterator<Item = usize> + '_ {
+        let end = if self.is_ready() {
+            self.allocable_len / M
+        } else {
+            FIRST_INDEX
+        };
+        (FIRST_INDEX..end).filter(|index| {
+            let depth = (usize::BITS - 1 - index.leading_zeros()) as u8;
+            !self.is_occupied(*index)
+                && self.node_order(*index) == depth + 1
+                && self.node_order(2 * index) == depth + 1
+                && self.node_order(2 * index + 1) == depth + 1
+        })
+    }
+    /// Merge all the retained pairs now
+    pub fn coalesce_all(&mut self) {
+        if self.check_metadata() {
+            self.coalesce_retained();
+        }
+    }
+    /// Number of free blocks split to serve an allocation since the creation
+    pub fn split_count(&self) -> usize {
+        self.splits
+    }
+    /// Last error reported by the ProtectedAllocator which owns this allocator: the Allocator
+    /// trait only gives AllocError, this tells why
+    pub fn last_error(&self) -> Option<BuddyError> {
+        self.last_error
+    }
+    pub(crate) fn record_error(&mut self, error: BuddyError) {
+        self.last_error = Some(error);
+    }
+    /// Persistent memory: 'flush' is called with the address and len of every metadata byte
+    /// right after it is written (clflush + sfence for example). A block is marked before its
+    /// parents are updated, from the leaf to the root, so after a crash the leaf state is
+    /// always persistent and only the parents orders may be stale
+    #[cfg(feature = "pmem")]
+    pub fn set_flush(&mut self, flush: Option<fn(*const u8, usize)>) {
+        self.flush = flush;
+    }
+    /// Write-ahead log: 'journal' is called with (index, old value, new value) right before
+    /// each metadata byte is written by alloc, free and resize, to keep undo or redo records.
+    /// The whole tree writes of the initialization, reset() and try_shrink_arena() are not
+    /// journaled
+    #[cfg(feature = "pmem")]
+    pub fn set_journal(&mut self, journal: Option<fn(usize, u8, u8)>) {
+        self.journal = journal;
+    }
+    /// Relaxed alignment, this changes the alignment semantics: a layout whose alignment is
+    /// bigger than its size only gets max(size rounded up to a power of two, 'hint'). A tiny
+    /// over-aligned object then takes a smaller block, and may be less aligned than asked. Its
+    /// deallocation must be done under the same hint
+    #[cfg(feature = "relax-overalignment")]
+    pub fn set_max_useful_align(&mut self, hint: Option<usize>) {
+        self.max_useful_align = hint.map(|hint| round_up_2(max!(hint, 1)));
+    }
+    /// Debug mode: 'table' holds the call site of every live block allocated by
+    /// alloc_tracked(), indexed by heap node. It needs 'allocable len' / M * 2 entries
+    pub fn set_location_table(
+        &mut self,
+        table: Option<&'a mut [Option<&'static Location<'static>>]>,
+    ) {
+        self.locations = None;
+        if let Some(table) = table {
+            assert!(table.len() >= self.allocable_len / M * 2);
+            table.fill(None);
+            self.locations = Some(table);
+        }
+    }
+    /// 'table' holds the requested size of every live block, indexed by heap node, for
+    /// internal_waste(). It needs 'allocable len' / M * 2 entries. The blocks allocated before
+    /// are not counted
+    pub fn set_size_table(&mut self, table: Option<&'a mut [usize]>) {
+        self.requested = None;
+        if let Some(table) = table {
+            assert!(table.len() >= self.allocable_len / M * 2);
+            table.fill(0);
+            self.requested = Some(table);
+        }
+    }
+    /// 'table' holds the last use of every live block, indexed by heap node, for
+    /// suggest_victim(). It needs 'allocable len' / M * 2 entries. The blocks allocated before
+    /// are never suggested
+    pub fn set_lru_table(&mut self, table: Option<&'a mut [u64]>) {
+        self.last_use = None;
+        if let Some(table) = table {
+            assert!(table.len() >= self.allocable_len / M * 2);
+            table.fill(0);
+            self.last_use = Some(table);
+        }
+    }
+    /// Tell if nothing has been allocated since construction or the last reset()
+    pub fn is_pristine(&self) -> bool {
+        !self.dirty
+    }
+    /// Forget all the allocations (and the reservations): the metadata are written again on
+    /// the next use. All the outstanding allocations become invalid
+    pub fn reset(&mut self) {
+        if let Some(sentinel) = self.meta.first_mut() {
+            *sentinel = METADATA_UNINIT; // Tell metadata must be writed
+        }
+        self.dirty = false;
+        self.retention.retained = [0; RETAIN_ORDERS];
+        self.eager.len = 0;
+        if let Some(table) = self.locations.as_deref_mut() {
+            table.fill(None);
+        }
+        if let Some(table) = self.requested.as_deref_mut() {
+            table.fill(0);
+        }
+        if let Some(table) = self.last_use.as_deref_mut() {
+            table.fill(0);
+        }
+        if self.ring_cursor.is_some() {
+            self.ring_cursor = Some(0);
+        }
+    }
+    /// Size of the biggest block that can be allocated right now, 0 when exhausted
+    pub fn largest_free_block(&mut self) -> usize {
+        if !self.check_metadata() {
+            return 0;
+        }
+        let max_order = self.max_order();
+        let root_order = self.node_order(FIRST_INDEX);
+        if root_order > max_order.0 {
+            0
+        } else {
+            self.allocable_len >> root_order
+        }
+    }
+    /// Number of free blocks of each order: a block of order n is allocable_len >> n bytes.
+    /// Retained pairs count as two blocks
+    pub fn free_histogram(&mut self) -> [usize; usize::BITS as usize] {
+        let mut histogram = [0; usize::BITS as usize];
+        if self.check_metadata() {
+            self.count_free(FIRST_INDEX, 0, &mut histogram);
+        }
+        histogram
+    }
+    fn count_free(&self, index: usize, depth: u8, histogram: &mut [usize]) {
+        if self.is_occupied(index) {
+            return;
+        }
+        if self.node_order(index) == depth {
+            histogram[depth as usize] += 1;
+        } else if depth < self.max_order().0 {
+            self.count_free(2 * index, depth + 1, histogram);
+            self.count_free(2 * index + 1, depth + 1, histogram);
+        }
+    }
+    /// Size of the biggest free block aligned on 'align', 0 when there is none. Buddies are
+    /// aligned on their size, so this is largest_free_block() when it is 'align' or more. A
+    /// smaller block may be aligned by chance, but an allocation aligned on 'align' takes
+    /// 'align' bytes at least: it is not counted
+    pub fn largest_free_aligned(&mut self, align: usize) -> usize {
+        let largest = self.largest_free_block();
+        if !align.is_power_of_two() || align > MAX_SUPPORTED_ALIGN || largest < align {
+            0
+        } else {
+            largest
+        }
+    }
+    /// Prepare a free block of 'size' bytes: the live blocks of the region which costs the
+    /// less to empty are moved out of it, bigger first. 'relocate' gets the old pointer, the
+    /// new one and the layout of each moved block once its content is copied. Reserved blocks
+    /// never move. On NoMoreSpace, the blocks already moved stay where they are
+    pub fn ensure_contiguous(
+        &mut self,
+        size: usize,
+        mut relocate: impl FnMut(NonNull<u8>, NonNull<u8>, Layout),
+    ) -> Result<(), BuddyError> {
+        self.lazy_init()?;
+        self.coalesce_retained();
+        let layout = Layout::from_size_align(size, 1).map_err(|_| BuddyError::TooBigSize)?;
+        let (_, order) = self.order_of(layout)?;
+        if self.node_order(FIRST_INDEX) <= order.0 {
+            return Ok(());
+        }
+        // ___ The region with the fewest live bytes, if they fit elsewhere ___
+        let (target, used) = ((1 << order.0)..(2 << order.0))
+            .filter_map(|index| self.evacuation_cost(index, order).map(|used| (index, used)))
+            .min_by_key(|(_, used)| *used)
+            .ok_or(BuddyError::NoMoreSpace)?;
+        let free_outside = self.total_free() - ((self.allocable_len >> order.0) - used);
+        if free_outside < used {
+            return Err(BuddyError::NoMoreSpace);
+        }
+        let max_order = self.max_order();
+        for depth in order.0..=max_order.0 {
+            let shift = depth - order.0;
+            let block_len = self.allocable_len >> depth;
+            let moved =
+                Layout::from_size_align(block_len, M).map_err(|_| BuddyError::TooBigSize)?;
+            for index in target << shift..(target + 1) << shift {
+                if !self.is_occupied(index) {
+                    continue;
+                }
+                let new_index = self
+                    .find_free_outside(FIRST_INDEX, 0, Order(depth), target)
+                    .ok_or(BuddyError::NoMoreSpace)?;
+                self.mark_at(new_index, Order(depth));
+                let (old, new) = (self.arena_range(index), self.arena_range(new_index));
+                self.arena.copy_within(old.clone(), new.start);
+                let base = self.arena.as_mut_ptr();
+                // SAFETY: Both blocks are inside the arena
+                let (old, new) = unsafe {
+                    (
+                        NonNull::new_unchecked(base.add(old.start)),
+                        NonNull::new_unchecked(base.add(new.start)),
+                    )
+                };
+                if let Some(table) = self.locations.as_deref_mut() {
+                    table[new_index] = table[index].take();
+                }
+                if let Some(table) = self.requested.as_deref_mut() {
+                    table[new_index] = core::mem::take(&mut table[index]);
+                }
+                if let Some(table) = self.last_use.as_deref_mut() {
+                    table[new_index] = core::mem::take(&mut table[index]);
+                }
+                self.unset_mark(Order(depth), index)?;
+                #[cfg(feature = "poison")]
+                self.poison_free(index);
+                relocate(old, new, moved);
+            }
+        }
+        Ok(())
+    }
+    /// Tell if alloc() would succeed right now, or why it would fail. The metadata tree is not
+    /// modified, even before the first allocation
+    pub fn can_alloc(&self, layout: Layout) -> Result<(), BuddyError> {
+        if !self.is_ready() {
+            // ___ lazy_init() would fail on a too short metadata buffer ___
+            if self.meta.is_empty() || self.meta.len() < tree_len(self.max_order())? {
+                return Err(BuddyError::InvalidAddressSpace);
+            }
+            // ___ Only the metadata block may be taken, order_of() tells if it is in the way ___
+            return self.order_of(layout).map(|_| ());
+        }
+        let (_, order) = self.order_of(layout)?;
+        // ___ Without writing anything: alloc() would coalesce the retained pairs first ___
+        match self.find_free(order) {
+            Err(BuddyError::NoMoreSpace)
+                if !self.retention.is_empty()
+                    && self.coalesced_order(FIRST_INDEX, 0) <= order.0 =>
+            {
+                Ok(())
+            }
+            result => result.map(|_| ()),
+        }
+    }
+    /// Explain how 'layout' would be allocated, without allocating
+    pub fn explain(&mut self, layout: Layout) -> Result<AllocationPlan, BuddyError> {
+        self.lazy_init()?;
+        let buddy_size = BuddySize::<M>::try_from(self.relaxed(layout))?;
+        let order = Order::try_from((buddy_size, BuddySize(self.allocable_len)))?;
+        Ok(AllocationPlan {
+            buddy_size: buddy_size.0,
+            order: order.0,
+            descent: FIRST_INDEX..1 << (order.0 + 1),
+            index: self.find_free(order).ok(),
+        })
+    }
+    /// Iterate over the live blocks (offset from base_ptr(), size)
+    pub fn live_blocks(&mut self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let end = if self.check_metadata() {
+            self.allocable_len / M * 2
+        } else {
+            FIRST_INDEX
+        };
+        (FIRST_INDEX..end)
+            .filter(|index| self.is_occupied(*index))
+            .map(|index| self.block_of(index))
+    }
+    /// Iterate over the live blocks allocated by alloc_tracked() (offset from base_ptr(), size,
+    /// call site): once everything should be freed, these are the leaks
+    pub fn leaked_locations(
+        &mut self,
+    ) -> impl Iterator<Item = (usize, usize, &'static Location<'static>)> + '_ {
+        let end = if self.check_metadata() {
+            self.allocable_len / M * 2
+        } else {
+            FIRST_INDEX
+        };
+        (FIRST_INDEX..end).filter_map(|index| {
+            let location = self.locations.as_deref()?[index]?;
+            self.is_occupied(index).then(|| {
+                let (offset, size) = self.block_of(index);
+                (offset, size, location)
+            })
+        })
+    }
+    /// Bytes lost by rounding the live requests up to their buddy size, 0 without a size table
+    /// (see set_size_table())
+    pub fn internal_waste(&self) -> usize {
+        if !self.is_ready() {
+            return 0;
+        }
+        self.requested.as_deref().map_or(0, |table| {
+            (FIRST_INDEX..self.allocable_len / M * 2)
+                .filter(|index| table[*index] != 0 && self.is_occupied(*index))
+                .map(|index| self.block_of(index).1 - table[index])
+                .sum()
+        })
+    }
+    /// Bytes of the occupied blocks, the metadata block included when it is inside the address
+    /// space
+    pub fn total_used(&mut self) -> usize {
+        self.live_blocks().map(|(_, len)| len).sum()
+    }
+    /// Bytes of the free blocks
+    pub fn total_free(&mut self) -> usize {
+        self.allocable_len - self.total_used()
+    }
+    /// Compute all the statistics from a single metadata walk
+    pub fn snapshot_stats(&mut self) -> Stats {
+        let largest_free = self.largest_free_block();
+        let (used, live_allocations) = self
+            .live_blocks()
+            .fold((0, 0), |(used, count), (_, len)| (used + len, count + 1));
+        let free = self.allocable_len - used;
+        Stats {
+            free,
+            used,
+            largest_free,
+            fragmentation: if free == 0 {
+                0.
+            } else {
+                1. - largest_free as f32 / free as f32
+            },
+            live_allocations,
+        }
+    }
+    /// Live blocks by order (live count, live bytes), from a single metadata walk. Orders
+    /// deeper than N - 1 are counted into the last entry. The metadata block is counted when
+    /// it is inside the address space. Nothing is written, even before the first allocation
+    pub fn size_class_report<const N: usize>(&self) -> [(usize, usize); N] {
+        let mut report = [(0, 0); N];
+        if N == 0 {
+            return report;
+        }
+        let allocable_len = self.allocable_len;
+        let mut count = |len: usize| {
+            let order = trailing_zero_right(allocable_len / len);
+            let entry = &mut report[min!(order, N - 1)];
+            entry.0 += 1;
+            entry.1 += len;
+        };
+        if self.is_ready() {
+            (FIRST_INDEX..allocable_len / M * 2)
+                .filter(|index| self.is_occupied(*index))
+                .for_each(|index| count(self.block_of(index).1));
+        } else if allocable_len != self.arena.len() {
+            // ___ Metadata not written yet: their block will be the only live one ___
+            count(round_up_2(max!(self.meta.len(), M)));
+        }
+        report
+    }
+    /// Check that no live blocks overlap: on a buddy tree, two blocks overlap only when one of
+    /// them contains the other, so look for an occupied parent of every live block
+    pub fn assert_no_overlaps(&mut self) -> Result<(), BuddyError> {
+        self.lazy_init()?;
+        for index in FIRST_INDEX..self.allocable_len / M * 2 {
+            if self.is_occupied(index) {
+                let mut parent = index / 2;
+                while parent >= FIRST_INDEX {
+                    if self.is_occupied(parent) {
+                        return Err(BuddyError::DoubleFreeOrCorruption);
+                    }
+                    parent /= 2;
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Deepest level of a split node (neither occupied nor entirely free), 0 when none. This
+    /// is the worst descent made by the next allocations
+    pub fn max_active_depth(&self) -> usize {
+        if self.is_ready() {
+            self.split_depth(FIRST_INDEX, 0).unwrap_or(0) as usize
+        } else {
+            0
+        }
+    }
+    fn split_depth(&self, index: usize, depth: u8) -> Option<u8> {
+        if self.is_occupied(index) || self.node_order(index) == depth {
+            None
+        } else {
+            let left = self.split_depth(2 * index, depth + 1);
+            let right = self.split_depth(2 * index + 1, depth + 1);
+            Some(max!(left.unwrap_or(depth), right.unwrap_or(depth)))
+        }
+    }
+    /// Draw the metadata tree as a Graphviz DOT graph: occupied blocks are red, free ones
+    /// green, and split ones show their smallest available order
+    pub fn to_dot(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        writeln!(f, "digraph buddy {{")?;
+        writeln!(f, "    node [shape=box, style=filled];")?;
+        if self.is_ready() {
+            self.node_to_dot(f, FIRST_INDEX, 0)?;
+        } else {
+            // ___ Metadata are not written yet: the whole address space is free ___
+            writeln!(
+                f,
+                "    n1 [label=\"0 +{}\", fillcolor=green];",
+                self.allocable_len
+            )?;
+        }
+        writeln!(f, "}}")
+    }
+    fn node_to_dot(&self, f: &mut impl fmt::Write, index: usize, depth: u8) -> fmt::Result {
+        let (offset, len) = self.block_of(index);
+        let node_order = self.node_order(index);
+        if self.is_occupied(index) {
+            writeln!(
+                f,
+                "    n{index} [label=\"{offset} +{len}\", fillcolor=red];"
+            )
+        } else if node_order == depth {
+            writeln!(
+                f,
+                "    n{index} [label=\"{offset} +{len}\", fillcolor=green];"
+            )
+        } else {
+            writeln!(
+                f,
+                "    n{index} [label=\"{offset} +{len}\\norder {node_order}\", fillcolor=white];"
+            )?;
+            for child in [2 * index, 2 * index + 1] {
+                writeln!(f, "    n{index} -> n{child};")?;
+                self.node_to_dot(f, child, depth + 1)?;
+            }
+            Ok(())
+        }
+    }
+    /// FNV-1a hash of the metadata, for a watchdog to detect unexpected changes between two
+    /// known-good points
+    pub fn metadata_checksum(&self) -> u64 {
+        self.meta.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+    }
+    /// Tell if the metadata still match a checksum given by metadata_checksum()
+    pub fn verify_checksum(&self, expected: u64) -> bool {
+        self.metadata_checksum() == expected
+    }
+    /// Raw access to metadata, to simulate corruptions
+    #[cfg(test)]
+    pub fn raw_meta(&mut self) -> &mut [u8] {
+        self.meta
+    }
+    /// End of the build phase: the allocator becomes a read-only view of its blocks, which
+    /// cannot allocate or free anymore
+    pub fn freeze(mut self) -> FrozenAllocator<'a, M, BITMAP> {
+        if self.check_metadata() {
+            self.coalesce_retained();
+        }
+        FrozenAllocator {
+            stats: self.snapshot_stats(),
+            inner: self,
+        }
+    }
+    /// Allocate a block of 'size' (rounded up to a power of two) aligned on its size (up to
+    /// MAX_SUPPORTED_ALIGN), to be the address space of a child allocator. This allocator stays
+    /// usable meanwhile, the block goes back to it with release_sub_arena()
+    ///
+    /// The block is given as a pointer: this allocator still covers it with its own arena
+    /// (reset(), swap_arena() and ensure_contiguous() may write there). A '&mut [u8]' made
+    /// from it for the child must not outlive the release nor be used across those calls
+    pub fn carve_sub_arena(&mut self, size: usize) -> Result<NonNull<[u8]>, BuddyError> {
+        let size = round_up_2(size);
+        let layout = Layout::from_size_align(size, min!(size, MAX_SUPPORTED_ALIGN))
+            .map_err(|_| BuddyError::TooBigSize)?;
+        self.alloc(layout)
+    }
+    /// Give back the block of carve_sub_arena() which begins at 'ptr'
+    /// # Safety
+    /// The block and the child allocator built on it must not be used anymore
+    pub unsafe fn release_sub_arena(&mut self, ptr: NonNull<u8>) -> Result<(), BuddyError> {
+        let layout = self.layout_of(ptr)?;
+        self.dealloc(ptr, layout)
+    }
+    /// Tell if 'ptr' is inside the allocable part of the address space
+    #[inline(always)]
+    pub fn owns(&self, ptr: NonNull<u8>) -> bool {
+        let start = self.arena.as_ptr() as usize;
+        (start..start + self.arena.len()).contains(&(ptr.as_ptr() as usize))
+    }
+    /// Order of the live block which contains 'ptr', found by following its address down the
+    /// tree: no layout is needed. The block is allocable_len >> order bytes long
+    pub fn order_at(&self, ptr: NonNull<u8>) -> Result<Order, BuddyError> {
+        self.node_at(ptr).map(|(_, order)| order)
+    }
+    /// Layout of the live block which begins at 'ptr', rebuilt from the metadata tree: its buddy
+    /// size with the minimal alignment. It frees the block with dealloc() like the original one
+    pub fn layout_of(&self, ptr: NonNull<u8>) -> Result<Layout, BuddyError> {
+        let (index, _) = self.node_at(ptr)?;
+        let (offset, size) = self.block_of(index);
+        if self.is_reserved(index) || ptr.addr().get() - self.base_ptr().addr().get() != offset {
+            return Err(BuddyError::DoubleFreeOrCorruption);
+        }
+        Layout::from_size_align(size, 1).map_err(|_| BuddyError::TooBigSize)
+    }
+    /// Record a use of the live block at 'ptr', see suggest_victim(). Nothing is recorded
+    /// without a LRU table (see set_lru_table())
+    pub fn touch(&mut self, ptr: NonNull<u8>) -> Result<(), BuddyError> {
+        let (index, _) = self.node_at(ptr)?;
+        if let Some(table) = self.last_use.as_deref_mut() {
+            self.clock += 1;
+            table[index] = self.clock;
         }
-        debug_assert!(self.meta[0] == 0xff);
+        Ok(())
     }
-    fn write_metadata(&mut self) {
-        let max_order = Order::try_from((BuddySize::<M>(M), BuddySize(self.allocable_len)))
-            .ok()
-            .expect("Woot ? Should be already checked !");
-        // Bytes needed:       2^(order) * 2
-        // order 0.  2o        o X
-        // order 1.  4o        o X + X X
-        // order 2.  8o        o X + X X + X X X X
-        // order 3. 16o        o X + X X + X X X X + X X X X X X X X
-        // [..]
-        let bytes_needed = (1 << max_order.0) * 2;
-        // Cannot use Iterator or IntoIterator in const fn, so we use the C style loop
-        // IMPORTANT: A huge problem is that 'bytes_needed' depends of inputs params on const fn
-        // it derives from <const SIZE: usize> so space.len(). So We have to hack the compiler to
-        // allow 'infinite' eval limit. #![feature(const_eval_limit)] && #![const_eval_limit = "0"]
-        // ___ Write original metadatas ___
-        let (mut current_order, mut members, mut index) = (0, 2, 0);
-        while index < bytes_needed {
-            members -= 1;
-            self.meta[index] = current_order;
-            if members == 0 {
-                current_order += 1;
-                members = 1 << current_order;
+    /// The least recently used live block (allocated or touched), to be freed first when the
+    /// address space is used as a LRU cache. None without a LRU table (see set_lru_table())
+    pub fn suggest_victim(&self) -> Option<(NonNull<u8>, Order)> {
+        let table = self.last_use.as_deref().filter(|_| self.is_ready())?;
+        let index = (FIRST_INDEX..self.allocable_len / M * 2)
+            .filter(|index| {
+                table[*index] != 0 && self.is_occupied(*index) && !self.is_reserved(*index)
+            })
+            .min_by_key(|index| table[*index])?;
+        let (offset, _) = self.block_of(index);
+        let depth = usize::BITS - 1 - index.leading_zeros();
+        // SAFETY: The block is inside the address space
+        let ptr = unsafe { NonNull::new_unchecked(self.base_ptr().as_ptr().add(offset)) };
+        Some((ptr, Order(depth as u8)))
+    }
+    /// Size of the live block which contains 'ptr'
+    #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+    pub(crate) fn live_len(&self, ptr: NonNull<u8>) -> Result<usize, BuddyError> {
+        self.node_at(ptr).map(|(index, _)| self.block_of(index).1)
+    }
+    /// Heap node and order of the live block which contains 'ptr'
+    fn node_at(&self, ptr: NonNull<u8>) -> Result<(usize, Order), BuddyError> {
+        if !self.owns(ptr) || !self.is_ready() {
+            return Err(BuddyError::DoubleFreeOrCorruption);
+        }
+        let offset = ptr.addr().get() - self.base_ptr().addr().get();
+        let max_order = self.max_order();
+        let (mut index, mut depth) = (FIRST_INDEX, 0);
+        loop {
+            if self.is_occupied(index) {
+                return Ok((index, Order(depth)));
             }
-            index += 1;
+            if depth == max_order.0 || self.node_order(index) == depth {
+                // ___ Entirely free ___
+                return Err(BuddyError::DoubleFreeOrCorruption);
+            }
+            depth += 1;
+            let half = self.allocable_len >> depth;
+            index = 2 * index + (offset / half) % 2;
         }
-        // ___ Bootstrap memory for metadata ___
-        if self.allocable_len != self.arena.len() {
-            let metadata_chunk_size = max!(bytes_needed, M);
-            let order = Order::try_from((
-                BuddySize::<M>(metadata_chunk_size),
-                BuddySize(self.allocable_len),
-            ))
-            .unwrap();
-            self.set_mark(order)
-                .ok()
-                .expect("Woot ? Already insuffisant memory ?!? That Buddy Allocator sucks !");
+    }
+    /// Write once each page of the metadata and of the free blocks, so that the first
+    /// allocations do not take a page fault. Occupied blocks are left to their owners
+    pub fn prefault(&mut self) {
+        if !self.check_metadata() {
+            return;
         }
-        self.meta[0] = 0xff; // Mark metadata done
+        for byte in self.meta.iter_mut().step_by(MAX_SUPPORTED_ALIGN) {
+            // SAFETY: A reference is valid for volatile accesses
+            unsafe { ptr::write_volatile(byte, ptr::read_volatile(byte)) };
+        }
+        self.prefault_node(FIRST_INDEX, 0);
     }
-    /// TODO
+    fn prefault_node(&mut self, index: usize, depth: u8) {
+        if self.is_occupied(index) {
+            return;
+        }
+        if self.node_order(index) == depth {
+            let (offset, len) = self.block_of(index);
+            let base = self.base_ptr().as_ptr();
+            for page in (offset..offset + len).step_by(MAX_SUPPORTED_ALIGN) {
+                // SAFETY: The block is free and inside the address space, nobody else uses it
+                unsafe { ptr::write_volatile(base.add(page), ptr::read_volatile(base.add(page))) };
+            }
+        } else {
+            self.prefault_node(2 * index, depth + 1);
+            self.prefault_node(2 * index + 1, depth + 1);
+        }
+    }
+    /// Start of the address space: all returned allocations are offsets from it
+    /// (when metadata are inside the address space, it is the metadata start)
     #[inline(always)]
-    pub fn alloc(&mut self, layout: Layout) -> Result<NonNull<[u8]>, BuddyError> {
-        self.check_metadata();
-        let buddy_size = BuddySize::<M>::try_from(layout)?;
-        let order = Order::try_from((buddy_size, BuddySize(self.allocable_len)))?;
-        let index = self.set_mark(order)?;
-        // ___ Calculate the pointer offset of the coresponding memory chunk ___
-        let mut alloc_offset = self.allocable_len / (1 << order.0) * (index & ((1 << order.0) - 1));
+    pub fn base_ptr(&self) -> NonNull<u8> {
+        // ___ Not indexed, the slices of a refused address space are empty ___
         if self.allocable_len != self.arena.len() {
+            NonNull::from(&*self.meta).cast()
+        } else {
+            NonNull::from(&*self.arena).cast()
+        }
+    }
+    /// Move the address space to 'new_base' without touching metadata (offsets are relative)
+    ///
+    /// # Safety
+    /// 'new_base' must be aligned like the original address space and point to a copy of it
+    /// (metadata included when they are inside) which lives as long as the allocator
+    pub unsafe fn rebase(&mut self, new_base: NonNull<u8>) {
+        let arena_len = self.arena.len();
+        if self.allocable_len != arena_len {
             // case metadata into allocated memory area
-            alloc_offset -= self.meta.len();
+            let meta_len = self.meta.len();
+            self.meta = core::slice::from_raw_parts_mut(new_base.as_ptr(), meta_len);
+            self.arena =
+                core::slice::from_raw_parts_mut(new_base.as_ptr().add(meta_len), arena_len);
+        } else {
+            self.arena = core::slice::from_raw_parts_mut(new_base.as_ptr(), arena_len);
         }
-        // ___ Report changes on parents ___
-        Ok(NonNull::from(
-            self.arena
-                .get_mut(alloc_offset..alloc_offset + buddy_size.0)
-                .unwrap(),
-        ))
     }
-    /// TODO
+
     #[inline(always)]
-    pub fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) -> Result<(), BuddyError> {
-        self.check_metadata();
-        let order = Order::try_from((
-            BuddySize::try_from(layout)?,
-            BuddySize::<M>(self.allocable_len),
-        ))?;
-        // L'arythmetique des pointeurs n'est pas possible dans une fonction constante.
-        // ___ TODO: Explain that ! ___
-        let alloc_offset = usize::from(ptr.addr())
-            - if self.allocable_len != self.arena.len() {
-                // case metadata into allocated memory area
-                self.meta.get(0).unwrap()
+    fn set_mark(&mut self, order: Order) -> Result<usize, BuddyError> {
+        let index = match self.pop_eager(order) {
+            Some(index) => index,
+            None => self.find_free(order)?,
+        };
+        self.mark_at(index, order);
+        Ok(index)
+    }
+    /// A ready block of the eager split mode which is still free, after a batch split when
+    /// there is none
+    fn pop_eager(&mut self, order: Order) -> Option<usize> {
+        if self.eager.order != Some(order) {
+            return None;
+        }
+        if self.eager.len == 0 {
+            self.split_eager(order);
+        }
+        while self.eager.len > 0 {
+            self.eager.len -= 1;
+            let index = self.eager.blocks[self.eager.len];
+            if self.is_free_block(index, order) {
+                return Some(index);
+            }
+        }
+        None
+    }
+    /// Keep ready the parts of order 'order' of a free block of EAGER_BLOCKS of them, or of a
+    /// smaller one. The tree is not written: a free block is made of free parts
+    fn split_eager(&mut self, order: Order) {
+        let mut batch = min!(EAGER_BLOCKS.trailing_zeros() as u8, order.0);
+        loop {
+            if let Ok(index) = self.find_free(Order(order.0 - batch)) {
+                let first = index << batch;
+                for index in (first..first + (1 << batch)).rev() {
+                    self.eager.blocks[self.eager.len] = index;
+                    self.eager.len += 1;
+                }
+                return;
+            }
+            if batch == 0 {
+                return;
+            }
+            batch -= 1;
+        }
+    }
+    #[inline(always)]
+    fn mark_at(&mut self, index: usize, order: Order) {
+        // ___ Mark as occupied with OCCUPIED_FLAG then mark order as 'max order' + 1 ___
+        let max_order = self.max_order();
+        self.release_retained(index, order);
+        self.mark_occupied(index, max_order);
+        self.modify_parents(index, order, Op::Allocate);
+    }
+    /// The free block 'index' is about to be taken: if it is inside a retained pair, the pair
+    /// is not anymore. There is at most one on the path, right under the last free ancestor
+    fn release_retained(&mut self, mut index: usize, order: Order) {
+        if self.retention.is_empty() {
+            return;
+        }
+        let mut depth = order.0;
+        while index > FIRST_INDEX && self.node_order(index / 2) == depth - 1 {
+            index /= 2;
+            depth -= 1;
+        }
+        if index > FIRST_INDEX
+            && self.node_order(index ^ 1) == depth
+            && self.node_order(index / 2) == depth
+        {
+            self.retention.retained[depth as usize] -= 1;
+        }
+    }
+    /// Coalesce the retained pairs when they are the only way to find a block of 'order'
+    fn make_room(&mut self, order: Order) {
+        if !self.retention.is_empty() && order.0 < self.meta_read(FIRST_INDEX) {
+            self.coalesce_retained();
+        }
+    }
+    /// Merge all the retained pairs, from the leaves to the root
+    fn coalesce_retained(&mut self) {
+        if self.retention.is_empty() {
+            return;
+        }
+        for index in (FIRST_INDEX..self.allocable_len / M).rev() {
+            if self.is_occupied(index) {
+                continue;
+            }
+            let depth = (usize::BITS - 1 - index.leading_zeros()) as u8;
+            let left = self.node_order(2 * index);
+            let right = self.node_order(2 * index + 1);
+            let value = if left == depth + 1 && right == depth + 1 {
+                depth
             } else {
-                // case metadata outside allocated memory area
-                self.arena.get(0).unwrap()
-            } as *const u8 as usize;
-        let start_idx = 1 << order.0;
-        // Cast as u64 to avoid mul overflow on 32bits target
-        #[cfg(target_pointer_width = "32")]
-        let index =
-            start_idx + (alloc_offset as u64 * (1 << order.0) as u64 / space.len() as u64) as usize;
-        // Cast as u128 to avoid mul overflow on 64bits target
-        #[cfg(target_pointer_width = "64")]
-        let index = start_idx
-            + (alloc_offset as u128 * (1 << order.0) as u128 / self.allocable_len as u128) as usize;
-        self.unset_mark(order, index)
+                min!(left, right)
+            };
+            if self.meta_read(index) != value {
+                self.meta_write(index, value);
+            }
+        }
+        self.retention.retained = [0; RETAIN_ORDERS];
     }
-    /// TODO
-    pub fn shrink(
-        &mut self,
-        _ptr: NonNull<u8>,
-        _old_layout: Layout,
-        _new_layout: Layout,
-    ) -> Result<NonNull<[u8]>, BuddyError> {
-        self.check_metadata();
-        unimplemented!();
+    /// Check the parents of 'index' up to the root before a free: each one holds the min of
+    /// its children, or its own depth (or depth + 1 when retained) over two free children
+    #[cfg(feature = "paranoid")]
+    fn parents_consistent(&self, mut index: usize) -> bool {
+        while index > FIRST_INDEX {
+            let parent = index / 2;
+            let depth = (usize::BITS - 1 - parent.leading_zeros()) as u8;
+            let left = self.node_order(2 * parent);
+            let right = self.node_order(2 * parent + 1);
+            let value = self.node_order(parent);
+            let consistent = if left == depth + 1 && right == depth + 1 {
+                value == depth || value == depth + 1
+            } else {
+                value == min!(left, right)
+            };
+            if self.is_occupied(parent) || !consistent {
+                return false;
+            }
+            index = parent;
+        }
+        true
     }
-    /// TODO
-    pub fn grow(
-        &mut self,
-        _ptr: NonNull<u8>,
-        _old_layout: Layout,
-        _new_layout: Layout,
-        _zeroed: bool,
-    ) -> Result<NonNull<[u8]>, BuddyError> {
-        self.check_metadata();
-        unimplemented!();
+    /// Order of the node once all the retained pairs below it are merged, as computed by
+    /// coalesce_retained(), but without writing anything
+    fn coalesced_order(&self, index: usize, depth: u8) -> u8 {
+        let node_order = self.node_order(index);
+        if self.is_occupied(index) || node_order == depth || depth == self.max_order().0 {
+            node_order
+        } else {
+            let left = self.coalesced_order(2 * index, depth + 1);
+            let right = self.coalesced_order(2 * index + 1, depth + 1);
+            if left == depth + 1 && right == depth + 1 {
+                depth
+            } else {
+                min!(left, right)
+            }
+        }
     }
-    /// TODO
+    /// Keep a pair of free buddies of 'order' uncoalesced, if the policy allows it
     #[inline(always)]
-    pub fn reserve(&mut self, _index: usize, _size: usize) -> Result<(), BuddyError> {
-        self.check_metadata();
-        unimplemented!();
+    fn retain(&mut self, order: Order) -> bool {
+        let order = order.0 as usize;
+        if order < RETAIN_ORDERS && self.retention.retained[order] < self.retention.limits[order] {
+            self.retention.retained[order] += 1;
+            true
+        } else {
+            false
+        }
     }
-    /// TODO
+    /// 'layout' with its alignment capped by set_max_useful_align()
     #[inline(always)]
-    pub fn unreserve(&mut self, _index: usize) -> Result<(), BuddyError> {
-        self.check_metadata();
-        unimplemented!();
+    fn relaxed(&self, layout: Layout) -> Layout {
+        #[cfg(feature = "relax-overalignment")]
+        if let Some(hint) = self.max_useful_align {
+            if layout.align() > layout.size() {
+                let align = min!(
+                    layout.align(),
+                    max!(round_up_2(max!(layout.size(), 1)), hint)
+                );
+                return Layout::from_size_align(layout.size(), align)
+                    .expect("Woot ? A smaller power of two alignment is always valid");
+            }
+        }
+        layout
     }
-
+    /// Buddy size and order of 'layout' into this address space
+    fn order_of(&self, layout: Layout) -> Result<(BuddySize<M>, Order), BuddyError> {
+        let buddy_size = BuddySize::<M>::try_from(self.relaxed(layout))?;
+        let order = Order::try_from((buddy_size, BuddySize(self.allocable_len)))?;
+        if self.allocable_len != self.arena.len()
+            && (order.0 == 0 || round_up_2(max!(self.meta.len(), M)) >= self.allocable_len)
+        {
+            // case metadata into allocated memory area: the root is never free, and no block is
+            // when the metadata chunk covers the whole address space (after try_shrink_arena())
+            Err(BuddyError::CannotFit)
+        } else {
+            Ok((buddy_size, order))
+        }
+    }
+    /// Index of the block that set_mark() would take for 'order'
     #[inline(always)]
-    fn set_mark(&mut self, order: Order) -> Result<usize, BuddyError> {
-        if order.0 < self.meta[FIRST_INDEX] {
+    fn find_free(&self, order: Order) -> Result<usize, BuddyError> {
+        if order.0 < self.meta_read(FIRST_INDEX) {
             Err(BuddyError::NoMoreSpace)
+        } else if let Some(cursor) = self.ring_cursor {
+            // ___ Next fit from the cursor, then from the begining ___
+            self.find_next_fit(FIRST_INDEX, 0, order, cursor)
+                .or_else(|| self.find_next_fit(FIRST_INDEX, 0, order, 0))
+                .ok_or(BuddyError::NoMoreSpace)
         } else {
             let (mut index, mut current_order) = (FIRST_INDEX, 0); // Begin on index 1
+
+            // ___ The preferred child is taken when it has room ___
+            let (first, second) = if self.prefer_high { (1, 0) } else { (0, 1) };
             while current_order < order.0 {
-                // ___ Find the best fited block ___
-                index = if self.meta[2 * index] <= order.0 {
-                    2 * index // 2n --> binary heap
+                // ___ Find the best fited block, 2n and 2n + 1 --> binary heap ___
+                index = if self.meta_read(2 * index + first) <= order.0 {
+                    2 * index + first
                 } else {
-                    2 * index + 1 // 2n + 1 --> binary heap
+                    2 * index + second
                 };
                 debug_assert!(
                     current_order < self.meta[index],
@@ -267,26 +2116,123 @@ impl<'a, const M: usize> InnerAllocator<'a, M> {
                 );
                 current_order += 1;
             }
-            // ___ Mark as occupied with 0x80 then mark order as 'max order' + 1 ___
-            self.meta[index] = 0x80
-                + Order::try_from((BuddySize::<M>(M), BuddySize(self.allocable_len)))
-                    .ok()
-                    .expect("Woot ? Should be already checked !")
-                    .0
-                + 1;
-            self.modify_parents(index, Order(current_order), Op::Allocate);
             Ok(index)
         }
     }
+    /// Mark as occupied the smallest block which covers [offset, offset + size)
+    fn reserve_region(&mut self, offset: usize, size: usize) -> Result<usize, BuddyError> {
+        if size == 0 || offset >= self.allocable_len || self.allocable_len - offset < size {
+            return Err(BuddyError::CannotFit);
+        }
+        let mut block_len = round_up_2(max!(size, M));
+        while offset / block_len != (offset + size - 1) / block_len {
+            block_len *= 2;
+        }
+        let order = Order::try_from((BuddySize::<M>(block_len), BuddySize(self.allocable_len)))?;
+        let index = (1 << order.0) + offset / block_len;
+        // ___ The block must be entirely free and not inside an occupied one ___
+        if !self.is_free_block(index, order) {
+            Err(BuddyError::NoMoreSpace)
+        } else {
+            self.mark_reserved(index, order);
+            Ok(index)
+        }
+    }
+    /// Like mark_at(), but the order byte is one more than the one of an allocated block, so
+    /// dealloc() refuses it
+    fn mark_reserved(&mut self, index: usize, order: Order) {
+        let max_order = self.max_order();
+        self.release_retained(index, order);
+        self.mark_occupied(index, max_order);
+        self.meta_write(index, self.meta_read(index) + 1);
+        self.modify_parents(index, order, Op::Allocate);
+    }
+    #[inline(always)]
+    fn is_reserved(&self, index: usize) -> bool {
+        self.node_order(index) == self.max_order().0 + 2
+    }
+    /// Tell if the block is free at this exact order (not a part of an occupied one, and
+    /// without occupied parts)
+    fn is_free_block(&self, index: usize, order: Order) -> bool {
+        let mut parent = index / 2;
+        while parent >= FIRST_INDEX {
+            if self.is_occupied(parent) {
+                return false;
+            }
+            parent /= 2;
+        }
+        self.node_order(index) == order.0
+    }
+    /// Leftmost free block of 'order' beginning at 'from' offset or after
+    fn find_next_fit(&self, index: usize, depth: u8, order: Order, from: usize) -> Option<usize> {
+        let block_len = self.allocable_len >> depth;
+        let offset = block_len * (index - (1 << depth));
+        if self.node_order(index) > order.0 || offset.saturating_add(block_len) <= from {
+            None
+        } else if depth == order.0 {
+            (offset >= from).then_some(index)
+        } else {
+            self.find_next_fit(2 * index, depth + 1, order, from)
+                .or_else(|| self.find_next_fit(2 * index + 1, depth + 1, order, from))
+        }
+    }
+    /// Leftmost free block of 'order' whose first 'size' bytes do not cross a 'boundary'
+    fn find_no_cross(&self, order: Order, size: usize, boundary: usize) -> Option<usize> {
+        let base = self.base_ptr().addr().get();
+        let mut from = 0;
+        while let Some(index) = self.find_next_fit(FIRST_INDEX, 0, order, from) {
+            let (offset, len) = self.block_of(index);
+            let start = base + offset;
+            if start / boundary == (start + max!(size, 1) - 1) / boundary {
+                return Some(index);
+            }
+            from = offset + len;
+        }
+        None
+    }
+    /// Leftmost free block of 'order' which is not a part of a bigger free block
+    fn find_split_free(&self, index: usize, depth: u8, order: Order) -> Option<usize> {
+        let node_order = self.node_order(index);
+        if node_order > order.0 {
+            None
+        } else if depth == order.0 {
+            Some(index)
+        } else if node_order == depth {
+            // Entirely free: taking anything below would split it
+            None
+        } else {
+            self.find_split_free(2 * index, depth + 1, order)
+                .or_else(|| self.find_split_free(2 * index + 1, depth + 1, order))
+        }
+    }
     #[inline(always)]
     fn unset_mark(&mut self, order: Order, index: usize) -> Result<(), BuddyError> {
-        if self.meta[index] & 0x80 == 0 {
+        if !self.is_occupied(index) {
+            if self.idempotent_free && self.is_free_block(index, order) {
+                Ok(())
+            } else {
+                Err(BuddyError::DoubleFreeOrCorruption)
+            }
+        } else if self.is_reserved(index) {
             Err(BuddyError::DoubleFreeOrCorruption)
         } else {
             // ___ Mark as free, like original value ___
-            self.meta[index] = order.0;
+            self.mark_free(index, order);
+            if let Some(table) = self.locations.as_deref_mut() {
+                table[index] = None;
+            }
+            if let Some(table) = self.requested.as_deref_mut() {
+                table[index] = 0;
+            }
+            if let Some(table) = self.last_use.as_deref_mut() {
+                table[index] = 0;
+            }
             // ___ Report changes on parents ___
             self.modify_parents(index, order, Op::Deallocate);
+            if self.eager.order == Some(order) && self.eager.len < EAGER_BLOCKS {
+                self.eager.blocks[self.eager.len] = index;
+                self.eager.len += 1;
+            }
             Ok(())
         }
     }
@@ -297,17 +2243,30 @@ impl<'a, const M: usize> InnerAllocator<'a, M> {
             let child_left = 2 * parent;
             let child_right = child_left + 1;
             let new_indice = match op {
-                Op::Allocate => min!(self.meta[child_left] & 0x7f, self.meta[child_right] & 0x7f),
+                Op::Allocate => min!(self.node_order(child_left), self.node_order(child_right)),
                 Op::Deallocate => {
-                    if self.meta[child_left] == order.0 && self.meta[child_right] == order.0 {
-                        order.0 - 1
+                    if self.meta_read(child_left) == order.0
+                        && self.meta_read(child_right) == order.0
+                    {
+                        if self.retain(order) {
+                            order.0
+                        } else {
+                            order.0 - 1
+                        }
                     } else {
-                        min!(self.meta[child_left] & 0x7f, self.meta[child_right] & 0x7f)
+                        min!(self.node_order(child_left), self.node_order(child_right))
                     }
                 }
             };
-            if self.meta[parent] != new_indice {
-                self.meta[parent] = new_indice;
+            let old_indice = self.meta_read(parent);
+            if old_indice != new_indice {
+                if let Op::Allocate = op {
+                    if old_indice == order.0 - 1 {
+                        // ___ The parent was a free block ___
+                        self.splits += 1;
+                    }
+                }
+                self.meta_write(parent, new_indice);
             } else {
                 break; // Job finished
             }
@@ -315,6 +2274,328 @@ impl<'a, const M: usize> InnerAllocator<'a, M> {
             index = parent;
         }
     }
+    /// Heap index and order of the live block at 'ptr' allocated with 'layout'
+    fn live_block(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+    ) -> Result<(usize, Order), BuddyError> {
+        self.lazy_init()?;
+        if !self.owns(ptr) {
+            return Err(BuddyError::DoubleFreeOrCorruption);
+        }
+        let (_, order) = self.order_of(layout)?;
+        let index = self.block_index(ptr, order);
+        if !self.is_occupied(index) || self.is_reserved(index) {
+            return Err(BuddyError::DoubleFreeOrCorruption);
+        }
+        Ok((index, order))
+    }
+    /// Tell if the block 'index' of 'old_order' is the first one of its ancestor of 'order',
+    /// and if all its buddies on the way up are free
+    fn can_grow_in_place(&self, index: usize, old_order: Order, order: Order) -> bool {
+        let shift = old_order.0 - order.0;
+        index & ((1 << shift) - 1) == 0
+            && (0..shift).all(|k| self.node_order((index >> k) + 1) == old_order.0 - k)
+    }
+    /// Live bytes inside the block 'index' of 'order', None when it cannot be emptied: it is a
+    /// part of an occupied block or it holds a reserved one
+    fn evacuation_cost(&self, index: usize, order: Order) -> Option<usize> {
+        let mut parent = index / 2;
+        while parent >= FIRST_INDEX {
+            if self.is_occupied(parent) {
+                return None;
+            }
+            parent /= 2;
+        }
+        let mut used = 0;
+        for depth in order.0..=self.max_order().0 {
+            let shift = depth - order.0;
+            for node in index << shift..(index + 1) << shift {
+                if self.is_reserved(node) {
+                    return None;
+                } else if self.is_occupied(node) {
+                    used += self.allocable_len >> depth;
+                }
+            }
+        }
+        Some(used)
+    }
+    /// Leftmost free block of 'order' which is not inside the block 'avoid'
+    fn find_free_outside(
+        &self,
+        index: usize,
+        depth: u8,
+        order: Order,
+        avoid: usize,
+    ) -> Option<usize> {
+        if index == avoid || self.node_order(index) > order.0 {
+            None
+        } else if depth == order.0 {
+            Some(index)
+        } else {
+            self.find_free_outside(2 * index, depth + 1, order, avoid)
+                .or_else(|| self.find_free_outside(2 * index + 1, depth + 1, order, avoid))
+        }
+    }
+    /// Bytes of the arena covered by the block of a node
+    fn arena_range(&self, index: usize) -> Range<usize> {
+        let (mut offset, block_len) = self.block_of(index);
+        if self.allocable_len != self.arena.len() {
+            // case metadata into allocated memory area
+            offset -= self.meta.len();
+        }
+        offset..offset + block_len
+    }
+    /// Move the occupied mark of the block 'index' of 'old_order' to the block of 'order'
+    /// which begins at the same offset, and return its index. When growing, the buddies on
+    /// the way must be free (see can_grow_in_place())
+    fn resize_in_place(&mut self, index: usize, old_order: Order, order: Order) -> usize {
+        let new_index = if order.0 < old_order.0 {
+            index >> (old_order.0 - order.0)
+        } else {
+            index << (order.0 - old_order.0)
+        };
+        self.mark_free(index, old_order);
+        // ___ When growing, the nodes between both blocks become free inside the new one ___
+        let (mut node, mut depth) = (index / 2, old_order.0);
+        while node > new_index {
+            depth -= 1;
+            self.meta_write(node, depth);
+            node /= 2;
+        }
+        let max_order = self.max_order();
+        self.mark_occupied(new_index, max_order);
+        self.modify_parents(new_index, order, Op::Allocate);
+        if let Some(table) = self.locations.as_deref_mut() {
+            table[new_index] = table[index].take();
+        }
+        if let Some(table) = self.requested.as_deref_mut() {
+            table[index] = 0;
+        }
+        if let Some(table) = self.last_use.as_deref_mut() {
+            table[new_index] = core::mem::take(&mut table[index]);
+        }
+        new_index
+    }
+    /// Allocate a block for 'new_layout', copy the first 'len' bytes of the live block 'index'
+    /// at 'ptr' then free it
+    fn move_block(
+        &mut self,
+        ptr: NonNull<u8>,
+        index: usize,
+        old_order: Order,
+        new_layout: Layout,
+        len: usize,
+    ) -> Result<NonNull<[u8]>, BuddyError> {
+        let block = self.alloc(new_layout)?;
+        // SAFETY: Both blocks are live and distinct, each one holds at least 'len' bytes
+        unsafe { ptr::copy_nonoverlapping(ptr.as_ptr(), block.as_mut_ptr(), len) };
+        self.unset_mark(old_order, index)?;
+        #[cfg(feature = "poison")]
+        self.poison_free(index);
+        Ok(block)
+    }
+    /// Fill the freed block 'index' with POISON_FREE, to catch the uses after free
+    #[cfg(feature = "poison")]
+    #[inline(always)]
+    fn poison_free(&mut self, index: usize) {
+        let range = self.arena_range(index);
+        self.arena[range].fill(POISON_FREE);
+    }
+    /// Heap index of the block of 'order' at 'ptr'
+    #[inline(always)]
+    fn block_index(&self, ptr: NonNull<u8>, order: Order) -> usize {
+        // L'arythmetique des pointeurs n'est pas possible dans une fonction constante.
+        // ___ TODO: Explain that ! ___
+        let alloc_offset = usize::from(ptr.addr())
+            - if self.allocable_len != self.arena.len() {
+                // case metadata into allocated memory area
+                self.meta.get(0).unwrap()
+            } else {
+                // case metadata outside allocated memory area
+                self.arena.get(0).unwrap()
+            } as *const u8 as usize;
+        if needs_wide_math::<M>(self.allocable_len) {
+            block_index_wide(alloc_offset, order, self.allocable_len)
+        } else {
+            block_index_narrow(alloc_offset, order, self.allocable_len)
+        }
+    }
+    /// Order of the smallest buddies
+    #[inline(always)]
+    fn max_order(&self) -> Order {
+        Order::try_from((BuddySize::<M>(M), BuddySize(self.allocable_len)))
+            .expect("Woot ? Should be already checked !")
+    }
+    /// Offset from base_ptr() and size of the block of a node
+    #[inline(always)]
+    fn block_of(&self, index: usize) -> (usize, usize) {
+        let depth = (usize::BITS - 1 - index.leading_zeros()) as usize;
+        let block_len = self.allocable_len >> depth;
+        (block_len * (index - (1 << depth)), block_len)
+    }
+    /// Every access to the metadata tree during alloc and free comes here
+    #[inline(always)]
+    fn meta_read(&self, index: usize) -> u8 {
+        #[cfg(feature = "count-accesses")]
+        self.accesses.0.set(self.accesses.0.get() + 1);
+        self.meta[index]
+    }
+    #[inline(always)]
+    fn meta_write(&mut self, index: usize, value: u8) {
+        #[cfg(feature = "count-accesses")]
+        self.accesses.1.set(self.accesses.1.get() + 1);
+        #[cfg(feature = "pmem")]
+        if let Some(journal) = self.journal {
+            journal(index, self.meta[index], value);
+        }
+        self.meta[index] = value;
+        #[cfg(feature = "pmem")]
+        self.flush_meta(index, 1);
+    }
+    #[cfg(feature = "pmem")]
+    #[inline(always)]
+    fn flush_meta(&self, index: usize, len: usize) {
+        if let Some(flush) = self.flush {
+            flush(self.meta[index..].as_ptr(), len);
+        }
+    }
+    /// Order byte of a node, without the occupied flag
+    #[inline(always)]
+    fn node_order(&self, index: usize) -> u8 {
+        if BITMAP {
+            self.meta_read(index)
+        } else {
+            self.meta_read(index) & !OCCUPIED_FLAG
+        }
+    }
+    #[inline(always)]
+    fn is_occupied(&self, index: usize) -> bool {
+        if BITMAP {
+            let bitmap = self.allocable_len / M * 2;
+            self.meta_read(bitmap + index / 8) & (1 << (index % 8)) != 0
+        } else {
+            self.meta_read(index) & OCCUPIED_FLAG != 0
+        }
+    }
+    /// The used bytes change by the block 'index', before its mark is written. A tree which
+    /// was not written by this allocator (a static address space) is walked the first time
+    #[cfg(feature = "stats")]
+    #[inline(always)]
+    fn account(&mut self, index: usize, occupied: bool) {
+        let used = match self.used {
+            Some(used) => used,
+            None => self.occupied_len(),
+        };
+        let block_len = self.block_of(index).1;
+        let used = if occupied {
+            used + block_len
+        } else {
+            used - block_len
+        };
+        self.used = Some(used);
+        self.peak = max!(self.peak, used);
+    }
+    /// Bytes of the occupied blocks, from a walk of the tree
+    #[cfg(feature = "stats")]
+    fn occupied_len(&self) -> usize {
+        (FIRST_INDEX..self.allocable_len / M * 2)
+            .filter(|index| self.is_occupied(*index))
+            .map(|index| self.block_of(index).1)
+            .sum()
+    }
+    #[inline(always)]
+    fn mark_occupied(&mut self, index: usize, max_order: Order) {
+        #[cfg(feature = "stats")]
+        self.account(index, true);
+        if BITMAP {
+            let bitmap = self.allocable_len / M * 2;
+            self.meta_write(
+                bitmap + index / 8,
+                self.meta_read(bitmap + index / 8) | 1 << (index % 8),
+            );
+            self.meta_write(index, max_order.0 + 1);
+        } else {
+            self.meta_write(index, OCCUPIED_FLAG + max_order.0 + 1);
+        }
+    }
+    #[inline(always)]
+    fn mark_free(&mut self, index: usize, order: Order) {
+        #[cfg(feature = "stats")]
+        self.account(index, false);
+        if BITMAP {
+            let bitmap = self.allocable_len / M * 2;
+            self.meta_write(
+                bitmap + index / 8,
+                self.meta_read(bitmap + index / 8) & !(1 << (index % 8)),
+            );
+        }
+        self.meta_write(index, order.0);
+    }
+}
+
+/// Read-only view of an allocator after its build phase, see InnerAllocator::freeze()
+/// Allocation and deallocation are not there:
+/// ```compile_fail
+/// # use night_buddy_allocator::InnerAllocator;
+/// # use std::alloc::Layout;
+/// fn allocate_after_freeze(allocator: InnerAllocator<'_, 64>) {
+///     let frozen = allocator.freeze();
+///     let _ = frozen.alloc(Layout::new::<u64>());
+/// }
+/// ```
+pub struct FrozenAllocator<'a, const M: usize, const BITMAP: bool = false> {
+    inner: InnerAllocator<'a, M, BITMAP>,
+    stats: Stats,
+}
+
+impl<'a, const M: usize, const BITMAP: bool> FrozenAllocator<'a, M, BITMAP> {
+    /// Statistics of the frozen allocator, they cannot change anymore
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+    /// Iterate over the live blocks (offset from base_ptr(), size)
+    pub fn live_blocks(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let end = if self.inner.is_ready() {
+            self.inner.allocable_len / M * 2
+        } else {
+            FIRST_INDEX
+        };
+        (FIRST_INDEX..end)
+            .filter(|index| self.inner.is_occupied(*index))
+            .map(|index| self.inner.block_of(index))
+    }
+    /// The allocable bytes, the metadata excluded
+    pub fn arena(&self) -> &[u8] {
+        self.inner.arena
+    }
+    /// Content of the live block which begins at 'ptr'
+    pub fn block(&self, ptr: NonNull<u8>) -> Result<&[u8], BuddyError> {
+        let len = self.inner.layout_of(ptr)?.size();
+        let start = ptr.addr().get() - self.inner.arena.as_ptr().addr();
+        Ok(&self.inner.arena[start..start + len])
+    }
+    /// See InnerAllocator::base_ptr()
+    pub fn base_ptr(&self) -> NonNull<u8> {
+        self.inner.base_ptr()
+    }
+    /// See InnerAllocator::owns()
+    pub fn owns(&self, ptr: NonNull<u8>) -> bool {
+        self.inner.owns(ptr)
+    }
+    /// See InnerAllocator::order_at()
+    pub fn order_at(&self, ptr: NonNull<u8>) -> Result<Order, BuddyError> {
+        self.inner.order_at(ptr)
+    }
+    /// See InnerAllocator::to_dot()
+    pub fn to_dot(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        self.inner.to_dot(f)
+    }
+    /// See InnerAllocator::metadata_checksum()
+    pub fn metadata_checksum(&self) -> u64 {
+        self.inner.metadata_checksum()
+    }
 }
 
 impl<const M: usize> TryFrom<(BuddySize<M>, BuddySize<M>)> for Order {
@@ -324,10 +2605,8 @@ impl<const M: usize> TryFrom<(BuddySize<M>, BuddySize<M>)> for Order {
         (buddy_size, max_buddy_size): (BuddySize<M>, BuddySize<M>),
     ) -> Result<Self, Self::Error> {
         // ___ Assuming in RELEASE profile that buddy sizes are pow of 2 ___
-        debug_assert!(round_up_2(buddy_size.0) == buddy_size.0);
-        debug_assert!(
-            max_buddy_size.0 == usize::MAX || round_up_2(max_buddy_size.0) == max_buddy_size.0
-        );
+        debug_assert!(round_up_2_checked(buddy_size.0).1);
+        debug_assert!(max_buddy_size.0 == usize::MAX || round_up_2_checked(max_buddy_size.0).1);
         let buddy_pow = trailing_zero_right(buddy_size.0);
         #[cfg(target_pointer_width = "32")]
         let space_pow = if max_buddy_size.0 == usize::MAX {
@@ -367,7 +2646,7 @@ impl<const M: usize> TryFrom<Layout> for BuddySize<M> {
 /// Error types from Allocator
 #[derive(Debug, Copy, Clone)]
 pub enum BuddyError {
-    /// Requested size cannot be allocated                                
+    /// Requested size cannot be allocated, even on an empty address space
     CannotFit,
     /// Alignment issue
     TooBigAlignment,
@@ -375,8 +2654,20 @@ pub enum BuddyError {
     TooBigSize,
     /// Attempt to free when is impossible
     DoubleFreeOrCorruption,
-    /// No more allocable space for requested size
+    /// No more allocable space for requested size, may success later on after some frees
     NoMoreSpace,
+    /// Metadata are not aligned as requested
+    MisalignedMetadata,
+    /// Address space len is not a power of two, is too small or is misaligned, or the
+    /// metadata buffer is too short
+    InvalidAddressSpace,
+    /// The lock of the inner allocator cannot be taken, a thread panicked while holding it
+    LockPoisoned,
+    /// The lock of the inner allocator is already held by the caller (a RefCell borrowed
+    /// again from inside its own closure)
+    Reentrant,
+    /// The metadata node at this index tells a wrong order, see check_integrity()
+    CorruptedNode(usize),
 }
 
 impl From<BuddyError> for &'static str {
@@ -388,6 +2679,23 @@ impl From<BuddyError> for &'static str {
             TooBigSize => "Bad size",
             DoubleFreeOrCorruption => "Double Free or corruption",
             NoMoreSpace => "Not enough room to swing a cat, a cat, the animal !",
+            MisalignedMetadata => "Metadata are not aligned",
+            InvalidAddressSpace => "Invalid address space",
+            LockPoisoned => "Lock poisoned",
+            Reentrant => "Reentrant lock",
+            CorruptedNode(_) => "Corrupted metadata node",
+        }
+    }
+}
+
+impl fmt::Display for BuddyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuddyError::CorruptedNode(index) => write!(f, "Corrupted metadata node {}", index),
+            _ => f.write_str((*self).into()),
         }
     }
 }
+
+#[cfg(not(feature = "no-std"))]
+impl std::error::Error for BuddyError {}
@@ -6,3 +6,29 @@ pub use math32::{round_up_2, trailing_zero_right};
 mod math64;
 #[cfg(target_pointer_width = "64")]
 pub use math64::{round_up_2, trailing_zero_right};
+
+/// Round up to the next highest power of 2, and tell if 'v' was already one
+#[inline(always)]
+pub const fn round_up_2_checked(v: usize) -> (usize, bool) {
+    let rounded = round_up_2(v);
+    (rounded, rounded == v)
+}
+
+#[cfg(test)]
+mod test {
+    use super::round_up_2_checked;
+    #[test]
+    fn round_up_2_checked_pow2() {
+        for i in (0..usize::BITS).map(|i| 1_usize << i) {
+            assert_eq!(round_up_2_checked(i), (i, true));
+        }
+    }
+    #[test]
+    fn round_up_2_checked_between() {
+        for i in (2..usize::BITS).map(|i| 1_usize << i) {
+            assert_eq!(round_up_2_checked(i / 2 + 1), (i, false));
+            assert_eq!(round_up_2_checked(i - 1), (i, false));
+        }
+        assert_eq!(round_up_2_checked(1000), (1024, false));
+    }
+}
@@ -18,25 +18,52 @@
 //#![feature(const_eval_limit)] // https://github.com/rust-lang/rust/issues/93481
 //#![const_eval_limit = "0"]
 
+mod arena_set;
 mod inner_allocator;
+#[cfg(all(feature = "mte", target_arch = "aarch64"))]
+mod mte;
 mod mutex;
 #[cfg(test)]
 mod tests;
+#[cfg(not(feature = "no-std"))]
+mod thread_cache;
+mod uniform_pool;
 
 use core::alloc::{AllocError, Allocator, GlobalAlloc, Layout};
+use core::fmt;
 use core::marker::PhantomData;
-use core::ops::Deref;
+use core::mem::MaybeUninit;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
 #[cfg(feature = "no-std")]
 use core::ptr::null_mut;
 use core::ptr::NonNull;
+#[cfg(all(feature = "latency-stats", not(feature = "no-std")))]
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::{AtomicBool, Ordering};
 #[cfg(not(feature = "no-std"))]
 use std::alloc::handle_alloc_error;
 
+#[cfg(feature = "single-threaded")]
+pub use mutex::RefCellError;
 /// These traits are exported to implement with your own Mutex
 pub use mutex::RwMutex;
+#[cfg(feature = "spin")]
+pub use mutex::SpinMutex;
 
-pub use inner_allocator::{BuddyError, InnerAllocator};
-pub use inner_allocator::{MAX_SUPPORTED_ALIGN, MIN_BUDDY_NB, MIN_CELL_LEN};
+pub use arena_set::ArenaSet;
+pub use inner_allocator::{const_alloc, needs_wide_math, recommended_min_cell_len};
+pub use inner_allocator::{static_space_size, StaticAddressSpace, StaticBuddyBuilder};
+pub use inner_allocator::{AllocationPlan, AllocatorConfig, BuddyError, CacheAligned};
+pub use inner_allocator::{FrozenAllocator, InnerAllocator, Order, Stats};
+pub use inner_allocator::{SplitMode, EAGER_BLOCKS};
+pub use inner_allocator::{MAX_SUPPORTED_ALIGN, METADATA_ALIGN, MIN_BUDDY_NB, MIN_CELL_LEN};
+pub use inner_allocator::{METADATA_READY, METADATA_UNINIT, OCCUPIED_FLAG, RETAIN_ORDERS};
+#[cfg(feature = "poison")]
+pub use inner_allocator::{POISON_ALLOC, POISON_FREE};
+#[cfg(not(feature = "no-std"))]
+pub use thread_cache::{ThreadCache, CACHE_BATCH, CACHE_CAPACITY, CACHE_MAX_LEN};
+pub use uniform_pool::UniformPool;
 
 /// Buddy Allocator
 #[repr(C, align(16))]
@@ -67,11 +94,222 @@ where
     pub fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, BuddyError> {
         self.protected_allocator.allocate(layout)
     }
+    /// Allocate memory and tell the extra bytes of the block
+    #[inline(always)]
+    pub fn allocate_with_slack(
+        &self,
+        layout: Layout,
+    ) -> Result<(NonNull<[u8]>, usize), BuddyError> {
+        self.protected_allocator.allocate_with_slack(layout)
+    }
+    /// Name of the allocator, see ProtectedAllocator::with_name()
+    #[inline(always)]
+    pub fn name(&self) -> &'static str {
+        self.protected_allocator.name()
+    }
+    /// Allocate a block of exactly 'huge_page_size' bytes aligned on it
+    #[inline(always)]
+    pub fn allocate_hugepage(&self, huge_page_size: usize) -> Result<NonNull<[u8]>, BuddyError> {
+        self.protected_allocator.allocate_hugepage(huge_page_size)
+    }
+    /// Allocate memory, retrying while the address space is full
+    #[inline(always)]
+    pub fn allocate_retry(&self, layout: Layout, spins: u32) -> Result<NonNull<[u8]>, BuddyError> {
+        self.protected_allocator.allocate_retry(layout, spins)
+    }
+    /// Allocate memory or abort: for code paths where failure is a fatal bug
+    #[inline(always)]
+    pub fn allocate_or_abort(&self, layout: Layout) -> NonNull<[u8]> {
+        self.protected_allocator.allocate_or_abort(layout)
+    }
+    /// Allocate an array of 'n' default elements
+    #[inline(always)]
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_array_zeroed<U: Copy + Default>(&self, n: usize) -> Result<&mut [U], BuddyError> {
+        self.protected_allocator.alloc_array_zeroed(n)
+    }
+    /// Allocate 'size' bytes which do not cross a multiple of 'boundary'
+    #[inline(always)]
+    pub fn allocate_no_cross(
+        &self,
+        size: usize,
+        boundary: usize,
+    ) -> Result<NonNull<[u8]>, BuddyError> {
+        self.protected_allocator.allocate_no_cross(size, boundary)
+    }
+    /// Allocate memory and initialize it with 'init'
+    #[inline(always)]
+    pub fn allocate_init<R, F: FnOnce(&mut [MaybeUninit<u8>]) -> R>(
+        &self,
+        layout: Layout,
+        init: F,
+    ) -> Result<(NonNull<[u8]>, R), BuddyError> {
+        self.protected_allocator.allocate_init(layout, init)
+    }
+    /// Allocate memory and initialize it under the lock
+    #[inline(always)]
+    pub fn allocate_with<R>(
+        &self,
+        layout: Layout,
+        init: impl FnOnce(&mut [u8]) -> R,
+    ) -> Result<(NonNull<[u8]>, R), BuddyError> {
+        self.protected_allocator.allocate_with(layout, init)
+    }
+    /// Allocate one block per layout, lazily
+    #[inline(always)]
+    pub fn try_allocate_iter<'i, I: Iterator<Item = Layout>>(
+        &'i self,
+        layouts: I,
+    ) -> AllocateIter<'i, 'a, X, M, I> {
+        self.protected_allocator.try_allocate_iter(layouts)
+    }
+    /// Allocate memory and remember the call site
+    #[inline(always)]
+    #[track_caller]
+    pub fn allocate_tracked(&self, layout: Layout) -> Result<NonNull<[u8]>, BuddyError> {
+        self.protected_allocator.allocate_tracked(layout)
+    }
+    /// Call sites of the live tracked blocks
+    #[inline(always)]
+    pub fn leaked_locations(
+        &self,
+        report: impl FnMut(usize, usize, &'static Location<'static>),
+    ) -> Result<(), BuddyError> {
+        self.protected_allocator.leaked_locations(report)
+    }
+    /// Tell if allocate() would succeed right now
+    #[inline(always)]
+    pub fn can_allocate(&self, layout: Layout) -> Result<(), BuddyError> {
+        self.protected_allocator.can_allocate(layout)
+    }
+    /// Bytes lost by rounding the live requests up
+    #[inline(always)]
+    pub fn internal_waste(&self) -> Result<usize, BuddyError> {
+        self.protected_allocator.internal_waste()
+    }
+    /// Statistics in the Prometheus text format
+    #[inline(always)]
+    pub fn write_prometheus(&self, f: &mut impl fmt::Write, prefix: &str) -> fmt::Result {
+        self.protected_allocator.write_prometheus(f, prefix)
+    }
+    /// Last error of this allocator
+    #[inline(always)]
+    pub fn last_error(&self) -> Option<BuddyError> {
+        self.protected_allocator.last_error()
+    }
+    /// Order of the live block which contains 'ptr'
+    #[inline(always)]
+    pub fn order_at(&self, ptr: NonNull<u8>) -> Result<Order, BuddyError> {
+        self.protected_allocator.order_at(ptr)
+    }
+    /// Layout of the live block at 'ptr', rebuilt from the metadata tree
+    #[inline(always)]
+    pub fn layout_of(&self, ptr: NonNull<u8>) -> Result<Layout, BuddyError> {
+        self.protected_allocator.layout_of(ptr)
+    }
+    /// Tell if 'ptr' is inside the allocable part of the address space
+    #[inline(always)]
+    pub fn owns(&self, ptr: NonNull<u8>) -> bool {
+        self.protected_allocator.owns(ptr)
+    }
+    /// Fault in the metadata and the free blocks before the first allocations
+    #[inline(always)]
+    pub fn prefault(&self) -> Result<(), BuddyError> {
+        self.protected_allocator.prefault()
+    }
+    /// Size of the biggest block that can be allocated right now, 0 when exhausted
+    #[inline(always)]
+    pub fn largest_free_block(&self) -> Result<usize, BuddyError> {
+        self.protected_allocator.largest_free_block()
+    }
+    /// Size of the biggest free block aligned on 'align', 0 when there is none
+    #[inline(always)]
+    pub fn largest_free_aligned(&self, align: usize) -> Result<usize, BuddyError> {
+        self.protected_allocator.largest_free_aligned(align)
+    }
+    /// Number of free blocks of each order
+    #[inline(always)]
+    pub fn free_histogram(&self) -> Result<[usize; usize::BITS as usize], BuddyError> {
+        self.protected_allocator.free_histogram()
+    }
+    /// Bytes of the occupied blocks
+    #[inline(always)]
+    pub fn total_used(&self) -> Result<usize, BuddyError> {
+        self.protected_allocator.total_used()
+    }
+    /// Bytes of the free blocks
+    #[inline(always)]
+    pub fn total_free(&self) -> Result<usize, BuddyError> {
+        self.protected_allocator.total_free()
+    }
+    /// Most bytes ever used at once
+    #[cfg(feature = "stats")]
+    #[inline(always)]
+    pub fn peak_used(&self) -> Result<usize, BuddyError> {
+        self.protected_allocator.peak_used()
+    }
+    /// Start a new high-water mark from the current usage
+    #[cfg(feature = "stats")]
+    #[inline(always)]
+    pub fn reset_peak(&self) -> Result<(), BuddyError> {
+        self.protected_allocator.reset_peak()
+    }
+    /// FNV-1a hash of the metadata
+    #[inline(always)]
+    pub fn metadata_checksum(&self) -> Result<u64, BuddyError> {
+        self.protected_allocator.metadata_checksum()
+    }
+    /// Tell if the metadata still match a checksum
+    #[inline(always)]
+    pub fn verify_checksum(&self, expected: u64) -> bool {
+        self.protected_allocator.verify_checksum(expected)
+    }
+    /// Walk the metadata tree and check its invariants
+    #[inline(always)]
+    pub fn check_integrity(&self) -> Result<(), BuddyError> {
+        self.protected_allocator.check_integrity()
+    }
+    /// Graphviz drawing of the metadata tree
+    #[cfg(not(feature = "no-std"))]
+    #[inline(always)]
+    pub fn to_dot(&self) -> Result<String, BuddyError> {
+        self.protected_allocator.to_dot()
+    }
+    /// Statistics taken under a single lock
+    #[inline(always)]
+    pub fn snapshot_stats(&self) -> Result<Stats, BuddyError> {
+        self.protected_allocator.snapshot_stats()
+    }
+    /// Allocate memory without splitting a bigger free block
+    #[inline(always)]
+    pub fn allocate_avoid_splitting(&self, layout: Layout) -> Result<NonNull<[u8]>, BuddyError> {
+        self.protected_allocator.allocate_avoid_splitting(layout)
+    }
+    /// Allocate zeroed memory
+    #[inline(always)]
+    pub fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, BuddyError> {
+        self.protected_allocator.allocate_zeroed(layout)
+    }
     /// Deallocate memory: should help for a global allocator implementation
     #[inline(always)]
     pub fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) -> Result<(), BuddyError> {
         self.protected_allocator.deallocate(ptr, layout)
     }
+    /// Deallocate the block at 'ptr', the order is read from the metadata tree
+    #[inline(always)]
+    pub fn deallocate_no_layout(&self, ptr: NonNull<u8>) -> Result<(), BuddyError> {
+        self.protected_allocator.deallocate_no_layout(ptr)
+    }
+    /// Allocate the block of the same size right after 'prev'
+    #[inline(always)]
+    pub fn allocate_adjacent(
+        &self,
+        prev: NonNull<u8>,
+        prev_layout: Layout,
+    ) -> Result<NonNull<[u8]>, BuddyError> {
+        self.protected_allocator
+            .allocate_adjacent(prev, prev_layout)
+    }
     /// Attempts to shrink the memory block
     #[inline(always)]
     pub fn shrink(
@@ -94,16 +332,25 @@ where
         self.protected_allocator
             .grow(ptr, old_layout, new_layout, zeroed)
     }
-    /// TODO
+    /// Permanently mark as occupied the block which covers [index * M, index * M + size),
+    /// return its first cell for unreserve()
     #[inline(always)]
-    pub fn reserve(&self, index: usize, size: usize) -> Result<(), BuddyError> {
+    pub fn reserve(&self, index: usize, size: usize) -> Result<usize, BuddyError> {
         self.protected_allocator.reserve(index, size)
     }
-    /// TODO
+    /// Give back a block reserved by reserve()
     #[inline(always)]
     pub fn unreserve(&self, index: usize) -> Result<(), BuddyError> {
         self.protected_allocator.unreserve(index)
     }
+    /// Lock once and give the inner allocator to a closure, for batch operations
+    #[inline(always)]
+    pub fn with_locked<R>(
+        &self,
+        f: impl FnOnce(&mut InnerAllocator<'a, M>) -> R,
+    ) -> Result<R, BuddyError> {
+        self.protected_allocator.with_locked(f)
+    }
 }
 
 /// Clone Boilerplate for ThreadSafeAllocator<'a, T, X, M>... - Cannot Derive Naturaly
@@ -128,36 +375,39 @@ where
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
         self.allocate(layout).map_err(|e| e.into())
     }
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate_zeroed(layout).map_err(|e| e.into())
+    }
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
         self.deallocate(ptr, layout).unwrap();
     }
-    // unsafe fn shrink(
-    //     &self,
-    //     ptr: NonNull<u8>,
-    //     old_layout: Layout,
-    //     new_layout: Layout,
-    // ) -> Result<NonNull<[u8]>, AllocError> {
-    //     self.shrink(ptr, old_layout, new_layout)
-    //         .map_err(|e| e.into())
-    // }
-    // unsafe fn grow(
-    //     &self,
-    //     ptr: NonNull<u8>,
-    //     old_layout: Layout,
-    //     new_layout: Layout,
-    // ) -> Result<NonNull<[u8]>, AllocError> {
-    //     self.grow(ptr, old_layout, new_layout, false)
-    //         .map_err(|e| e.into())
-    // }
-    // unsafe fn grow_zeroed(
-    //     &self,
-    //     ptr: NonNull<u8>,
-    //     old_layout: Layout,
-    //     new_layout: Layout,
-    // ) -> Result<NonNull<[u8]>, AllocError> {
-    //     self.grow(ptr, old_layout, new_layout, true)
-    //         .map_err(|e| e.into())
-    // }
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.shrink(ptr, old_layout, new_layout)
+            .map_err(|e| e.into())
+    }
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.grow(ptr, old_layout, new_layout, false)
+            .map_err(|e| e.into())
+    }
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.grow(ptr, old_layout, new_layout, true)
+            .map_err(|e| e.into())
+    }
 }
 
 /// Static Buddy Allocator
@@ -168,6 +418,14 @@ where
 {
     inner_allocator: X,
     error_hook: Option<fn(BuddyError) -> ()>,
+    named_error_hook: Option<fn(BuddyError, &'static str)>,
+    name: &'static str,
+    zero_on_free: bool,
+    #[cfg(all(feature = "latency-stats", not(feature = "no-std")))]
+    latencies: [AtomicU64; 32],
+    #[cfg(feature = "no-std")]
+    in_hook: AtomicBool,
+    reentrant: AtomicBool,
     phantom: PhantomData<&'a X>,
 }
 
@@ -177,27 +435,438 @@ where
 {
     /// Attach a previously allocated chunk generated by create_static_memory_area()
     pub const fn new(mutex_of_inner_allocator: X, error_hook: Option<fn(BuddyError)>) -> Self {
+        // ___ Two blocks cannot share a tag granule ___
+        #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+        assert!(M >= mte::GRANULE_LEN, "M must be at least 16 with MTE");
+        #[cfg(all(feature = "latency-stats", not(feature = "no-std")))]
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: AtomicU64 = AtomicU64::new(0);
         Self {
             inner_allocator: mutex_of_inner_allocator,
             error_hook,
+            named_error_hook: None,
+            name: "",
+            zero_on_free: false,
+            #[cfg(all(feature = "latency-stats", not(feature = "no-std")))]
+            latencies: [ZERO; 32],
+            #[cfg(feature = "no-std")]
+            in_hook: AtomicBool::new(false),
+            reentrant: AtomicBool::new(false),
             phantom: PhantomData,
         }
     }
+    /// Security mode: each freed block is entirely zeroed under the lock, before it may be given
+    /// again. For secrets like key material
+    pub const fn with_zero_on_free(mut self, enabled: bool) -> Self {
+        self.zero_on_free = enabled;
+        self
+    }
+    /// Name of this allocator, given to the named error hook to tell which arena failed when
+    /// there are several of them
+    pub const fn with_name(mut self, name: &'static str) -> Self {
+        self.name = name;
+        self
+    }
+    /// Error hook which gets the name of the allocator too, called after the plain one
+    pub const fn with_named_error_hook(
+        mut self,
+        named_error_hook: Option<fn(BuddyError, &'static str)>,
+    ) -> Self {
+        self.named_error_hook = named_error_hook;
+        self
+    }
+    /// Name given by with_name(), empty by default
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+    /// Same as new(), with an error instead of a panic when M does not suit the features
+    pub fn try_new(
+        mutex_of_inner_allocator: X,
+        error_hook: Option<fn(BuddyError)>,
+    ) -> Result<Self, BuddyError> {
+        #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+        if M < mte::GRANULE_LEN {
+            return Err(BuddyError::InvalidAddressSpace);
+        }
+        Ok(Self::new(mutex_of_inner_allocator, error_hook))
+    }
     /// Allocate memory: should help for a global allocator implementation
     #[inline(always)]
     pub fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, BuddyError> {
+        #[cfg(all(feature = "latency-stats", not(feature = "no-std")))]
+        let start = std::time::Instant::now();
+        let result = self.try_locked(|r| r.alloc(layout));
+        #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+        let result = result.map(|block| unsafe { mte::tag_block(block) });
+        #[cfg(all(feature = "latency-stats", not(feature = "no-std")))]
+        self.record_latency(start);
+        result
+    }
+    /// Allocate a block of exactly 'huge_page_size' bytes aligned on it, for a huge page
+    /// Free it with the layout (huge_page_size, 1) or deallocate_no_layout()
+    pub fn allocate_hugepage(&self, huge_page_size: usize) -> Result<NonNull<[u8]>, BuddyError> {
+        let result = self.try_locked(|r| r.alloc_hugepage(huge_page_size));
+        #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+        let result = result.map(|block| unsafe { mte::tag_block(block) });
+        result
+    }
+    /// Allocate zeroed memory, the whole returned block is cleared
+    #[inline(always)]
+    pub fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, BuddyError> {
+        let block = self.allocate(layout)?;
+        // SAFETY: The block is new, nobody else references it
+        unsafe { core::ptr::write_bytes(block.as_mut_ptr(), 0, block.len()) };
+        Ok(block)
+    }
+    /// Allocate memory, retrying up to 'spins' times while the address space is full: another
+    /// thread may free a block in between. Other errors are returned at once
+    pub fn allocate_retry(&self, layout: Layout, spins: u32) -> Result<NonNull<[u8]>, BuddyError> {
+        let mut result = self.locked(|r| r.alloc(layout))?;
+        for _ in 0..spins {
+            if !matches!(result, Err(BuddyError::NoMoreSpace)) {
+                break;
+            }
+            core::hint::spin_loop();
+            result = self.locked(|r| r.alloc(layout))?;
+        }
+        let result = result.map_err(|e| self.check(e));
+        #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+        let result = result.map(|block| unsafe { mte::tag_block(block) });
+        result
+    }
+    /// Allocate memory and remember the call site until the block is freed, when a location
+    /// table is set on the inner allocator (see InnerAllocator::set_location_table())
+    #[track_caller]
+    pub fn allocate_tracked(&self, layout: Layout) -> Result<NonNull<[u8]>, BuddyError> {
+        let location = Location::caller();
+        let result = self.try_locked(|r| r.alloc_tracked(layout, location));
+        #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+        let result = result.map(|block| unsafe { mte::tag_block(block) });
+        result
+    }
+    /// Give to 'report' the offset, size and call site of each live block allocated by
+    /// allocate_tracked()
+    pub fn leaked_locations(
+        &self,
+        mut report: impl FnMut(usize, usize, &'static Location<'static>),
+    ) -> Result<(), BuddyError> {
+        self.locked(|r| {
+            r.leaked_locations()
+                .for_each(|(offset, size, location)| report(offset, size, location))
+        })
+    }
+    /// Bytes lost by rounding the live requests up to their buddy size, when a size table is
+    /// set on the inner allocator (see InnerAllocator::set_size_table())
+    pub fn internal_waste(&self) -> Result<usize, BuddyError> {
+        self.locked(|r| r.internal_waste())
+    }
+    /// Last error of this allocator, including the ones hidden behind an AllocError by the
+    /// Allocator trait (a failed Vec::try_reserve() for example). The lock error itself when
+    /// the lock cannot be taken
+    pub fn last_error(&self) -> Option<BuddyError> {
+        self.locked(|r| r.last_error()).unwrap_or_else(Some)
+    }
+    /// Order of the live block which contains 'ptr', without its layout: for inspectors
+    pub fn order_at(&self, ptr: NonNull<u8>) -> Result<Order, BuddyError> {
+        #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+        let ptr = mte::untagged(ptr);
+        self.locked(|r| r.order_at(ptr))?
+    }
+    /// Layout of the live block at 'ptr', rebuilt from the metadata tree: the buddy size with
+    /// the minimal alignment. Give it to deallocate() when the original one is lost
+    pub fn layout_of(&self, ptr: NonNull<u8>) -> Result<Layout, BuddyError> {
+        #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+        let ptr = mte::untagged(ptr);
+        self.locked(|r| r.layout_of(ptr))?
+    }
+    /// Tell if 'ptr' is inside the allocable part of the address space (not in the metadata),
+    /// before giving a pointer of uncertain origin to deallocate(). The address space may be
+    /// swapped, so the lock is taken briefly
+    pub fn owns(&self, ptr: NonNull<u8>) -> bool {
+        #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+        let ptr = mte::untagged(ptr);
         self.inner_allocator
-            .lock_mut(|r| r.alloc(layout).map_err(|e| self.check(e)))
-            .unwrap()
+            .lock_mut(|r| r.owns(ptr))
+            .unwrap_or(false)
+    }
+    /// Write once each page of the metadata and of the free blocks, for latency sensitive
+    /// code which cannot afford a page fault on its first allocations
+    pub fn prefault(&self) -> Result<(), BuddyError> {
+        self.locked(|r| r.prefault())
+    }
+    /// Allocate memory and tell how many bytes the block has beyond 'layout.size()', which the
+    /// caller may use as extra capacity
+    #[inline(always)]
+    pub fn allocate_with_slack(
+        &self,
+        layout: Layout,
+    ) -> Result<(NonNull<[u8]>, usize), BuddyError> {
+        self.allocate(layout)
+            .map(|block| (block, block.len() - layout.size()))
+    }
+    /// Export snapshot_stats() in the Prometheus text format, see Stats::write_prometheus()
+    /// A lock error is a fmt::Error, nothing is written
+    pub fn write_prometheus(&self, f: &mut impl fmt::Write, prefix: &str) -> fmt::Result {
+        self.snapshot_stats()
+            .map_err(|_| fmt::Error)?
+            .write_prometheus(f, prefix)
+    }
+    /// Size of the biggest block that can be allocated right now, read from the root of the
+    /// tree: check it before a big allocation to not trigger the error hook. 0 when exhausted
+    pub fn largest_free_block(&self) -> Result<usize, BuddyError> {
+        self.locked(|r| r.largest_free_block())
+    }
+    /// Number of free blocks of each order, for capacity planning: a block of order n is the
+    /// address space len >> n bytes long
+    pub fn free_histogram(&self) -> Result<[usize; usize::BITS as usize], BuddyError> {
+        self.locked(|r| r.free_histogram())
+    }
+    /// Size of the biggest free block aligned on 'align', 0 when there is none: the biggest
+    /// allocation aligned on 'align' which would succeed right now
+    pub fn largest_free_aligned(&self, align: usize) -> Result<usize, BuddyError> {
+        self.locked(|r| r.largest_free_aligned(align))
+    }
+    /// Bytes of the occupied blocks, rounded up to their buddy size: the metadata block counts
+    /// when it is inside the address space
+    pub fn total_used(&self) -> Result<usize, BuddyError> {
+        self.locked(|r| r.total_used())
+    }
+    /// Bytes of the free blocks, split or not
+    pub fn total_free(&self) -> Result<usize, BuddyError> {
+        self.locked(|r| r.total_free())
+    }
+    /// Most bytes ever used at once since construction or reset_peak(), to right-size the
+    /// address space. Updated on each allocation and free, not by walking the tree
+    #[cfg(feature = "stats")]
+    pub fn peak_used(&self) -> Result<usize, BuddyError> {
+        self.locked(|r| r.peak_used())
+    }
+    /// Start a new high-water mark from the current usage
+    #[cfg(feature = "stats")]
+    pub fn reset_peak(&self) -> Result<(), BuddyError> {
+        self.locked(|r| r.reset_peak())
+    }
+    /// FNV-1a hash of the metadata, taken under the lock
+    pub fn metadata_checksum(&self) -> Result<u64, BuddyError> {
+        self.locked(|r| r.metadata_checksum())
+    }
+    /// Tell if the metadata still match a checksum given by metadata_checksum(), false when
+    /// the lock cannot be taken
+    pub fn verify_checksum(&self, expected: u64) -> bool {
+        matches!(self.metadata_checksum(), Ok(checksum) if checksum == expected)
+    }
+    /// Walk the metadata tree and check its invariants, to hunt a heap corruption
+    /// The error hook is called on the first broken node
+    pub fn check_integrity(&self) -> Result<(), BuddyError> {
+        self.try_locked(|r| r.check_integrity())
+    }
+    /// Graphviz drawing of the metadata tree into a String, to look at the fragmentation
+    #[cfg(not(feature = "no-std"))]
+    pub fn to_dot(&self) -> Result<String, BuddyError> {
+        let mut dot = String::new();
+        self.locked(|r| r.to_dot(&mut dot))?
+            .expect("Woot ? Writing into a String");
+        Ok(dot)
+    }
+    /// Coherent point in time statistics: the lock is taken once
+    pub fn snapshot_stats(&self) -> Result<Stats, BuddyError> {
+        self.locked(|r| r.snapshot_stats())
+    }
+    /// Allocate memory only from an already split block of the exact order: preserve the big
+    /// contiguous regions for the big allocations. NoMoreSpace if there is none
+    #[inline(always)]
+    pub fn allocate_avoid_splitting(&self, layout: Layout) -> Result<NonNull<[u8]>, BuddyError> {
+        let result = self.try_locked(|r| r.alloc_avoid_splitting(layout));
+        #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+        let result = result.map(|block| unsafe { mte::tag_block(block) });
+        result
+    }
+    /// Allocate an array of 'n' elements initialized to T::default() (zero for the numbers)
+    /// The array is tied to the allocator borrow and is never deallocated by itself
+    #[allow(clippy::mut_from_ref)] // Every call gives a new block, never an alias
+    pub fn alloc_array_zeroed<T: Copy + Default>(&self, n: usize) -> Result<&mut [T], BuddyError> {
+        let layout = Layout::array::<T>(n).map_err(|_| self.check(BuddyError::TooBigSize))?;
+        let ptr = if layout.size() == 0 {
+            NonNull::<T>::dangling()
+        } else {
+            self.allocate(layout)?.as_non_null_ptr().cast::<T>()
+        };
+        // SAFETY: The block is at least n * size_of::<T>() bytes long and aligned for T
+        unsafe {
+            for i in 0..n {
+                ptr.as_ptr().add(i).write(T::default());
+            }
+            Ok(core::slice::from_raw_parts_mut(ptr.as_ptr(), n))
+        }
+    }
+    /// Tell if allocate() would succeed right now, or the error it would return (is_ok() for a
+    /// plain bool). Nothing is allocated, the metadata tree is not modified and the error hook
+    /// is not called
+    pub fn can_allocate(&self, layout: Layout) -> Result<(), BuddyError> {
+        self.locked(|r| r.can_alloc(layout))?
+    }
+    /// Configuration of the allocator, including the error hook
+    pub fn config(&self) -> Result<AllocatorConfig, BuddyError> {
+        Ok(AllocatorConfig {
+            error_hook: self.error_hook,
+            zero_on_free: self.zero_on_free,
+            ..self.locked(|r| r.config())?
+        })
+    }
+    /// Allocate 'size' bytes which do not cross a multiple of 'boundary', for DMA engines
+    pub fn allocate_no_cross(
+        &self,
+        size: usize,
+        boundary: usize,
+    ) -> Result<NonNull<[u8]>, BuddyError> {
+        self.try_locked(|r| r.alloc_no_cross(size, boundary))
+    }
+    /// Allocate memory and give it at once to 'init', return the block and the 'init' result
+    pub fn allocate_init<R, F: FnOnce(&mut [MaybeUninit<u8>]) -> R>(
+        &self,
+        layout: Layout,
+        init: F,
+    ) -> Result<(NonNull<[u8]>, R), BuddyError> {
+        let block = self.allocate(layout)?;
+        // SAFETY: The block is new and MaybeUninit<u8> has the layout of u8
+        let region = unsafe {
+            core::slice::from_raw_parts_mut(
+                block.as_mut_ptr().cast::<MaybeUninit<u8>>(),
+                block.len(),
+            )
+        };
+        Ok((block, init(region)))
+    }
+    /// Allocate memory and run 'init' on it before the lock is released: no other allocation
+    /// may happen before the block is initialized
+    pub fn allocate_with<R>(
+        &self,
+        layout: Layout,
+        init: impl FnOnce(&mut [u8]) -> R,
+    ) -> Result<(NonNull<[u8]>, R), BuddyError> {
+        self.try_locked(|r| {
+            r.alloc(layout).map(|block| {
+                #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+                let block = unsafe { mte::tag_block(block) };
+                // SAFETY: The block is new, nobody else references it
+                let result = init(unsafe { &mut *block.as_ptr() });
+                (block, result)
+            })
+        })
+    }
+    /// Allocate one block per layout as the iterator is consumed. Once the address space is full,
+    /// the items are Err(NoMoreSpace): the caller may stop pulling and wait for some free
+    pub fn try_allocate_iter<'i, I: Iterator<Item = Layout>>(
+        &'i self,
+        layouts: I,
+    ) -> AllocateIter<'i, 'a, X, M, I> {
+        AllocateIter {
+            allocator: self,
+            layouts,
+        }
+    }
+    /// Move 'value' into a new block, dropped and freed with the box
+    pub fn allocate_boxed<T>(&self, value: T) -> Result<BuddyBox<'_, 'a, X, M, T>, BuddyError> {
+        let layout = Layout::new::<T>();
+        let ptr = self.allocate(layout)?.as_non_null_ptr().cast::<T>();
+        // SAFETY: The block is new, big enough and aligned for T
+        unsafe { ptr.as_ptr().write(value) };
+        Ok(BuddyBox {
+            allocator: self,
+            ptr,
+            layout,
+        })
+    }
+    /// Allocate a temporary block, freed when the guard is dropped
+    pub fn scratch(&self, layout: Layout) -> Result<ScratchGuard<'_, 'a, X, M>, BuddyError> {
+        Ok(ScratchGuard {
+            allocator: self,
+            block: self.allocate(layout)?,
+            layout,
+        })
+    }
+    /// Allocate memory or abort: for code paths where failure is a fatal bug
+    /// The error hook is called before aborting (panic on no-std)
+    #[inline(always)]
+    pub fn allocate_or_abort(&self, layout: Layout) -> NonNull<[u8]> {
+        match self.allocate(layout) {
+            Ok(non_null) => non_null,
+            Err(e) => abort(e),
+        }
     }
     /// dellocate memory: should help for a global allocator implementation
     #[inline(always)]
     pub fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) -> Result<(), BuddyError> {
-        self.inner_allocator
-            .lock_mut(|r| r.dealloc(ptr, layout).map_err(|e| self.check(e)))
-            .unwrap()
+        #[cfg(all(feature = "latency-stats", not(feature = "no-std")))]
+        let start = std::time::Instant::now();
+        let result = self.try_locked(|r| self.free_locked(r, ptr, layout));
+        #[cfg(all(feature = "latency-stats", not(feature = "no-std")))]
+        self.record_latency(start);
+        result
     }
-    /// Attempts to shrink the memory block
+    /// Free the block under the lock already taken, wiped with zero_on_free
+    #[inline(always)]
+    fn free_locked(
+        &self,
+        r: &mut InnerAllocator<'a, M>,
+        ptr: NonNull<u8>,
+        layout: Layout,
+    ) -> Result<(), BuddyError> {
+        #[cfg(not(all(feature = "mte", target_arch = "aarch64")))]
+        let result = match self.zero_on_free {
+            true => r.dealloc_zeroed(ptr, layout),
+            false => r.dealloc(ptr, layout),
+        };
+        // ___ Retag under the lock, before the block may be given again ___
+        #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+        let result = {
+            // ___ The block is wiped through the tagged pointer, the untagged one would trap ___
+            let block_len = r.live_len(mte::untagged(ptr));
+            r.dealloc(mte::untagged(ptr), layout).map(|_| {
+                // ___ The whole block is retagged, the bytes past layout.size() too ___
+                if let Ok(block_len) = block_len {
+                    if self.zero_on_free {
+                        unsafe { ptr.as_ptr().write_bytes(0, block_len) };
+                    }
+                    unsafe { mte::retag_block(ptr, block_len) }
+                }
+            })
+        };
+        result
+    }
+    /// Deallocate the block at 'ptr' without its layout, the order is read from the metadata
+    /// tree. DoubleFreeOrCorruption if 'ptr' is not the start of a live block
+    pub fn deallocate_no_layout(&self, ptr: NonNull<u8>) -> Result<(), BuddyError> {
+        #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+        let untagged = mte::untagged(ptr);
+        #[cfg(not(all(feature = "mte", target_arch = "aarch64")))]
+        let untagged = ptr;
+        #[cfg(all(feature = "latency-stats", not(feature = "no-std")))]
+        let start = std::time::Instant::now();
+        // ___ The layout is read under the lock of the free, no other free may come between ___
+        let result = self.try_locked(|r| {
+            let layout = r.layout_of(untagged)?;
+            self.free_locked(r, ptr, layout)
+        });
+        #[cfg(all(feature = "latency-stats", not(feature = "no-std")))]
+        self.record_latency(start);
+        result
+    }
+    /// Allocate the block of the same size right after 'prev', to extend a contiguous region.
+    /// NoMoreSpace if it is taken
+    pub fn allocate_adjacent(
+        &self,
+        prev: NonNull<u8>,
+        prev_layout: Layout,
+    ) -> Result<NonNull<[u8]>, BuddyError> {
+        #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+        let prev = mte::untagged(prev);
+        let result = self.try_locked(|r| r.alloc_adjacent(prev, prev_layout));
+        #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+        let result = result.map(|block| unsafe { mte::tag_block(block) });
+        result
+    }
+    /// Attempts to shrink the memory block, in place when the alignment allows it
     #[inline(always)]
     pub fn shrink(
         &self,
@@ -205,14 +874,14 @@ where
         old_layout: Layout,
         new_layout: Layout,
     ) -> Result<NonNull<[u8]>, BuddyError> {
-        self.inner_allocator
-            .lock_mut(|r| {
-                r.shrink(ptr, old_layout, new_layout)
-                    .map_err(|e| self.check(e))
-            })
-            .unwrap()
+        #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+        {
+            self.move_tagged(ptr, old_layout, new_layout, false)
+        }
+        #[cfg(not(all(feature = "mte", target_arch = "aarch64")))]
+        self.try_locked(|r| r.shrink(ptr, old_layout, new_layout))
     }
-    /// Attempts to extend the memory block
+    /// Attempts to extend the memory block, in place when its buddies are free
     #[inline(always)]
     pub fn grow(
         &self,
@@ -221,34 +890,287 @@ where
         new_layout: Layout,
         zeroed: bool,
     ) -> Result<NonNull<[u8]>, BuddyError> {
-        self.inner_allocator
-            .lock_mut(|r| {
-                r.grow(ptr, old_layout, new_layout, zeroed)
-                    .map_err(|e| self.check(e))
-            })
-            .unwrap()
+        #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+        {
+            self.move_tagged(ptr, old_layout, new_layout, zeroed)
+        }
+        #[cfg(not(all(feature = "mte", target_arch = "aarch64")))]
+        self.try_locked(|r| r.grow(ptr, old_layout, new_layout, zeroed))
+    }
+    /// With MTE, a block resized in place would keep a part without its tag: always move it
+    #[cfg(all(feature = "mte", target_arch = "aarch64"))]
+    fn move_tagged(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        zeroed: bool,
+    ) -> Result<NonNull<[u8]>, BuddyError> {
+        let block = self.allocate(new_layout)?;
+        let len = old_layout.size().min(new_layout.size());
+        unsafe {
+            core::ptr::copy_nonoverlapping(ptr.as_ptr(), block.as_mut_ptr(), len);
+            if zeroed {
+                block
+                    .as_mut_ptr()
+                    .add(len)
+                    .write_bytes(0, new_layout.size() - len);
+            }
+        }
+        self.deallocate(ptr, old_layout)?;
+        Ok(block)
     }
-    /// TODO
+    /// Permanently mark as occupied the block which covers [index * M, index * M + size),
+    /// return its first cell for unreserve()
     #[inline(always)]
-    pub fn reserve(&self, index: usize, size: usize) -> Result<(), BuddyError> {
-        self.inner_allocator
-            .lock_mut(|r| r.reserve(index, size).map_err(|e| self.check(e)))
-            .unwrap()
+    pub fn reserve(&self, index: usize, size: usize) -> Result<usize, BuddyError> {
+        self.try_locked(|r| r.reserve(index, size))
     }
-    /// TODO
+    /// Give back a block reserved by reserve()
     #[inline(always)]
     pub fn unreserve(&self, index: usize) -> Result<(), BuddyError> {
+        self.try_locked(|r| r.unreserve(index))
+    }
+    /// Replace the inner allocator, and so the address space, under the lock: double buffered
+    /// arenas. The old one is given back with its allocations, which are now the caller's
+    /// business. When the lock cannot be taken, 'new' is given back with the error
+    #[allow(clippy::result_large_err)] // The caller gets back its own allocator
+    pub fn swap_arena(
+        &self,
+        new: InnerAllocator<'a, M>,
+    ) -> Result<InnerAllocator<'a, M>, (BuddyError, InnerAllocator<'a, M>)> {
+        let mut other = new;
+        match self.locked(|r| core::mem::swap(r, &mut other)) {
+            Ok(()) => Ok(other),
+            Err(e) => Err((e, other)),
+        }
+    }
+    /// Lock once and give the inner allocator to a closure, for batch operations
+    #[inline(always)]
+    pub fn with_locked<R>(
+        &self,
+        f: impl FnOnce(&mut InnerAllocator<'a, M>) -> R,
+    ) -> Result<R, BuddyError> {
+        self.locked(f)
+    }
+    /// Lock the inner allocator, a locking error is reported through RwMutex::lock_error()
+    #[inline(always)]
+    fn locked<R>(&self, f: impl FnOnce(&mut InnerAllocator<'a, M>) -> R) -> Result<R, BuddyError> {
         self.inner_allocator
-            .lock_mut(|r| r.unreserve(index).map_err(|e| self.check(e)))
-            .unwrap()
+            .lock_mut(f)
+            .map_err(|e| self.check(X::lock_error(&e)))
+    }
+    /// Same as locked() for a fallible operation, its error goes to the error hook too. The
+    /// error is recorded under the same lock
+    #[inline(always)]
+    fn try_locked<R>(
+        &self,
+        f: impl FnOnce(&mut InnerAllocator<'a, M>) -> Result<R, BuddyError>,
+    ) -> Result<R, BuddyError> {
+        self.locked(|r| {
+            let result = f(r);
+            if let Err(e) = &result {
+                r.record_error(*e);
+            }
+            result
+        })?
+        .map_err(|e| self.call_hooks(e))
+    }
+    /// Latencies of allocate() and deallocate(): bucket N counts operations which took
+    /// between 2^(N-1) and 2^N - 1 nanoseconds (the last one takes all the slower)
+    #[cfg(all(feature = "latency-stats", not(feature = "no-std")))]
+    pub fn latency_histogram(&self) -> [u64; 32] {
+        let mut histogram = [0; 32];
+        for (bucket, latency) in histogram.iter_mut().zip(self.latencies.iter()) {
+            *bucket = latency.load(Ordering::Relaxed);
+        }
+        histogram
+    }
+    #[cfg(all(feature = "latency-stats", not(feature = "no-std")))]
+    #[inline(always)]
+    fn record_latency(&self, start: std::time::Instant) {
+        let nanos = start.elapsed().as_nanos() as u64;
+        let bucket = ((u64::BITS - nanos.leading_zeros()) as usize).min(31);
+        self.latencies[bucket].fetch_add(1, Ordering::Relaxed);
     }
+    /// Tell if an error occured while the error hook was running, its call was suppressed
+    pub fn reentrant(&self) -> bool {
+        self.reentrant.load(Ordering::Relaxed)
+    }
+    /// Record an error raised out of the lock, then call the hooks
     #[inline(always)]
     fn check(&self, error: BuddyError) -> BuddyError {
-        if let Some(error_hook) = self.error_hook {
-            error_hook(error);
+        let _ = self.inner_allocator.lock_mut(|r| r.record_error(error));
+        self.call_hooks(error)
+    }
+    /// The hook is called out of the lock, so it may use the allocator. But an error raised
+    /// from inside the hook does not call it again. Other threads keep their own hook calls,
+    /// except with no-std where there is no thread local storage
+    #[inline(always)]
+    fn call_hooks(&self, error: BuddyError) -> BuddyError {
+        if self.error_hook.is_some() || self.named_error_hook.is_some() {
+            match self.enter_hook() {
+                None => self.reentrant.store(true, Ordering::Relaxed),
+                Some(_guard) => {
+                    if let Some(error_hook) = self.error_hook {
+                        error_hook(error);
+                    }
+                    if let Some(named_error_hook) = self.named_error_hook {
+                        named_error_hook(error, self.name);
+                    }
+                }
+            }
         }
         error
     }
+    /// Mark the hooks as running until the guard is dropped, None if they already run
+    #[inline(always)]
+    fn enter_hook(&self) -> Option<HookGuard<'_>> {
+        #[cfg(not(feature = "no-std"))]
+        let entered = !IN_HOOK.with(|in_hook| in_hook.replace(true));
+        #[cfg(feature = "no-std")]
+        let entered = !self.in_hook.swap(true, Ordering::Acquire);
+        entered.then_some(HookGuard {
+            #[cfg(feature = "no-std")]
+            in_hook: &self.in_hook,
+            phantom: PhantomData,
+        })
+    }
+}
+
+#[cfg(not(feature = "no-std"))]
+std::thread_local! {
+    /// Set while an error hook runs on this thread
+    static IN_HOOK: core::cell::Cell<bool> = const { core::cell::Cell::new(false) };
+}
+
+/// Hooks running mark, cleared on drop even when a hook panics
+struct HookGuard<'g> {
+    #[cfg(feature = "no-std")]
+    in_hook: &'g AtomicBool,
+    phantom: PhantomData<&'g ()>,
+}
+
+impl Drop for HookGuard<'_> {
+    fn drop(&mut self) {
+        #[cfg(not(feature = "no-std"))]
+        IN_HOOK.with(|in_hook| in_hook.set(false));
+        #[cfg(feature = "no-std")]
+        self.in_hook.store(false, Ordering::Release);
+    }
+}
+
+/// Lazy allocations given by ProtectedAllocator::try_allocate_iter()
+pub struct AllocateIter<'i, 'a, X, const M: usize, I>
+where
+    X: RwMutex<InnerAllocator<'a, M>>,
+{
+    allocator: &'i ProtectedAllocator<'a, X, M>,
+    layouts: I,
+}
+
+impl<'i, 'a, X, const M: usize, I> Iterator for AllocateIter<'i, 'a, X, M, I>
+where
+    X: RwMutex<InnerAllocator<'a, M>>,
+    I: Iterator<Item = Layout>,
+{
+    type Item = Result<NonNull<[u8]>, BuddyError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.layouts
+            .next()
+            .map(|layout| self.allocator.allocate(layout))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.layouts.size_hint()
+    }
+}
+
+/// Temporary block given by ProtectedAllocator::scratch(), freed on drop
+pub struct ScratchGuard<'g, 'a, X, const M: usize>
+where
+    X: RwMutex<InnerAllocator<'a, M>>,
+{
+    allocator: &'g ProtectedAllocator<'a, X, M>,
+    block: NonNull<[u8]>,
+    layout: Layout,
+}
+
+impl<'g, 'a, X, const M: usize> Deref for ScratchGuard<'g, 'a, X, M>
+where
+    X: RwMutex<InnerAllocator<'a, M>>,
+{
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        // SAFETY: The block is owned by the guard until drop
+        unsafe { self.block.as_ref() }
+    }
+}
+
+impl<'g, 'a, X, const M: usize> DerefMut for ScratchGuard<'g, 'a, X, M>
+where
+    X: RwMutex<InnerAllocator<'a, M>>,
+{
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: The block is owned by the guard until drop
+        unsafe { self.block.as_mut() }
+    }
+}
+
+impl<'g, 'a, X, const M: usize> Drop for ScratchGuard<'g, 'a, X, M>
+where
+    X: RwMutex<InnerAllocator<'a, M>>,
+{
+    fn drop(&mut self) {
+        // ___ The error, if any, is already reported to the error hook ___
+        let _ = self
+            .allocator
+            .deallocate(self.block.as_non_null_ptr(), self.layout);
+    }
+}
+
+/// Owned value given by ProtectedAllocator::allocate_boxed(), dropped and freed on drop
+pub struct BuddyBox<'g, 'a, X, const M: usize, T>
+where
+    X: RwMutex<InnerAllocator<'a, M>>,
+{
+    allocator: &'g ProtectedAllocator<'a, X, M>,
+    ptr: NonNull<T>,
+    layout: Layout,
+}
+
+impl<'g, 'a, X, const M: usize, T> Deref for BuddyBox<'g, 'a, X, M, T>
+where
+    X: RwMutex<InnerAllocator<'a, M>>,
+{
+    type Target = T;
+    fn deref(&self) -> &T {
+        // SAFETY: The value is owned by the box until drop
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<'g, 'a, X, const M: usize, T> DerefMut for BuddyBox<'g, 'a, X, M, T>
+where
+    X: RwMutex<InnerAllocator<'a, M>>,
+{
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: The value is owned by the box until drop
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<'g, 'a, X, const M: usize, T> Drop for BuddyBox<'g, 'a, X, M, T>
+where
+    X: RwMutex<InnerAllocator<'a, M>>,
+{
+    fn drop(&mut self) {
+        // SAFETY: The value is valid and never used again
+        unsafe { self.ptr.as_ptr().drop_in_place() };
+        // ___ The error, if any, is already reported to the error hook ___
+        let _ = self
+            .allocator
+            .deallocate(self.ptr.cast::<u8>(), self.layout);
+    }
 }
 
 unsafe impl<'a, X, const M: usize> Allocator for ProtectedAllocator<'a, X, M>
@@ -258,36 +1180,39 @@ where
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
         self.allocate(layout).map_err(|e| e.into())
     }
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate_zeroed(layout).map_err(|e| e.into())
+    }
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
         self.deallocate(ptr, layout).unwrap();
     }
-    // unsafe fn shrink(
-    //     &self,
-    //     ptr: NonNull<u8>,
-    //     old_layout: Layout,
-    //     new_layout: Layout,
-    // ) -> Result<NonNull<[u8]>, AllocError> {
-    //     self.shrink(ptr, old_layout, new_layout)
-    //         .map_err(|e| e.into())
-    // }
-    // unsafe fn grow(
-    //     &self,
-    //     ptr: NonNull<u8>,
-    //     old_layout: Layout,
-    //     new_layout: Layout,
-    // ) -> Result<NonNull<[u8]>, AllocError> {
-    //     self.grow(ptr, old_layout, new_layout, false)
-    //         .map_err(|e| e.into())
-    // }
-    // unsafe fn grow_zeroed(
-    //     &self,
-    //     ptr: NonNull<u8>,
-    //     old_layout: Layout,
-    //     new_layout: Layout,
-    // ) -> Result<NonNull<[u8]>, AllocError> {
-    //     self.grow(ptr, old_layout, new_layout, true)
-    //         .map_err(|e| e.into())
-    // }
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.shrink(ptr, old_layout, new_layout)
+            .map_err(|e| e.into())
+    }
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.grow(ptr, old_layout, new_layout, false)
+            .map_err(|e| e.into())
+    }
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.grow(ptr, old_layout, new_layout, true)
+            .map_err(|e| e.into())
+    }
 }
 
 unsafe impl<'a, X, const M: usize> GlobalAlloc for ProtectedAllocator<'a, X, M>
@@ -300,26 +1225,61 @@ where
             Err(_e) => handle_global_alloc_error(layout),
         }
     }
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        match self.allocate_zeroed(layout) {
+            Ok(non_null) => non_null.as_mut_ptr(),
+            Err(_e) => handle_global_alloc_error(layout),
+        }
+    }
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         self.deallocate(NonNull::new(ptr).unwrap(), layout).unwrap();
     }
-    // unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-    //     let new_layout = Layout::from_size_align(new_size, layout.align());
-    //     match new_layout {
-    //         Err(_) => handle_global_alloc_error(layout),
-    //         Ok(new_layout) => {
-    //             let result = if new_layout.size() > layout.size() {
-    //                 self.grow(NonNull::new(ptr).unwrap(), layout, new_layout, false)
-    //             } else {
-    //                 self.shrink(NonNull::new(ptr).unwrap(), layout, new_layout)
-    //             };
-    //             match result {
-    //                 Ok(non_null) => non_null.as_mut_ptr(),
-    //                 Err(_e) => handle_global_alloc_error(layout),
-    //             }
-    //         }
-    //     }
-    // }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // ___ A zero 'new_size' breaks the contract: it gets the smallest block ___
+        let new_layout = Layout::from_size_align(new_size, layout.align());
+        match new_layout {
+            Err(_) => handle_global_alloc_error(layout),
+            Ok(new_layout) => {
+                let result = if new_layout.size() > layout.size() {
+                    self.grow(NonNull::new(ptr).unwrap(), layout, new_layout, false)
+                } else {
+                    self.shrink(NonNull::new(ptr).unwrap(), layout, new_layout)
+                };
+                match result {
+                    Ok(non_null) => non_null.as_mut_ptr(),
+                    Err(_e) => handle_global_alloc_error(new_layout),
+                }
+            }
+        }
+    }
+}
+
+/// Global allocator which survives a bad free: the error goes to the error hook and the block
+/// is leaked instead of aborting, so the process may still shut down gracefully. The leak is
+/// the price: if the pointer was a live block, it is never given again
+pub struct ResilientGlobal<A>(pub A);
+
+impl<A> ResilientGlobal<A> {
+    /// Wrap an allocator
+    pub const fn new(allocator: A) -> Self {
+        Self(allocator)
+    }
+}
+
+unsafe impl<'a, X, const M: usize> GlobalAlloc for ResilientGlobal<ProtectedAllocator<'a, X, M>>
+where
+    X: RwMutex<InnerAllocator<'a, M>>,
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // ___ The error hook is already called by deallocate() ___
+        match NonNull::new(ptr) {
+            Some(ptr) => drop(self.0.deallocate(ptr, layout)),
+            None => drop(self.0.check(BuddyError::DoubleFreeOrCorruption)),
+        }
+    }
 }
 
 fn handle_global_alloc_error(layout: Layout) -> *mut u8 {
@@ -329,6 +1289,14 @@ fn handle_global_alloc_error(layout: Layout) -> *mut u8 {
     null_mut()
 }
 
+#[allow(unused_variables)]
+fn abort(error: BuddyError) -> ! {
+    #[cfg(not(feature = "no-std"))]
+    std::process::abort();
+    #[cfg(feature = "no-std")]
+    panic!("{}", <BuddyError as Into<&str>>::into(error));
+}
+
 #[allow(unused_variables)]
 impl From<BuddyError> for AllocError {
     #[inline(always)]
@@ -338,8 +1306,6 @@ impl From<BuddyError> for AllocError {
 }
 
 // TODO: Reserve blocks
-// TODO: design Realloc & Shrink
-// TODO: Draw nodes to explain the Buddy research update tree
 // TODO: Create test of allowing more memory space to be addressable
 // TODO: Create good documentations
 
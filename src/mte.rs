@@ -0,0 +1,76 @@
+//! ARM Memory Tagging Extension support
+//!
+//! Each allocation gets a random 4 bits tag from the 'irg' instruction, stored on every 16 bytes
+//! granule of the block with 'stg' and into the top byte of the returned pointer. On
+//! deallocation the block is retagged with another value, so a use after free or an overflow
+//! into a neighbour block traps. The address space must be mapped with PROT_MTE, and M must be
+//! at least GRANULE_LEN since two blocks cannot share a granule.
+
+use core::arch::asm;
+use core::ptr::NonNull;
+
+/// Tags cover 16 bytes
+pub const GRANULE_LEN: usize = 16;
+
+const TAG_SHIFT: usize = 56;
+const TAG_MASK: usize = 0xf << TAG_SHIFT;
+
+/// Tag of a pointer
+#[inline(always)]
+pub fn tag_of(ptr: *mut u8) -> u8 {
+    ((ptr.addr() & TAG_MASK) >> TAG_SHIFT) as u8
+}
+
+/// Pointer without its tag, as known by the inner allocator
+#[inline(always)]
+pub fn untagged(ptr: NonNull<u8>) -> NonNull<u8> {
+    // SAFETY: Only the top byte changes, the address stays non null
+    unsafe { NonNull::new_unchecked(ptr.as_ptr().map_addr(|addr| addr & !TAG_MASK)) }
+}
+
+/// Tag a new block with a random tag, and return the tagged pointer
+/// # Safety
+/// 'block' must be a whole block of the allocator, granule aligned
+#[target_feature(enable = "mte")]
+pub unsafe fn tag_block(block: NonNull<[u8]>) -> NonNull<[u8]> {
+    let len = block.len();
+    let tagged = random_tag(block.as_mut_ptr(), 0);
+    store_tags(tagged, len);
+    NonNull::slice_from_raw_parts(NonNull::new_unchecked(tagged), len)
+}
+
+/// Give to a freed block a tag different from the one used by 'ptr'
+/// # Safety
+/// 'ptr' must be the tagged pointer of a block of 'len' bytes, granule aligned
+#[target_feature(enable = "mte")]
+pub unsafe fn retag_block(ptr: NonNull<u8>, len: usize) {
+    let exclude = 1_u64 << tag_of(ptr.as_ptr());
+    let tagged = random_tag(untagged(ptr).as_ptr(), exclude);
+    store_tags(tagged, len);
+}
+
+/// Insert a random tag into 'ptr', the tags set into 'exclude' bitmask are never choosen
+#[target_feature(enable = "mte")]
+unsafe fn random_tag(ptr: *mut u8, exclude: u64) -> *mut u8 {
+    let tagged: *mut u8;
+    asm!(
+        "irg {tagged}, {ptr}, {exclude}",
+        tagged = out(reg) tagged,
+        ptr = in(reg) ptr,
+        exclude = in(reg) exclude,
+        options(nomem, nostack, preserves_flags)
+    );
+    tagged
+}
+
+/// Store the tag of 'tagged' on each granule of [tagged, tagged + len)
+#[target_feature(enable = "mte")]
+unsafe fn store_tags(tagged: *mut u8, len: usize) {
+    for offset in (0..len).step_by(GRANULE_LEN) {
+        asm!(
+            "stg {granule}, [{granule}]",
+            granule = in(reg) tagged.add(offset),
+            options(nostack, preserves_flags)
+        );
+    }
+}
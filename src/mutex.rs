@@ -3,6 +3,8 @@
 //!
 //! The trait in this module allow code to be generic over the mutex type used.
 
+use crate::BuddyError;
+
 use core::fmt::Debug;
 
 /// A read-write (mutable) mutex trait.
@@ -19,6 +21,11 @@ pub trait RwMutex<T>: Sized {
     /// `lock_mut` will call a closure with a mutable reference to the unlocked
     /// mutex's value.
     fn lock_mut<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, Self::Error>;
+
+    /// Allocator error reported for a locking error, LockPoisoned by default
+    fn lock_error(_error: &Self::Error) -> BuddyError {
+        BuddyError::LockPoisoned
+    }
 }
 
 #[cfg(all(not(feature = "no-std"), not(feature = "no-generic-std-mutex-impl")))]
@@ -32,8 +39,104 @@ mod std_mutex {
 
         #[inline(always)]
         fn lock_mut<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, Self::Error> {
-            let mut v = self.lock().unwrap();
+            let mut v = self.lock().map_err(|_| ())?;
+            Ok(f(&mut v))
+        }
+    }
+}
+
+#[cfg(feature = "single-threaded")]
+pub use ref_cell::RefCellError;
+#[cfg(feature = "spin")]
+pub use spin_mutex::SpinMutex;
+
+#[cfg(feature = "spin")]
+mod spin_mutex {
+    use super::RwMutex;
+
+    use core::cell::UnsafeCell;
+    use core::convert::Infallible;
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    /// A spinlock for bare metal targets, without any OS support
+    ///
+    /// Locking never fails: `Error` is `core::convert::Infallible`. A panic inside the closure
+    /// releases the lock, there is no poisoning.
+    pub struct SpinMutex<T> {
+        locked: AtomicBool,
+        value: UnsafeCell<T>,
+    }
+
+    // SAFETY: The value is only reachable by the owner of the lock
+    unsafe impl<T: Send> Sync for SpinMutex<T> {}
+
+    impl<T> SpinMutex<T> {
+        /// Create an unlocked mutex
+        pub const fn new(value: T) -> Self {
+            Self {
+                locked: AtomicBool::new(false),
+                value: UnsafeCell::new(value),
+            }
+        }
+    }
+
+    /// Release the lock on drop, even when the closure panics
+    struct Unlock<'a>(&'a AtomicBool);
+
+    impl Drop for Unlock<'_> {
+        fn drop(&mut self) {
+            self.0.store(false, Ordering::Release);
+        }
+    }
+
+    impl<T> RwMutex<T> for SpinMutex<T> {
+        type Error = Infallible;
+
+        #[inline(always)]
+        fn lock_mut<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, Self::Error> {
+            while self
+                .locked
+                .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                core::hint::spin_loop();
+            }
+            let _unlock = Unlock(&self.locked);
+            // SAFETY: The lock is taken until '_unlock' is dropped
+            Ok(f(unsafe { &mut *self.value.get() }))
+        }
+    }
+}
+
+#[cfg(feature = "single-threaded")]
+mod ref_cell {
+    use super::RwMutex;
+    use crate::BuddyError;
+
+    use core::cell::RefCell;
+
+    /// Locking error of a RefCell
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum RefCellError {
+        /// lock_mut() was called again while the value is borrowed, from its own closure (an
+        /// allocation from inside the allocator for example)
+        Reentrant,
+    }
+
+    /// Single core targets without threads: a borrow flag is enough, no atomics
+    impl<T> RwMutex<T> for RefCell<T> {
+        type Error = RefCellError;
+
+        #[inline(always)]
+        fn lock_mut<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, Self::Error> {
+            let mut v = self.try_borrow_mut().map_err(|_| RefCellError::Reentrant)?;
             Ok(f(&mut v))
         }
+
+        fn lock_error(error: &Self::Error) -> BuddyError {
+            match error {
+                RefCellError::Reentrant => BuddyError::Reentrant,
+            }
+        }
     }
 }
@@ -1,10 +1,17 @@
 mod random;
 #[cfg(not(feature = "no-std"))]
-use random::{srand_init, Rand};
+use random::{srand_init, srand_init_with, Lfsr, Rand};
 
 use super::inner_allocator::*;
 use super::*;
 
+/// Take an aligned address space from a heap allocated memory of 'len + MAX_SUPPORTED_ALIGN' bytes
+#[cfg(not(feature = "no-std"))]
+fn aligned_slice(memory: &mut Vec<u8>, len: usize) -> &mut [u8] {
+    let offset = memory.as_ptr().align_offset(MAX_SUPPORTED_ALIGN);
+    &mut memory[offset..offset + len]
+}
+
 #[cfg(not(feature = "no-std"))]
 mod allocator {
     use super::*;
@@ -138,15 +145,30 @@ mod allocator {
         for _ in 0..(MO * 6) {
             v.push(42_u8);
         }
-        let out = v.try_reserve(MO * 6); // The allocator cannot handle that
+        // Growing in place may take the whole address space, but not more
+        let out = v.try_reserve(MO * 12); // The allocator cannot handle that
         if let Ok(_) = &out {
             panic!("This allocation is impossible");
         }
     }
     static mut CHUNK: MemChunk = MemChunk([0; CHUNK_SIZE]);
+    /// Seed the stress tests, with the 32 bits LFSR when LFSR32 is set in the environment
+    fn stress_seed(seed: u16) {
+        if std::env::var_os("LFSR32").is_some() {
+            srand_init_with(
+                Lfsr::builder()
+                    .taps(Lfsr::TAPS_32)
+                    .width(32)
+                    .seed(seed as u32)
+                    .build(),
+            );
+        } else {
+            srand_init(seed);
+        }
+    }
     #[test]
     fn memory_sodomizer1() {
-        srand_init(10);
+        stress_seed(10);
         for _ in 0..4 {
             let alloc = ClonableBuddy::new(Arc::new(ProtectedBuddy::new(
                 Mutex::new(InnerBuddy::<MIN_CELL_LEN>::new_from_refs(
@@ -164,7 +186,7 @@ mod allocator {
     }
     #[test]
     fn memory_sodomizer2_multithreaded() {
-        srand_init(21);
+        stress_seed(21);
         let mut memory = vec![0x21_u8; CHUNK_SIZE + MAX_SUPPORTED_ALIGN];
         let (_prefix, aligned_memory, _suffix) = unsafe { memory.align_to_mut::<MemChunk>() };
         // thread::spawn can only take static reference so force the compiler by
@@ -194,20 +216,18 @@ mod allocator {
         final_test(&alloc);
     }
     const MIN_CELL_LEN: usize = 64;
-    static mut STATIC_SPACE: StaticAddressSpace<CHUNK_SIZE, MIN_CELL_LEN> =
-        StaticAddressSpace::new();
+    type StaticBuilder = StaticBuddyBuilder<CHUNK_SIZE, MIN_CELL_LEN>;
+    static mut STATIC_SPACE: StaticAddressSpace<CHUNK_SIZE, MIN_CELL_LEN> = StaticBuilder::space();
     static STATIC_ALLOCATOR: ProtectedBuddy<Mutex<InnerBuddy<MIN_CELL_LEN>>, MIN_CELL_LEN> =
         ProtectedBuddy::new(
-            Mutex::new(InnerBuddy::<MIN_CELL_LEN>::new_from_static(unsafe {
-                &mut STATIC_SPACE
-            })),
+            Mutex::new(StaticBuilder::build(unsafe { &mut STATIC_SPACE })),
             Some(|e| {
                 dbg!(<BuddyError as Into<&str>>::into(e));
             }),
         );
     #[test]
     fn memory_sodomizer3_multithreaded_with_static() {
-        srand_init(42);
+        stress_seed(42);
         let mut thread_list = Vec::new();
         for _ in 0..4 {
             thread_list.push(std::thread::spawn(move || {
@@ -219,6 +239,644 @@ mod allocator {
         }
         final_test(&STATIC_ALLOCATOR);
     }
+    #[test]
+    fn allocate_or_abort() {
+        #[repr(align(4096))]
+        struct MemChunk([u8; 256]);
+        let mut chunk = MemChunk([0; 256]);
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                chunk.0.as_mut_slice(),
+                None,
+            )),
+            None,
+        );
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let b = alloc.allocate_or_abort(layout);
+        assert!(b.len() >= 64);
+        unsafe { b.as_mut_ptr().write_bytes(0xaa, 64) };
+        alloc.deallocate(b.as_non_null_ptr(), layout).unwrap();
+    }
+    #[test]
+    fn cannot_fit_or_no_more_space() {
+        #[repr(align(4096))]
+        struct MemChunk([u8; 256]);
+        let mut chunk = MemChunk([0; 256]);
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                chunk.0.as_mut_slice(),
+                None,
+            )),
+            None,
+        );
+        let layout = |size| Layout::from_size_align(size, 8).unwrap();
+        // Never fits, whatever is the occupation
+        assert!(matches!(
+            alloc.allocate(layout(512)),
+            Err(BuddyError::CannotFit)
+        ));
+        // The metadata are inside the address space, the whole one is never free
+        assert!(matches!(
+            alloc.allocate(layout(256)),
+            Err(BuddyError::CannotFit)
+        ));
+        // Would fit on an empty address space
+        let b = alloc.allocate(layout(128)).unwrap();
+        assert!(matches!(
+            alloc.allocate(layout(128)),
+            Err(BuddyError::NoMoreSpace)
+        ));
+        alloc.deallocate(b.as_non_null_ptr(), layout(128)).unwrap();
+        alloc.allocate(layout(128)).unwrap();
+    }
+    #[test]
+    fn cannot_fit_with_bitmap() {
+        // The bitmap metadata take the lower half of the address space: every block smaller
+        // than the whole one still fits on an empty arena, the whole one never does
+        for size in [256, 4096, 65536] {
+            let mut memory = vec![0; size + MAX_SUPPORTED_ALIGN];
+            let mut alloc = InnerAllocator::<MIN_CELL_LEN, true>::new_from_refs(
+                aligned_slice(&mut memory, size),
+                None,
+            );
+            let layout = |size| Layout::from_size_align(size, 8).unwrap();
+            assert!(matches!(
+                alloc.alloc(layout(size)),
+                Err(BuddyError::CannotFit)
+            ));
+            let mut len = size / 2;
+            while len >= MIN_CELL_LEN {
+                let b = alloc.alloc(layout(len)).unwrap();
+                alloc.dealloc(b.as_non_null_ptr(), layout(len)).unwrap();
+                len /= 2;
+            }
+        }
+    }
+    struct CountingMutex<T> {
+        mutex: Mutex<T>,
+        locks: std::sync::atomic::AtomicUsize,
+    }
+    impl<T> RwMutex<T> for CountingMutex<T> {
+        type Error = ();
+        fn lock_mut<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, Self::Error> {
+            self.locks
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            self.mutex.lock_mut(f)
+        }
+    }
+    #[test]
+    fn with_locked() {
+        #[repr(align(4096))]
+        struct MemChunk([u8; 4096]);
+        let mut chunk = MemChunk([0; 4096]);
+        let alloc = ProtectedAllocator::new(
+            CountingMutex {
+                mutex: Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                    chunk.0.as_mut_slice(),
+                    None,
+                )),
+                locks: 0.into(),
+            },
+            None,
+        );
+        let layout = Layout::from_size_align(512, 8).unwrap();
+        let largest = alloc
+            .with_locked(|inner| {
+                for _ in 0..4 {
+                    inner.alloc(layout).unwrap();
+                }
+                inner.largest_free_block()
+            })
+            .unwrap();
+        assert_eq!(largest, 1024);
+        assert_eq!(
+            alloc
+                .inner_allocator
+                .locks
+                .load(std::sync::atomic::Ordering::Relaxed),
+            1
+        );
+    }
+    #[test]
+    fn error_under_one_lock() {
+        #[repr(align(4096))]
+        struct MemChunk([u8; 4096]);
+        let mut chunk = MemChunk([0; 4096]);
+        let alloc = ProtectedAllocator::new(
+            CountingMutex {
+                mutex: Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                    chunk.0.as_mut_slice(),
+                    None,
+                )),
+                locks: 0.into(),
+            },
+            None,
+        );
+        assert!(alloc
+            .allocate(Layout::from_size_align(8192, 8).unwrap())
+            .is_err());
+        // The error is recorded by the failing operation, without another lock
+        assert_eq!(
+            alloc
+                .inner_allocator
+                .locks
+                .load(std::sync::atomic::Ordering::Relaxed),
+            1
+        );
+        assert!(matches!(alloc.last_error(), Some(BuddyError::CannotFit)));
+    }
+    #[test]
+    fn free_without_layout_under_one_lock() {
+        #[repr(align(4096))]
+        struct MemChunk([u8; 4096]);
+        let mut chunk = MemChunk([0; 4096]);
+        let alloc = ProtectedAllocator::new(
+            CountingMutex {
+                mutex: Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                    chunk.0.as_mut_slice(),
+                    None,
+                )),
+                locks: 0.into(),
+            },
+            None,
+        );
+        let block = alloc
+            .allocate(Layout::from_size_align(100, 8).unwrap())
+            .unwrap();
+        let locks = || {
+            alloc
+                .inner_allocator
+                .locks
+                .load(std::sync::atomic::Ordering::Relaxed)
+        };
+        let before = locks();
+        // The layout lookup and the free share the lock
+        alloc.deallocate_no_layout(block.as_non_null_ptr()).unwrap();
+        assert_eq!(locks(), before + 1);
+    }
+    #[test]
+    fn snapshot_stats() {
+        #[repr(align(4096))]
+        struct MemChunk([u8; 4096]);
+        let mut chunk = MemChunk([0; 4096]);
+        let alloc = ProtectedAllocator::new(
+            CountingMutex {
+                mutex: Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                    chunk.0.as_mut_slice(),
+                    None,
+                )),
+                locks: 0.into(),
+            },
+            None,
+        );
+        for size in [512, 512, 128] {
+            alloc
+                .allocate(Layout::from_size_align(size, 8).unwrap())
+                .unwrap();
+        }
+        let locks = || {
+            alloc
+                .inner_allocator
+                .locks
+                .load(std::sync::atomic::Ordering::Relaxed)
+        };
+        let before = locks();
+        let stats = alloc.snapshot_stats().unwrap();
+        assert_eq!(locks(), before + 1);
+        let (largest, live) = alloc
+            .with_locked(|inner| {
+                (
+                    inner.largest_free_block(),
+                    inner.live_blocks().collect::<Vec<_>>(),
+                )
+            })
+            .unwrap();
+        assert_eq!(stats.largest_free, largest);
+        assert_eq!(stats.live_allocations, live.len());
+        assert_eq!(stats.used, live.iter().map(|(_, len)| len).sum::<usize>());
+        assert_eq!(stats.free + stats.used, 4096);
+        // The metadata block is counted as used
+        assert_eq!((stats.free, stats.largest_free), (2816, 2048));
+        assert_eq!(stats.fragmentation, 1. - 2048. / 2816.);
+    }
+    #[test]
+    fn alloc_array_zeroed() {
+        const ARENA_SIZE: usize = 4096;
+        let mut memory = vec![0xff; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        );
+        let array = alloc.alloc_array_zeroed::<u64>(128).unwrap();
+        assert_eq!(array, [0; 128]);
+        array[127] = 42;
+        let largest = alloc
+            .with_locked(|inner| inner.largest_free_block())
+            .unwrap();
+        // An empty array takes no space
+        assert!(alloc.alloc_array_zeroed::<u64>(0).unwrap().is_empty());
+        assert_eq!(
+            alloc
+                .with_locked(|inner| inner.largest_free_block())
+                .unwrap(),
+            largest
+        );
+        assert!(matches!(
+            alloc.alloc_array_zeroed::<u64>(usize::MAX),
+            Err(BuddyError::TooBigSize)
+        ));
+    }
+    #[test]
+    fn can_allocate() {
+        #[repr(align(4096))]
+        struct MemChunk([u8; 4096]);
+        let mut chunk = MemChunk([0; 4096]);
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                chunk.0.as_mut_slice(),
+                None,
+            )),
+            None,
+        );
+        let layout = |size| Layout::from_size_align(size, 8).unwrap();
+        alloc.allocate(layout(1024)).unwrap();
+        // The metadata and 1024 blocks are taken: 2048 is fine, 4096 never fits
+        assert!(alloc.can_allocate(layout(2048)).is_ok());
+        alloc.allocate(layout(2048)).unwrap();
+        assert!(matches!(
+            alloc.can_allocate(layout(2048)),
+            Err(BuddyError::NoMoreSpace)
+        ));
+        assert!(matches!(
+            alloc.can_allocate(layout(4096)),
+            Err(BuddyError::CannotFit)
+        ));
+        assert!(matches!(
+            alloc.can_allocate(Layout::from_size_align(8, 2 * MAX_SUPPORTED_ALIGN).unwrap()),
+            Err(BuddyError::TooBigAlignment)
+        ));
+        // Nothing has been allocated: the 512 block next to the metadata one is still there
+        assert!(alloc.can_allocate(layout(512)).is_ok());
+        alloc.allocate(layout(512)).unwrap();
+        assert!(alloc.can_allocate(layout(512)).is_err());
+    }
+    #[test]
+    fn scratch() {
+        #[repr(align(4096))]
+        struct MemChunk([u8; 4096]);
+        let mut chunk = MemChunk([0; 4096]);
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                chunk.0.as_mut_slice(),
+                None,
+            )),
+            None,
+        );
+        let free = alloc.snapshot_stats().unwrap().free;
+        {
+            let mut scratch = alloc
+                .scratch(Layout::from_size_align(1024, 8).unwrap())
+                .unwrap();
+            assert_eq!(scratch.len(), 1024);
+            scratch.fill(0x42);
+            assert_eq!(alloc.snapshot_stats().unwrap().free, free - 1024);
+        }
+        assert_eq!(alloc.snapshot_stats().unwrap().free, free);
+    }
+    #[test]
+    fn allocate_init() {
+        const ARENA_SIZE: usize = 4096;
+        let mut memory = vec![0xff; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        );
+        let (block, len) = alloc
+            .allocate_init(Layout::from_size_align(100, 8).unwrap(), |region| {
+                for (i, byte) in region.iter_mut().enumerate() {
+                    byte.write(i as u8);
+                }
+                region.len()
+            })
+            .unwrap();
+        assert_eq!(len, block.len());
+        let bytes = unsafe { block.as_ref() };
+        assert!(bytes.iter().enumerate().all(|(i, byte)| *byte == i as u8));
+    }
+    #[test]
+    fn allocate_with() {
+        const ARENA_SIZE: usize = 1024 * 1024;
+        const THREADS: usize = 4;
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        );
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let blocks: Vec<(usize, u8)> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..THREADS as u8)
+                .map(|pattern| {
+                    let alloc = &alloc;
+                    scope.spawn(move || {
+                        (0..1000)
+                            .map(|_| {
+                                let (block, len) = alloc
+                                    .allocate_with(layout, |bytes| {
+                                        bytes.fill(pattern);
+                                        bytes.len()
+                                    })
+                                    .unwrap();
+                                assert_eq!(len, 64);
+                                (block.as_mut_ptr().addr(), pattern)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .flat_map(|thread| thread.join().unwrap())
+                .collect()
+        });
+        // No later allocation has written over an initialized block
+        for (addr, pattern) in blocks {
+            let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, 64) };
+            assert!(bytes.iter().all(|byte| *byte == pattern));
+        }
+    }
+    #[test]
+    fn try_allocate_iter() {
+        #[repr(align(4096))]
+        struct MemChunk([u8; 4096]);
+        let mut chunk = MemChunk([0; 4096]);
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                chunk.0.as_mut_slice(),
+                None,
+            )),
+            None,
+        );
+        let layouts = core::iter::repeat(Layout::from_size_align(512, 8).unwrap()).take(10);
+        let results: Vec<_> = alloc.try_allocate_iter(layouts).collect();
+        // The metadata takes the first 512 bytes block, 7 remain
+        assert!(results[..7].iter().all(|r| r.is_ok()));
+        assert!(results[7..]
+            .iter()
+            .all(|r| matches!(r, Err(BuddyError::NoMoreSpace))));
+        for block in results.into_iter().flatten() {
+            alloc
+                .deallocate(
+                    block.as_non_null_ptr(),
+                    Layout::from_size_align(512, 8).unwrap(),
+                )
+                .unwrap();
+        }
+        assert!(alloc
+            .can_allocate(Layout::from_size_align(2048, 8).unwrap())
+            .is_ok());
+    }
+    #[test]
+    fn leaked_locations() {
+        #[repr(align(4096))]
+        struct MemChunk([u8; 4096]);
+        let mut chunk = MemChunk([0; 4096]);
+        let mut table = [None; 4096 / MIN_CELL_LEN * 2];
+        let mut inner = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(chunk.0.as_mut_slice(), None);
+        inner.set_location_table(Some(&mut table));
+        let alloc = ProtectedAllocator::new(Mutex::new(inner), None);
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        let freed = alloc.allocate_tracked(layout).unwrap();
+        let (leaked, line) = (alloc.allocate_tracked(layout).unwrap(), line!());
+        let untracked = alloc.allocate(layout).unwrap();
+        alloc.deallocate(freed.as_non_null_ptr(), layout).unwrap();
+        alloc
+            .deallocate(untracked.as_non_null_ptr(), layout)
+            .unwrap();
+        let mut leaks = Vec::new();
+        alloc.leaked_locations(|offset, size, location| leaks.push((offset, size, location)));
+        assert_eq!(leaks.len(), 1);
+        let (offset, size, location) = leaks[0];
+        let base = alloc.with_locked(|r| r.base_ptr()).unwrap();
+        assert_eq!(base.as_ptr().wrapping_add(offset), leaked.as_mut_ptr());
+        assert_eq!(size, 256);
+        assert_eq!((location.file(), location.line()), (file!(), line));
+    }
+    #[test]
+    fn allocate_adjacent() {
+        #[repr(align(4096))]
+        struct MemChunk([u8; 4096]);
+        let mut chunk = MemChunk([0; 4096]);
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                chunk.0.as_mut_slice(),
+                None,
+            )),
+            None,
+        );
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        // The metadata take the first 256 block: 'first' is its right buddy, the next block
+        // begins the following 512 one
+        let first = alloc.allocate(layout).unwrap();
+        let second = alloc
+            .allocate_adjacent(first.as_non_null_ptr(), layout)
+            .unwrap();
+        assert_eq!(first.as_mut_ptr().wrapping_add(256), second.as_mut_ptr());
+        // Both form a single contiguous buffer
+        let buffer = unsafe { core::slice::from_raw_parts_mut(first.as_mut_ptr(), 512) };
+        buffer.fill(0xaa);
+        assert!(buffer.iter().all(|byte| *byte == 0xaa));
+        // The block after 'second' is its own buddy
+        let third = alloc
+            .allocate_adjacent(second.as_non_null_ptr(), layout)
+            .unwrap();
+        assert_eq!(second.as_mut_ptr().wrapping_add(256), third.as_mut_ptr());
+        alloc.deallocate(third.as_non_null_ptr(), layout).unwrap();
+        let other = alloc.allocate(layout).unwrap();
+        assert_eq!(other, third);
+        assert!(matches!(
+            alloc.allocate_adjacent(second.as_non_null_ptr(), layout),
+            Err(BuddyError::NoMoreSpace)
+        ));
+        alloc.deallocate(other.as_non_null_ptr(), layout).unwrap();
+        assert!(matches!(
+            alloc.allocate_adjacent(other.as_non_null_ptr(), layout),
+            Err(BuddyError::DoubleFreeOrCorruption)
+        ));
+    }
+    #[test]
+    fn internal_waste() {
+        #[repr(align(4096))]
+        struct MemChunk([u8; 4096]);
+        let mut chunk = MemChunk([0; 4096]);
+        let mut table = [0; 4096 / MIN_CELL_LEN * 2];
+        let mut inner = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(chunk.0.as_mut_slice(), None);
+        inner.set_size_table(Some(&mut table));
+        let alloc = ProtectedAllocator::new(Mutex::new(inner), None);
+        let layout = |size| Layout::from_size_align(size, 8).unwrap();
+        // The metadata block is not a request
+        assert_eq!(alloc.internal_waste().unwrap(), 0);
+        let a = alloc.allocate(layout(1000)).unwrap();
+        let b = alloc.allocate(layout(100)).unwrap();
+        let c = alloc.allocate(layout(64)).unwrap();
+        let d = alloc.allocate(layout(300)).unwrap();
+        assert_eq!(alloc.internal_waste().unwrap(), 24 + 28 + 0 + 212);
+        alloc.deallocate(b.as_non_null_ptr(), layout(100)).unwrap();
+        assert_eq!(alloc.internal_waste().unwrap(), 24 + 212);
+        for (block, size) in [(a, 1000), (c, 64), (d, 300)] {
+            alloc
+                .deallocate(block.as_non_null_ptr(), layout(size))
+                .unwrap();
+        }
+        assert_eq!(alloc.internal_waste().unwrap(), 0);
+    }
+    #[test]
+    fn allocate_boxed() {
+        #[repr(align(4096))]
+        struct MemChunk([u8; 4096]);
+        let mut chunk = MemChunk([0; 4096]);
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                chunk.0.as_mut_slice(),
+                None,
+            )),
+            None,
+        );
+        struct Point {
+            x: u64,
+            y: u64,
+            name: Arc<str>,
+        }
+        let name: Arc<str> = Arc::from("origin");
+        let used = alloc.snapshot_stats().unwrap().used;
+        let mut point = alloc
+            .allocate_boxed(Point {
+                x: 0,
+                y: 0,
+                name: name.clone(),
+            })
+            .unwrap();
+        point.x += 3;
+        point.y = point.x * 2;
+        assert_eq!((point.x, point.y, &*point.name), (3, 6, "origin"));
+        assert_eq!(alloc.snapshot_stats().unwrap().used, used + MIN_CELL_LEN);
+        assert_eq!(Arc::strong_count(&name), 2);
+        drop(point);
+        // The value is dropped and its block is free again
+        assert_eq!(Arc::strong_count(&name), 1);
+        assert_eq!(alloc.snapshot_stats().unwrap().used, used);
+    }
+    #[test]
+    fn allocate_with_slack() {
+        #[repr(align(4096))]
+        struct MemChunk([u8; 4096]);
+        let mut chunk = MemChunk([0; 4096]);
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                chunk.0.as_mut_slice(),
+                None,
+            )),
+            None,
+        );
+        let (block, slack) = alloc
+            .allocate_with_slack(Layout::from_size_align(100, 8).unwrap())
+            .unwrap();
+        assert_eq!((block.len(), slack), (128, 28));
+        let (_, slack) = alloc
+            .allocate_with_slack(Layout::from_size_align(64, 8).unwrap())
+            .unwrap();
+        assert_eq!(slack, 0);
+    }
+    #[test]
+    fn write_prometheus() {
+        #[repr(align(4096))]
+        struct MemChunk([u8; 4096]);
+        let mut chunk = MemChunk([0; 4096]);
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                chunk.0.as_mut_slice(),
+                None,
+            )),
+            None,
+        );
+        alloc
+            .allocate(Layout::from_size_align(2048, 8).unwrap())
+            .unwrap();
+        let mut out = String::new();
+        alloc.write_prometheus(&mut out, "buddy").unwrap();
+        // The metadata take 128 bytes, then a 128, a 256, a 512 and a 1024 blocks are free
+        for line in [
+            "buddy_free_bytes 1920",
+            "buddy_used_bytes 2176",
+            "buddy_largest_free_block 1024",
+            "buddy_live_allocations 2",
+            "# TYPE buddy_fragmentation gauge",
+        ] {
+            assert!(out.lines().any(|l| l == line), "{} not in\n{}", line, out);
+        }
+        let fragmentation = out
+            .lines()
+            .find_map(|l| l.strip_prefix("buddy_fragmentation "))
+            .unwrap();
+        assert!((fragmentation.parse::<f32>().unwrap() - (1. - 1024. / 1920.)).abs() < 1e-6);
+    }
+    #[test]
+    fn grow_with_stronger_alignment() {
+        #[derive(Clone, Copy)]
+        #[repr(align(64))]
+        struct Aligned64(u32);
+        const ARENA_SIZE: usize = 64 * 1024;
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<8>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        );
+        let mut v: Vec<Aligned64, _> = Vec::new_in(&alloc);
+        let mut last_capacity = 0;
+        let mut reallocations = 0;
+        for i in 0..256 {
+            v.push(Aligned64(i));
+            if v.capacity() != last_capacity {
+                last_capacity = v.capacity();
+                let last_ptr = v.as_ptr();
+                reallocations += 1;
+                assert_eq!(last_ptr as usize % 64, 0);
+                assert!(v.iter().enumerate().all(|(j, e)| e.0 == j as u32));
+            }
+        }
+        assert!(reallocations > 1);
+    }
+    #[cfg(feature = "latency-stats")]
+    #[test]
+    fn latency_histogram() {
+        #[repr(align(4096))]
+        struct MemChunk([u8; 4096]);
+        let mut chunk = MemChunk([0; 4096]);
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                chunk.0.as_mut_slice(),
+                None,
+            )),
+            None,
+        );
+        let layout = Layout::from_size_align(128, 8).unwrap();
+        for _ in 0..1000 {
+            let b = alloc.allocate(layout).unwrap();
+            alloc.deallocate(b.as_non_null_ptr(), layout).unwrap();
+        }
+        assert_eq!(alloc.latency_histogram().iter().sum::<u64>(), 2000);
+    }
 }
 mod buddy_convert {
     use super::*;
@@ -320,6 +978,32 @@ mod order_convert {
         .unwrap();
     }
 }
+mod metadata_len {
+    use super::*;
+    #[test]
+    fn largest_address_space() {
+        // usize::MAX is the biggest address space allowed
+        let max_order = Order::try_from((
+            BuddySize::<MIN_CELL_LEN>(MIN_CELL_LEN),
+            BuddySize::<MIN_CELL_LEN>(usize::MAX),
+        ))
+        .unwrap();
+        assert_eq!(
+            tree_len(max_order).unwrap(),
+            (usize::MAX / MIN_CELL_LEN + 1) * 2
+        );
+    }
+    #[test]
+    fn boundary() {
+        let bits = usize::BITS as u8;
+        assert_eq!(tree_len(Order(bits - 2)).unwrap(), 1 << (bits - 1));
+        assert!(matches!(
+            tree_len(Order(bits - 1)),
+            Err(BuddyError::TooBigSize)
+        ));
+        assert!(matches!(tree_len(Order(bits)), Err(BuddyError::TooBigSize)));
+    }
+}
 #[cfg(none)]
 mod constructor {
     use super::*;
@@ -478,3 +1162,3297 @@ mod constructor {
         )));
     }
 }
+#[cfg(not(feature = "no-std"))]
+mod occupancy_bitmap {
+    use super::*;
+    const ARENA_SIZE: usize = MIN_CELL_LEN << 16; // max order 16
+    #[test]
+    fn same_behavior_than_packed() {
+        srand_init(7);
+        let mut packed_memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut bitmap_memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let packed_chunk = aligned_slice(&mut packed_memory, ARENA_SIZE);
+        let bitmap_chunk = aligned_slice(&mut bitmap_memory, ARENA_SIZE);
+        let packed_base = packed_chunk.as_ptr() as usize;
+        let bitmap_base = bitmap_chunk.as_ptr() as usize;
+        // Metadata outside of the arena: both allocators share the same memory layout
+        let mut packed_meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut bitmap_meta =
+            vec![0; ARENA_SIZE / MIN_CELL_LEN * 2 + ARENA_SIZE / MIN_CELL_LEN / 4];
+        let mut packed = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            packed_chunk,
+            Some(packed_meta.as_mut_slice()),
+        );
+        let mut bitmap = InnerAllocator::<MIN_CELL_LEN, true>::new_from_refs(
+            bitmap_chunk,
+            Some(bitmap_meta.as_mut_slice()),
+        );
+        let mut v = Vec::new();
+        for _ in 0..4096 {
+            if bool::srand(true) && !v.is_empty() {
+                let (p, b, layout): (NonNull<u8>, NonNull<u8>, Layout) =
+                    v.remove(usize::srand(v.len() - 1));
+                packed.dealloc(p, layout).unwrap();
+                bitmap.dealloc(b, layout).unwrap();
+            } else {
+                let layout = Layout::from_size_align(usize::srand(4096) + 1, 8).unwrap();
+                match (packed.alloc(layout), bitmap.alloc(layout)) {
+                    (Ok(p), Ok(b)) => {
+                        assert_eq!(
+                            p.as_mut_ptr() as usize - packed_base,
+                            b.as_mut_ptr() as usize - bitmap_base
+                        );
+                        v.push((p.as_non_null_ptr(), b.as_non_null_ptr(), layout));
+                    }
+                    (Err(_), Err(_)) => {}
+                    _ => panic!("Packed and bitmap allocators diverge"),
+                }
+            }
+        }
+        for (p, b, layout) in v.into_iter() {
+            packed.dealloc(p, layout).unwrap();
+            bitmap.dealloc(b, layout).unwrap();
+        }
+        // Double free must be detected with the bitmap too
+        let layout = Layout::from_size_align(MIN_CELL_LEN, MIN_CELL_LEN).unwrap();
+        let b = bitmap.alloc(layout).unwrap();
+        bitmap.dealloc(b.as_non_null_ptr(), layout).unwrap();
+        assert!(bitmap.dealloc(b.as_non_null_ptr(), layout).is_err());
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod relocation {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn rebase_on_copied_buffer() {
+        let mut old_memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut new_memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let old_chunk = aligned_slice(&mut old_memory, ARENA_SIZE);
+        let new_chunk = aligned_slice(&mut new_memory, ARENA_SIZE);
+        let new_base = NonNull::new(new_chunk.as_mut_ptr()).unwrap();
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(old_chunk, None);
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        let block = alloc.alloc(layout).unwrap();
+        unsafe { block.as_mut_ptr().write_bytes(0x42, 256) };
+        let offset = block.as_mut_ptr() as usize - alloc.base_ptr().as_ptr() as usize;
+        // Copy the whole address space then relocate
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                alloc.base_ptr().as_ptr(),
+                new_base.as_ptr(),
+                ARENA_SIZE,
+            );
+            alloc.rebase(new_base);
+        }
+        assert_eq!(alloc.base_ptr(), new_base);
+        let relocated = unsafe { new_base.as_ptr().add(offset) };
+        assert_eq!(unsafe { *relocated.add(255) }, 0x42);
+        let other = alloc.alloc(layout).unwrap();
+        let other_offset = other.as_mut_ptr() as usize - new_base.as_ptr() as usize;
+        assert!(other_offset < ARENA_SIZE && other_offset != offset);
+        alloc
+            .dealloc(NonNull::new(relocated).unwrap(), layout)
+            .unwrap();
+        alloc.dealloc(other.as_non_null_ptr(), layout).unwrap();
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod ring_mode {
+    use super::*;
+    use std::collections::VecDeque;
+    const ARENA_SIZE: usize = 1024 * 1024;
+    const LIVE_BUFFERS: usize = 32;
+    #[test]
+    fn fifo_stays_contiguous() {
+        srand_init(12);
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut alloc =
+            InnerAllocator::<64>::new_from_refs(aligned_slice(&mut memory, ARENA_SIZE), None);
+        alloc.set_ring_mode(true);
+        let mut fifo = VecDeque::new();
+        for _ in 0..10000 {
+            if fifo.len() == LIVE_BUFFERS {
+                let (ptr, layout): (NonNull<u8>, Layout) = fifo.pop_front().unwrap();
+                alloc.dealloc(ptr, layout).unwrap();
+            }
+            let layout = Layout::from_size_align(usize::srand(4095) + 1, 8).unwrap();
+            let ptr = alloc.alloc(layout).unwrap().as_non_null_ptr();
+            fifo.push_back((ptr, layout));
+            // At most 32 * 4096 bytes are alive: a quarter of the arena at least must be free
+            assert!(alloc.largest_free_block() >= ARENA_SIZE / 4);
+        }
+        for (ptr, layout) in fifo.into_iter() {
+            alloc.dealloc(ptr, layout).unwrap();
+        }
+        // Out-of-order frees are handled by the buddy tree, everything must be coalesced
+        assert_eq!(alloc.largest_free_block(), ARENA_SIZE / 2);
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod metadata_alignment {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn aligned_or_not() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta_memory = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2 + MAX_SUPPORTED_ALIGN + 1];
+        let meta = aligned_slice(&mut meta_memory, ARENA_SIZE / MIN_CELL_LEN * 2 + 1);
+        let (_, unaligned_meta) = meta.split_at_mut(1);
+        assert!(matches!(
+            InnerAllocator::<MIN_CELL_LEN>::new_from_refs_aligned(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                unaligned_meta,
+                METADATA_ALIGN
+            ),
+            Err(BuddyError::MisalignedMetadata)
+        ));
+        let meta = aligned_slice(&mut meta_memory, ARENA_SIZE / MIN_CELL_LEN * 2);
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs_aligned(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            meta,
+            METADATA_ALIGN,
+        )
+        .unwrap();
+        let layout = Layout::from_size_align(ARENA_SIZE, 8).unwrap();
+        // Metadata are outside: the whole address space is allocable
+        let b = alloc.alloc(layout).unwrap();
+        alloc.dealloc(b.as_non_null_ptr(), layout).unwrap();
+    }
+}
+#[cfg(all(not(feature = "no-std"), feature = "count-accesses"))]
+mod count_accesses {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn single_allocation() {
+        for order in [0, 3, 6, 9] {
+            let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+            let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+            let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                Some(meta.as_mut_slice()),
+            );
+            // Write the metadata first
+            assert_eq!(alloc.largest_free_block(), ARENA_SIZE);
+            alloc.reset_meta_accesses();
+            let layout = Layout::from_size_align(ARENA_SIZE >> order, 8).unwrap();
+            alloc.alloc(layout).unwrap();
+            // Root check + one read by level on descent, then 3 reads and 1 write by parent
+            assert_eq!(
+                alloc.meta_accesses(),
+                (1 + order + 3 * order, 1 + order),
+                "order {}",
+                order
+            );
+        }
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod overlaps {
+    use super::*;
+    const ARENA_SIZE: usize = 64 * 1024;
+    #[test]
+    fn no_overlaps_under_stress() {
+        srand_init(33);
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            None,
+        );
+        let mut v = Vec::new();
+        for _ in 0..2048 {
+            if bool::srand(true) && !v.is_empty() {
+                let (ptr, layout): (NonNull<u8>, Layout) = v.remove(usize::srand(v.len() - 1));
+                alloc.dealloc(ptr, layout).unwrap();
+            } else {
+                let layout = Layout::from_size_align(usize::srand(1024) + 1, 8).unwrap();
+                if let Ok(b) = alloc.alloc(layout) {
+                    v.push((b.as_non_null_ptr(), layout));
+                    alloc.assert_no_overlaps().unwrap();
+                }
+            }
+        }
+        // Metadata block + live blocks
+        assert_eq!(alloc.live_blocks().count(), v.len() + 1);
+    }
+    #[test]
+    fn corrupted_tree() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            None,
+        );
+        let layout = Layout::from_size_align(ARENA_SIZE / 4, 8).unwrap();
+        let b = alloc.alloc(layout).unwrap();
+        let offset = b.as_mut_ptr() as usize - alloc.base_ptr().as_ptr() as usize;
+        alloc.assert_no_overlaps().unwrap();
+        // Index of the block at order 2, then mark its left child as occupied too
+        let index = 4 + offset / (ARENA_SIZE / 4);
+        alloc.raw_meta()[2 * index] |= 0x80;
+        assert!(matches!(
+            alloc.assert_no_overlaps(),
+            Err(BuddyError::DoubleFreeOrCorruption)
+        ));
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod reservations {
+    use super::*;
+    const ARENA_SIZE: usize = 64 * 1024;
+    #[test]
+    fn allocations_avoid_reserved_regions() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let reservations = [(16 * 1024, 4096), (40 * 1024 + 100, 1000)];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_with_reservations(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            &reservations,
+        )
+        .unwrap();
+        let base = alloc.base_ptr().as_ptr() as usize;
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        let mut count = 0;
+        while let Ok(b) = alloc.alloc(layout) {
+            let offset = b.as_mut_ptr() as usize - base;
+            for (start, size) in reservations.iter() {
+                assert!(offset + 256 <= *start || offset >= start + size);
+            }
+            count += 1;
+        }
+        // Metadata (16K) and the covering blocks (4096 and 2048 bytes) are not allocable
+        assert_eq!(count, (ARENA_SIZE - 16 * 1024 - 4096 - 2048) / 256);
+    }
+    #[test]
+    fn overlapping_reservations() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        assert!(matches!(
+            InnerAllocator::<MIN_CELL_LEN>::new_with_reservations(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                &[(4096, 4096), (6000, 16)],
+            ),
+            Err(BuddyError::NoMoreSpace)
+        ));
+    }
+    #[test]
+    fn reserve_then_unreserve() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            None,
+        );
+        let base = alloc.base_ptr().as_ptr() as usize;
+        let index = 32 * 1024 / MIN_CELL_LEN;
+        assert_eq!(alloc.reserve(index, 4096).unwrap(), index);
+        assert!(matches!(
+            alloc.reserve(index + 1, 8),
+            Err(BuddyError::NoMoreSpace)
+        ));
+        // The reserved block cannot be deallocated, and a cell inside it is not a reservation
+        let reserved = NonNull::new((base + 32 * 1024) as *mut u8).unwrap();
+        let layout = Layout::from_size_align(4096, 8).unwrap();
+        assert!(matches!(
+            alloc.dealloc(reserved, layout),
+            Err(BuddyError::DoubleFreeOrCorruption)
+        ));
+        assert!(matches!(
+            alloc.unreserve(index + 1),
+            Err(BuddyError::DoubleFreeOrCorruption)
+        ));
+        let mut v = Vec::new();
+        while let Ok(b) = alloc.alloc(layout) {
+            let offset = b.as_mut_ptr() as usize - base;
+            assert!(offset + 4096 <= 32 * 1024 || offset >= 36 * 1024);
+            v.push(b);
+        }
+        // Metadata (16K) and the reserved block are not allocable
+        assert_eq!(v.len(), (ARENA_SIZE - 16 * 1024 - 4096) / 4096);
+        let allocated = v[0].as_mut_ptr() as usize - base;
+        assert!(matches!(
+            alloc.unreserve(allocated / MIN_CELL_LEN),
+            Err(BuddyError::DoubleFreeOrCorruption)
+        ));
+        alloc.unreserve(index).unwrap();
+        assert!(matches!(
+            alloc.unreserve(index),
+            Err(BuddyError::DoubleFreeOrCorruption)
+        ));
+        let b = alloc.alloc(layout).unwrap();
+        assert_eq!(b.as_mut_ptr() as usize - base, 32 * 1024);
+    }
+    #[test]
+    fn unaligned_reservation() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(meta.as_mut_slice()),
+        );
+        // Cells 3 and 4 are on both sides of a buddy boundary: the block begins at cell 0
+        let start = alloc.reserve(3, 2 * MIN_CELL_LEN).unwrap();
+        assert_eq!(start, 0);
+        assert!(matches!(
+            alloc.unreserve(3),
+            Err(BuddyError::DoubleFreeOrCorruption)
+        ));
+        alloc.unreserve(start).unwrap();
+        assert_eq!(alloc.largest_free_block(), ARENA_SIZE);
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod explain {
+    use super::*;
+    const ARENA_SIZE: usize = 64 * 1024;
+    #[test]
+    fn plan_match_allocation() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            None,
+        );
+        let base = alloc.base_ptr().as_ptr() as usize;
+        let layout = Layout::from_size_align(3000, 8).unwrap();
+        let plan = alloc.explain(layout).unwrap();
+        assert_eq!(plan.buddy_size, 4096);
+        assert_eq!(plan.order, 4);
+        assert_eq!(plan.descent, 1..32);
+        let b = alloc.alloc(layout).unwrap();
+        assert_eq!(b.len(), plan.buddy_size);
+        let index = plan.index.unwrap();
+        assert_eq!(
+            b.as_mut_ptr() as usize - base,
+            (index - (1 << plan.order)) * plan.buddy_size
+        );
+    }
+}
+mod arena_set {
+    use super::*;
+    #[repr(align(4096))]
+    struct MemChunk([u8; 1024]);
+    #[test]
+    fn spill_across_regions() {
+        let (mut a, mut b, mut c) = (
+            MemChunk([0; 1024]),
+            MemChunk([0; 1024]),
+            MemChunk([0; 1024]),
+        );
+        let mut set = ArenaSet::<4, 64>::new();
+        set.add_region(&mut a.0).unwrap();
+        set.add_region(&mut b.0).unwrap();
+        set.add_region(&mut c.0).unwrap();
+        // Metadata take 64 bytes of each region, a half is still free inside each one
+        let layout = Layout::from_size_align(512, 8).unwrap();
+        let mut blocks = [None; 4];
+        for block in blocks.iter_mut().take(3) {
+            *block = Some(set.allocate(layout).unwrap().as_non_null_ptr());
+        }
+        assert!(matches!(set.allocate(layout), Err(BuddyError::NoMoreSpace)));
+        assert!(matches!(
+            set.allocate(Layout::from_size_align(2048, 8).unwrap()),
+            Err(BuddyError::CannotFit)
+        ));
+        for block in blocks.iter().flatten() {
+            set.deallocate(*block, layout).unwrap();
+        }
+        // Everything is free again
+        for block in blocks.iter_mut().take(3) {
+            *block = Some(set.allocate(layout).unwrap().as_non_null_ptr());
+        }
+        assert!(matches!(
+            set.deallocate(NonNull::dangling(), layout),
+            Err(BuddyError::DoubleFreeOrCorruption)
+        ));
+    }
+    #[test]
+    fn located() {
+        let (mut a, mut b) = (MemChunk([0; 1024]), MemChunk([0; 1024]));
+        let mut set = ArenaSet::<4, 64>::new();
+        set.add_region(&mut a.0).unwrap();
+        set.add_region(&mut b.0).unwrap();
+        let layout = Layout::from_size_align(512, 8).unwrap();
+        let (first, first_region) = set.allocate_located(layout).unwrap();
+        let (second, second_region) = set.allocate_located(layout).unwrap();
+        assert_eq!((first_region, second_region), (0, 1));
+        // A wrong or empty region is an error
+        assert!(set
+            .deallocate_located(first.as_non_null_ptr(), layout, second_region)
+            .is_err());
+        assert!(set
+            .deallocate_located(first.as_non_null_ptr(), layout, 2)
+            .is_err());
+        set.deallocate_located(first.as_non_null_ptr(), layout, first_region)
+            .unwrap();
+        set.deallocate_located(second.as_non_null_ptr(), layout, second_region)
+            .unwrap();
+        assert_eq!(set.allocate_located(layout).unwrap().1, 0);
+    }
+    #[test]
+    fn bad_region() {
+        let (mut a, mut b) = (MemChunk([0; 1024]), MemChunk([0; 1024]));
+        let mut set = ArenaSet::<4, 64>::new();
+        // Not a power of two, then too short
+        assert!(matches!(
+            set.add_region(&mut a.0[..768]),
+            Err(BuddyError::InvalidAddressSpace)
+        ));
+        assert!(matches!(
+            set.add_region(&mut b.0[..128]),
+            Err(BuddyError::InvalidAddressSpace)
+        ));
+        // The slot is still free
+        let mut c = MemChunk([0; 1024]);
+        assert_eq!(set.add_region(&mut c.0).unwrap(), 0);
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod idempotent_free {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn double_free() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            None,
+        );
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        let b = alloc.alloc(layout).unwrap().as_non_null_ptr();
+        alloc.dealloc(b, layout).unwrap();
+        assert!(alloc.dealloc(b, layout).is_err());
+        alloc.set_idempotent_free(true);
+        alloc.dealloc(b, layout).unwrap();
+        // The second free is a no-op: the block is allocable once
+        let c = alloc.alloc(layout).unwrap().as_non_null_ptr();
+        assert_eq!(b, c);
+        let d = alloc.alloc(layout).unwrap().as_non_null_ptr();
+        assert_ne!(c, d);
+        // Wrong order or foreign pointer are still errors
+        assert!(alloc
+            .dealloc(c, Layout::from_size_align(512, 8).unwrap())
+            .is_err());
+        assert!(alloc.dealloc(NonNull::dangling(), layout).is_err());
+        alloc.dealloc(c, layout).unwrap();
+        alloc.dealloc(d, layout).unwrap();
+    }
+}
+#[cfg(all(not(feature = "no-std"), feature = "histogram"))]
+mod histogram {
+    use super::*;
+    const ARENA_SIZE: usize = 64 * 1024;
+    #[test]
+    fn align_classes() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            None,
+        );
+        for (align, count) in [(1, 2), (8, 1), (16, 3), (256, 1), (4096, 2)] {
+            for _ in 0..count {
+                alloc
+                    .alloc(Layout::from_size_align(8, align).unwrap())
+                    .unwrap();
+            }
+        }
+        assert_eq!(alloc.align_histogram(), [3, 3, 0, 0, 0, 1, 0, 0, 0, 2]);
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod avoid_splitting {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn exact_order_only() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            None,
+        );
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        // The metadata take the first quarter: the free blocks are the second quarter and the
+        // upper half, none of them has the exact order
+        assert!(matches!(
+            alloc.alloc_avoid_splitting(layout),
+            Err(BuddyError::NoMoreSpace)
+        ));
+        let a = alloc.alloc(layout).unwrap().as_non_null_ptr();
+        // The buddy of 'a' is now a free block of the exact order
+        let b = alloc
+            .alloc_avoid_splitting(layout)
+            .unwrap()
+            .as_non_null_ptr();
+        assert_eq!(b.addr().get() - a.addr().get(), 256);
+        assert!(matches!(
+            alloc.alloc_avoid_splitting(layout),
+            Err(BuddyError::NoMoreSpace)
+        ));
+        let c = alloc
+            .alloc_avoid_splitting(Layout::from_size_align(512, 8).unwrap())
+            .unwrap()
+            .as_non_null_ptr();
+        assert_eq!(c.addr().get() - a.addr().get(), 512);
+        alloc.assert_no_overlaps().unwrap();
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod raw_parts {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn give_back_the_memory() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let capacity = memory.capacity();
+        let chunk = aligned_slice(&mut memory, ARENA_SIZE);
+        let ptr = chunk.as_mut_ptr();
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(chunk, None);
+        let block = alloc.alloc(layout).unwrap();
+        alloc.dealloc(block.as_non_null_ptr(), layout).unwrap();
+        let (arena, meta) = alloc.into_raw_parts();
+        assert!(meta.is_none());
+        assert_eq!((arena.as_mut_ptr(), arena.len()), (ptr, ARENA_SIZE));
+        // The memory may serve again
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(arena, meta);
+        alloc.alloc(layout).unwrap();
+        drop(alloc);
+        assert_eq!(memory.capacity(), capacity);
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod reentrancy {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 4096;
+    const MIN_CELL_LEN: usize = 64;
+    static mut SPACE: StaticAddressSpace<ARENA_SIZE, MIN_CELL_LEN> = StaticAddressSpace::new();
+    static HOOK_CALLS: AtomicUsize = AtomicUsize::new(0);
+    static ALLOCATOR: ProtectedAllocator<Mutex<InnerAllocator<MIN_CELL_LEN>>, MIN_CELL_LEN> =
+        ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_static(unsafe {
+                &mut SPACE
+            })),
+            Some(|_| {
+                HOOK_CALLS.fetch_add(1, Ordering::Relaxed);
+                // Log on the same allocator: a small one which works, then a failing one
+                let layout = Layout::from_size_align(64, 8).unwrap();
+                let log = ALLOCATOR.allocate(layout).unwrap();
+                ALLOCATOR.deallocate(log.as_non_null_ptr(), layout).unwrap();
+                assert!(ALLOCATOR
+                    .allocate(Layout::from_size_align(2 * ARENA_SIZE, 8).unwrap())
+                    .is_err());
+            }),
+        );
+    #[test]
+    fn allocating_hook() {
+        assert!(!ALLOCATOR.reentrant());
+        assert!(matches!(
+            ALLOCATOR.allocate(Layout::from_size_align(2 * ARENA_SIZE, 8).unwrap()),
+            Err(BuddyError::CannotFit)
+        ));
+        assert_eq!(HOOK_CALLS.load(Ordering::Relaxed), 1);
+        assert!(ALLOCATOR.reentrant());
+    }
+    static mut SHARED_SPACE: StaticAddressSpace<ARENA_SIZE, MIN_CELL_LEN> =
+        StaticAddressSpace::new();
+    static RUNNING_HOOKS: AtomicUsize = AtomicUsize::new(0);
+    static SHARED: ProtectedAllocator<Mutex<InnerAllocator<MIN_CELL_LEN>>, MIN_CELL_LEN> =
+        ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_static(unsafe {
+                &mut SHARED_SPACE
+            })),
+            Some(|_| {
+                // Wait for the hook of the other thread
+                RUNNING_HOOKS.fetch_add(1, Ordering::Relaxed);
+                let start = std::time::Instant::now();
+                while RUNNING_HOOKS.load(Ordering::Relaxed) < 2
+                    && start.elapsed() < std::time::Duration::from_secs(5)
+                {
+                    std::thread::yield_now();
+                }
+            }),
+        );
+    #[test]
+    fn hooks_of_two_threads() {
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    SHARED
+                        .allocate(Layout::from_size_align(2 * ARENA_SIZE, 8).unwrap())
+                        .is_err()
+                })
+            })
+            .collect();
+        for thread in threads {
+            assert!(thread.join().unwrap());
+        }
+        // Both hooks ran at the same time, none was taken for a reentrant call
+        assert_eq!(RUNNING_HOOKS.load(Ordering::Relaxed), 2);
+        assert!(!SHARED.reentrant());
+    }
+    static mut PANIC_SPACE: StaticAddressSpace<ARENA_SIZE, MIN_CELL_LEN> =
+        StaticAddressSpace::new();
+    static PANIC_CALLS: AtomicUsize = AtomicUsize::new(0);
+    static PANICKING: ProtectedAllocator<Mutex<InnerAllocator<MIN_CELL_LEN>>, MIN_CELL_LEN> =
+        ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_static(unsafe {
+                &mut PANIC_SPACE
+            })),
+            Some(|_| {
+                if PANIC_CALLS.fetch_add(1, Ordering::Relaxed) == 0 {
+                    panic!("First hook call");
+                }
+            }),
+        );
+    #[test]
+    fn panicking_hook() {
+        let layout = Layout::from_size_align(2 * ARENA_SIZE, 8).unwrap();
+        assert!(std::panic::catch_unwind(|| PANICKING.allocate(layout)).is_err());
+        // The unwinding cleared the mark, the hook is called again
+        assert!(PANICKING.allocate(layout).is_err());
+        assert_eq!(PANIC_CALLS.load(Ordering::Relaxed), 2);
+        assert!(!PANICKING.reentrant());
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod uniform_pool {
+    use super::*;
+    const ARENA_SIZE: usize = 64 * 1024;
+    const CELL: usize = 64;
+    #[test]
+    fn fixed_size_workload() {
+        let mut pool_memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut bitmap = vec![0; UniformPool::<CELL>::bitmap_len(ARENA_SIZE)];
+        let mut pool = UniformPool::<CELL>::new_from_refs(
+            aligned_slice(&mut pool_memory, ARENA_SIZE),
+            &mut bitmap,
+        );
+        let mut buddy_memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / CELL * 2];
+        let mut buddy = InnerAllocator::<CELL>::new_from_refs(
+            aligned_slice(&mut buddy_memory, ARENA_SIZE),
+            Some(&mut meta),
+        );
+        // One bit per cell against two bytes per cell
+        assert_eq!(
+            UniformPool::<CELL>::bitmap_len(ARENA_SIZE) * 16,
+            ARENA_SIZE / CELL * 2
+        );
+        let layout = Layout::from_size_align(48, 16).unwrap();
+        let offsets = |pool_ptr: NonNull<[u8]>, buddy_ptr: NonNull<[u8]>| {
+            assert_eq!(pool_ptr.len(), buddy_ptr.len());
+            (pool_ptr.as_mut_ptr(), buddy_ptr.as_mut_ptr())
+        };
+        let mut live = Vec::new();
+        for _ in 0..ARENA_SIZE / CELL {
+            live.push(offsets(
+                pool.alloc(layout).unwrap(),
+                buddy.alloc(layout).unwrap(),
+            ));
+        }
+        assert!(matches!(pool.alloc(layout), Err(BuddyError::NoMoreSpace)));
+        assert!(matches!(buddy.alloc(layout), Err(BuddyError::NoMoreSpace)));
+        // Free one cell over three, both allocators give back the same holes
+        let pool_base = live[0].0 as usize;
+        let buddy_base = live[0].1 as usize;
+        for (pool_ptr, buddy_ptr) in live.iter().step_by(3) {
+            pool.dealloc(NonNull::new(*pool_ptr).unwrap(), layout)
+                .unwrap();
+            buddy
+                .dealloc(NonNull::new(*buddy_ptr).unwrap(), layout)
+                .unwrap();
+        }
+        for _ in live.iter().step_by(3) {
+            let (pool_ptr, buddy_ptr) =
+                offsets(pool.alloc(layout).unwrap(), buddy.alloc(layout).unwrap());
+            assert_eq!(
+                pool_ptr as usize - pool_base,
+                buddy_ptr as usize - buddy_base
+            );
+        }
+        assert!(pool
+            .dealloc(
+                NonNull::new(live[1].0).unwrap(),
+                Layout::from_size_align(128, 8).unwrap()
+            )
+            .is_err());
+        pool.dealloc(NonNull::new(live[1].0).unwrap(), layout)
+            .unwrap();
+        assert!(pool
+            .dealloc(NonNull::new(live[1].0).unwrap(), layout)
+            .is_err());
+        assert!(matches!(
+            pool.alloc(Layout::from_size_align(128, 8).unwrap()),
+            Err(BuddyError::CannotFit)
+        ));
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod dot {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn draw_tree() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            None,
+        );
+        let mut dot = String::new();
+        alloc.to_dot(&mut dot).unwrap();
+        assert!(dot.contains("n1 [label=\"0 +4096\", fillcolor=green]"));
+        alloc
+            .alloc(Layout::from_size_align(1024, 8).unwrap())
+            .unwrap();
+        alloc
+            .alloc(Layout::from_size_align(256, 8).unwrap())
+            .unwrap();
+        let mut dot = String::new();
+        alloc.to_dot(&mut dot).unwrap();
+        assert!(dot.starts_with("digraph buddy {"));
+        assert!(dot.contains("n1 -> n2;"));
+        // The metadata block, then the 1024 bytes one
+        assert!(dot.contains("n4 [label=\"0 +1024\", fillcolor=red]"));
+        assert!(dot.contains("n5 [label=\"1024 +1024\", fillcolor=red]"));
+        assert!(dot.contains("n24 [label=\"2048 +256\", fillcolor=red]"));
+        assert!(dot.contains("n7 [label=\"3072 +1024\", fillcolor=green]"));
+        assert!(dot.contains("fillcolor=red"));
+    }
+}
+#[cfg(all(not(feature = "no-std"), feature = "mte", target_arch = "aarch64"))]
+mod mte {
+    use super::*;
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn tagged_round_trip() {
+        if !std::arch::is_aarch64_feature_detected!("mte") {
+            eprintln!("MTE not supported, skipped");
+            return;
+        }
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let chunk = aligned_slice(&mut memory, ARENA_SIZE);
+        let range = chunk.as_ptr_range();
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<16>::new_from_refs(chunk, None)),
+            None,
+        );
+        let layout = Layout::from_size_align(256, 16).unwrap();
+        let block = alloc.allocate(layout).unwrap();
+        let untagged = block.as_mut_ptr().map_addr(|addr| addr & !(0xf << 56));
+        assert!(range.contains(&(untagged as *const u8)));
+        unsafe {
+            block.as_mut_ptr().write_bytes(0x42, 256);
+            assert_eq!(*block.as_mut_ptr().add(255), 0x42);
+        }
+        alloc.deallocate(block.as_non_null_ptr(), layout).unwrap();
+        // The tag is stripped to find the block, and a double free is still detected
+        assert!(alloc.deallocate(block.as_non_null_ptr(), layout).is_err());
+    }
+}
+#[cfg(all(not(feature = "no-std"), feature = "pmem"))]
+mod pmem {
+    use super::*;
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 4096;
+    static FLUSHED: Mutex<Vec<(usize, usize)>> = Mutex::new(Vec::new());
+    static JOURNAL: Mutex<Vec<(usize, u8, u8)>> = Mutex::new(Vec::new());
+    #[test]
+    fn flush_every_changed_byte() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let meta_ptr = meta.as_ptr() as usize;
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(&mut meta),
+        );
+        alloc.set_flush(Some(|ptr, len| {
+            FLUSHED.lock().unwrap().push((ptr as usize, len))
+        }));
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        alloc.alloc(layout).unwrap();
+        // The whole tree then the done mark, before the allocation
+        assert_eq!(
+            FLUSHED.lock().unwrap()[..2],
+            [(meta_ptr, ARENA_SIZE / MIN_CELL_LEN * 2), (meta_ptr, 1)]
+        );
+        let before = alloc.raw_meta().to_vec();
+        FLUSHED.lock().unwrap().clear();
+        alloc.alloc(layout).unwrap();
+        let after = alloc.raw_meta().to_vec();
+        let flushed = FLUSHED.lock().unwrap();
+        let changed = (0..before.len()).filter(|i| before[*i] != after[*i]);
+        let mut count = 0;
+        for index in changed {
+            assert!(flushed.contains(&(meta_ptr + index, 1)));
+            count += 1;
+        }
+        assert!(count > 0 && count <= flushed.len());
+    }
+    #[test]
+    fn replay_journal() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(&mut meta),
+        );
+        // The first allocation writes the whole tree, outside of the journal
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let first = alloc.alloc(layout).unwrap();
+        let mut replica = alloc.raw_meta().to_vec();
+        alloc.set_journal(Some(|index, old, new| {
+            JOURNAL.lock().unwrap().push((index, old, new))
+        }));
+        let second = alloc
+            .alloc(Layout::from_size_align(300, 8).unwrap())
+            .unwrap();
+        alloc.dealloc(first.as_non_null_ptr(), layout).unwrap();
+        alloc.alloc(Layout::from_size_align(8, 8).unwrap()).unwrap();
+        alloc
+            .dealloc(
+                second.as_non_null_ptr(),
+                Layout::from_size_align(300, 8).unwrap(),
+            )
+            .unwrap();
+        let after = alloc.raw_meta().to_vec();
+        alloc.set_journal(None);
+        let records = JOURNAL.lock().unwrap();
+        assert!(!records.is_empty());
+        for &(index, old, new) in records.iter() {
+            assert_eq!(replica[index], old);
+            replica[index] = new;
+        }
+        assert_eq!(replica, after);
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod config {
+    use super::*;
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 64 * 1024;
+    #[test]
+    fn same_policy_on_another_region() {
+        let mut first_memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut second_memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut first = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut first_memory, ARENA_SIZE),
+            None,
+        );
+        first.set_ring_mode(true);
+        first.set_idempotent_free(true);
+        let mut second = InnerAllocator::<MIN_CELL_LEN>::new_with_config(
+            aligned_slice(&mut second_memory, ARENA_SIZE),
+            first.config(),
+        );
+        let config = second.config();
+        assert!(config.ring_mode && config.idempotent_free);
+        // Ring mode: a freed block is not taken again before the end of the address space
+        let layout = Layout::from_size_align(1024, 8).unwrap();
+        for alloc in [&mut first, &mut second] {
+            let base = alloc.base_ptr().addr().get();
+            let a = alloc.alloc(layout).unwrap().as_non_null_ptr();
+            alloc.dealloc(a, layout).unwrap();
+            alloc.dealloc(a, layout).unwrap();
+            let b = alloc.alloc(layout).unwrap().as_non_null_ptr();
+            assert_eq!(b.addr().get() - a.addr().get(), 1024);
+            // Right after the metadata block
+            assert_eq!(a.addr().get() - base, ARENA_SIZE / MIN_CELL_LEN * 2);
+        }
+        // The error hook comes from the ProtectedAllocator
+        let protected = ProtectedAllocator::new(Mutex::new(first), Some(|_| {}));
+        let config = protected.config().unwrap();
+        assert!(config.error_hook.is_some() && config.ring_mode);
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod no_cross {
+    use super::*;
+    const ARENA_SIZE: usize = 256 * 1024;
+    const BOUNDARY: usize = 64 * 1024;
+    const SIZE: usize = 48 * 1024;
+    #[test]
+    fn dma_boundary() {
+        let mut memory = vec![0; ARENA_SIZE + 2 * BOUNDARY];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let aligned = memory.as_ptr().align_offset(BOUNDARY);
+        // Whatever is the position of the address space, the blocks of an address space
+        // have the same position into a BOUNDARY window
+        for (position, fits) in [(0, true), (16 * 1024, true), (32 * 1024, false)] {
+            let offset = aligned + position;
+            let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                &mut memory[offset..offset + ARENA_SIZE],
+                Some(&mut meta),
+            );
+            let mut count = 0;
+            while let Ok(block) = alloc.alloc_no_cross(SIZE, BOUNDARY) {
+                let start = block.as_mut_ptr().addr();
+                assert_eq!(start / BOUNDARY, (start + SIZE - 1) / BOUNDARY);
+                count += 1;
+            }
+            assert_eq!(count, if fits { ARENA_SIZE / BOUNDARY } else { 0 });
+            assert!(matches!(
+                alloc.alloc_no_cross(SIZE, BOUNDARY),
+                Err(BuddyError::NoMoreSpace)
+            ));
+            if !fits {
+                // A plain allocation crosses the boundary
+                let block = alloc
+                    .alloc(Layout::from_size_align(SIZE, 8).unwrap())
+                    .unwrap();
+                let start = block.as_mut_ptr().addr();
+                assert_ne!(start / BOUNDARY, (start + SIZE - 1) / BOUNDARY);
+            }
+        }
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            &mut memory[aligned..aligned + ARENA_SIZE],
+            Some(&mut meta),
+        );
+        assert!(matches!(
+            alloc.alloc_no_cross(2 * BOUNDARY, BOUNDARY),
+            Err(BuddyError::CannotFit)
+        ));
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod pristine {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn reset() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            None,
+        );
+        assert!(alloc.is_pristine());
+        // Metadata are written and some introspection is done, nothing is allocated
+        let largest = alloc.largest_free_block();
+        assert!(alloc.is_pristine());
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        let block = alloc.alloc(layout).unwrap();
+        assert!(!alloc.is_pristine());
+        alloc.dealloc(block.as_non_null_ptr(), layout).unwrap();
+        assert!(!alloc.is_pristine());
+        alloc.reset();
+        assert!(alloc.is_pristine());
+        assert_eq!(alloc.largest_free_block(), largest);
+        // Outstanding blocks are forgotten
+        alloc.alloc(layout).unwrap();
+        alloc.reset();
+        assert_eq!(alloc.largest_free_block(), largest);
+        assert_eq!(alloc.live_blocks().count(), 1); // The metadata block
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod sentinels {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn metadata_encoding() {
+        assert_eq!(
+            (METADATA_UNINIT, METADATA_READY, OCCUPIED_FLAG),
+            (0x42, 0xff, 0x80)
+        );
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(&mut meta),
+        );
+        assert_eq!(alloc.raw_meta()[0], METADATA_UNINIT);
+        let layout = Layout::from_size_align(ARENA_SIZE / 2, 8).unwrap();
+        alloc.alloc(layout).unwrap();
+        let meta = alloc.raw_meta();
+        assert_eq!(meta[0], METADATA_READY);
+        // The left child of the root is occupied, the right one is free at order 1
+        assert_ne!(meta[2] & OCCUPIED_FLAG, 0);
+        assert_eq!(meta[3], 1);
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod active_depth {
+    use super::*;
+    const ARENA_SIZE: usize = 64 * 1024;
+    #[test]
+    fn deepest_split() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(&mut meta),
+        );
+        assert_eq!(alloc.max_active_depth(), 0);
+        let large = Layout::from_size_align(ARENA_SIZE / 2, 8).unwrap();
+        alloc.alloc(large).unwrap();
+        // Only the root is split
+        assert_eq!(alloc.max_active_depth(), 0);
+        let small = Layout::from_size_align(MIN_CELL_LEN, 8).unwrap();
+        let block = alloc.alloc(small).unwrap();
+        // Every parent of the smallest block is split, the deepest one is just above the leaf
+        let max_order = (ARENA_SIZE / MIN_CELL_LEN).trailing_zeros() as usize;
+        assert_eq!(alloc.max_active_depth(), max_order - 1);
+        alloc.dealloc(block.as_non_null_ptr(), small).unwrap();
+        assert_eq!(alloc.max_active_depth(), 0);
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod false_sharing {
+    use super::*;
+    const SHARDS: usize = 4;
+    const ARENA_SIZE: usize = 128;
+    const META_LEN: usize = ARENA_SIZE / MIN_CELL_LEN * 2;
+    const ROUNDS: usize = 1_000_000;
+    #[test]
+    fn metadata_layout() {
+        assert_eq!(core::mem::align_of::<CacheAligned<u8>>(), METADATA_ALIGN);
+        let metas = [CacheAligned([0_u8; META_LEN]); SHARDS];
+        for pair in metas.windows(2) {
+            let distance = pair[1].as_ptr().addr() - pair[0].as_ptr().addr();
+            assert!(distance >= METADATA_ALIGN);
+        }
+    }
+    fn run(metas: Vec<&mut [u8]>) -> std::time::Duration {
+        let layout = Layout::from_size_align(MIN_CELL_LEN, 8).unwrap();
+        let start = std::time::Instant::now();
+        std::thread::scope(|scope| {
+            for meta in metas {
+                scope.spawn(move || {
+                    let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+                    let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                        aligned_slice(&mut memory, ARENA_SIZE),
+                        Some(meta),
+                    );
+                    for _ in 0..ROUNDS {
+                        let block = alloc.alloc(layout).unwrap();
+                        alloc.dealloc(block.as_non_null_ptr(), layout).unwrap();
+                    }
+                });
+            }
+        });
+        start.elapsed()
+    }
+    /// Benchmark: cargo test --release false_sharing -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn shards_throughput() {
+        // Packed: the metadata of two shards share each cache line
+        let mut packed = CacheAligned([0_u8; META_LEN * SHARDS]);
+        let packed = run(packed.chunks_mut(META_LEN).collect());
+        let mut aligned = [CacheAligned([0_u8; META_LEN]); SHARDS];
+        let aligned = run(aligned.iter_mut().map(|meta| meta.as_mut_slice()).collect());
+        let throughput =
+            |elapsed: std::time::Duration| (SHARDS * ROUNDS) as f64 / elapsed.as_secs_f64() / 1e6;
+        println!(
+            "packed: {:.1} Mops/s, cache aligned: {:.1} Mops/s",
+            throughput(packed),
+            throughput(aligned)
+        );
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod const_alloc {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    const MIN_CELL_LEN: usize = 64;
+    const fn layout(size: usize) -> Layout {
+        match Layout::from_size_align(size, 8) {
+            Ok(layout) => layout,
+            Err(_) => panic!("bad layout"),
+        }
+    }
+    const BLOCK: usize = 512;
+    static mut SPACE: StaticAddressSpace<ARENA_SIZE, MIN_CELL_LEN> = {
+        let mut space = StaticAddressSpace::new();
+        assert!(matches!(const_alloc(&mut space, layout(BLOCK)), Some(0)));
+        assert!(matches!(
+            const_alloc(&mut space, layout(BLOCK)),
+            Some(BLOCK)
+        ));
+        space
+    };
+    #[test]
+    fn compile_time_blocks() {
+        let mut alloc = InnerAllocator::new_from_static(unsafe { &mut SPACE });
+        // The compile time blocks are still allocated
+        let base = alloc.base_ptr().addr().get();
+        let block = alloc.alloc(layout(BLOCK)).unwrap();
+        assert_eq!(block.as_mut_ptr().addr() - base, 2 * BLOCK);
+        alloc.assert_no_overlaps().unwrap();
+        let first = NonNull::new((base + BLOCK) as *mut u8).unwrap();
+        alloc.dealloc(first, layout(BLOCK)).unwrap();
+        assert!(alloc.dealloc(first, layout(BLOCK)).is_err());
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod size_class_report {
+    use super::*;
+    const ARENA_SIZE: usize = 64 * 1024;
+    #[test]
+    fn live_by_order() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(&mut meta),
+        );
+        assert_eq!(alloc.size_class_report::<14>(), [(0, 0); 14]);
+        let mut blocks = Vec::new();
+        // Orders 1, 4 and 13 (the smallest cell)
+        for (size, count) in [(32 * 1024, 1), (4096, 3), (5, 6)] {
+            let layout = Layout::from_size_align(size, 1).unwrap();
+            for _ in 0..count {
+                blocks.push((alloc.alloc(layout).unwrap().as_non_null_ptr(), layout));
+            }
+        }
+        // Free one 4096 and two 8 bytes blocks
+        for i in [1, 4, 9] {
+            let (ptr, layout) = blocks[i];
+            alloc.dealloc(ptr, layout).unwrap();
+        }
+        let report = alloc.size_class_report::<14>();
+        assert_eq!(report[1], (1, 32 * 1024));
+        assert_eq!(report[4], (2, 2 * 4096));
+        assert_eq!(report[13], (4, 4 * 8));
+        assert_eq!(report.iter().map(|(count, _)| count).sum::<usize>(), 7);
+        // Deeper orders are folded into the last entry
+        assert_eq!(alloc.size_class_report::<4>()[3], (6, 2 * 4096 + 4 * 8));
+    }
+    #[test]
+    fn metadata_block() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            None,
+        );
+        // The metadata take a quarter, counted before they are written too
+        let report = alloc.size_class_report::<14>();
+        assert_eq!(report[2], (1, ARENA_SIZE / 4));
+        alloc.alloc(Layout::from_size_align(8, 1).unwrap()).unwrap();
+        assert_eq!(alloc.size_class_report::<14>()[2], report[2]);
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod resilient {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 4096;
+    const MIN_CELL_LEN: usize = 64;
+    const CHILD: &str = "RESILIENT_GLOBAL_CHILD";
+    static HOOK_CALLS: AtomicUsize = AtomicUsize::new(0);
+    /// Run into a subprocess by corrupt_free(): a bad free with the plain GlobalAlloc aborts
+    #[test]
+    fn child() {
+        if std::env::var_os(CHILD).is_none() {
+            return;
+        }
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let global = ResilientGlobal::new(ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            )),
+            Some(|e| {
+                HOOK_CALLS.fetch_add(1, Ordering::Relaxed);
+                println!("hook: {}", <BuddyError as Into<&str>>::into(e));
+            }),
+        ));
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        unsafe {
+            let ptr = global.alloc(layout);
+            assert!(!ptr.is_null());
+            global.dealloc(ptr, layout);
+            global.dealloc(ptr, layout);
+            global.dealloc(core::ptr::null_mut(), layout);
+        }
+        assert_eq!(HOOK_CALLS.load(Ordering::Relaxed), 2);
+        println!("survived");
+    }
+    #[test]
+    fn corrupt_free() {
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "tests::resilient::child", "--nocapture"])
+            .env(CHILD, "1")
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{}", stdout);
+        assert!(stdout.contains("hook: "));
+        assert!(stdout.contains("survived"));
+    }
+}
+mod wide_math {
+    use super::*;
+    #[test]
+    fn narrow_and_wide_indexes() {
+        assert!(!needs_wide_math::<MIN_CELL_LEN>(1 << 20));
+        assert!(needs_wide_math::<MIN_CELL_LEN>(usize::MAX));
+        let half_bits = usize::BITS / 2;
+        assert!(!needs_wide_math::<64>(1 << (half_bits + 2)));
+        assert!(needs_wide_math::<64>(1 << (half_bits + 4)));
+        for len in [4096_usize, 1 << 20, 1 << (half_bits + 2)] {
+            let max_order = (len / 64).trailing_zeros() as u8;
+            for order in 0..=max_order {
+                let block_len = len >> order;
+                for offset in [0, block_len, len / 2, len - block_len] {
+                    let narrow = block_index_narrow(offset, Order(order), len);
+                    assert_eq!(narrow, block_index_wide(offset, Order(order), len));
+                    assert_eq!(narrow, (1 << order) + offset / block_len);
+                }
+            }
+        }
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod validate_metadata {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    const MIN_CELL_LEN: usize = 64;
+    type Inner<'a> = InnerAllocator<'a, MIN_CELL_LEN>;
+    #[test]
+    fn tampered() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc =
+            Inner::new_from_refs(aligned_slice(&mut memory, ARENA_SIZE), Some(&mut meta));
+        // Never used: not written yet
+        assert!(Inner::validate_metadata(alloc.raw_meta(), ARENA_SIZE).is_ok());
+        alloc
+            .alloc(Layout::from_size_align(1024, 8).unwrap())
+            .unwrap();
+        alloc
+            .alloc(Layout::from_size_align(64, 8).unwrap())
+            .unwrap();
+        let valid = alloc.raw_meta().to_vec();
+        assert!(Inner::validate_metadata(&valid, ARENA_SIZE).is_ok());
+        assert!(Inner::validate_metadata(&valid, 2 * ARENA_SIZE).is_err());
+        assert!(Inner::validate_metadata(&valid[..valid.len() - 1], ARENA_SIZE).is_err());
+        let tampered = |index: usize, value: u8| {
+            let mut meta = valid.clone();
+            meta[index] = value;
+            Inner::validate_metadata(&meta, ARENA_SIZE)
+        };
+        // Sentinel
+        assert!(tampered(0, 0).is_err());
+        // Root: its smallest free order is 1 (the right half)
+        assert_eq!(valid[1], 1);
+        assert!(matches!(tampered(1, 2), Err(BuddyError::CorruptedNode(1))));
+        // A free leaf, the 1024 block, and a free node which tells a wrong order
+        let leaf = 2 * ARENA_SIZE / MIN_CELL_LEN - 1;
+        assert!(
+            matches!(tampered(leaf, 0), Err(BuddyError::CorruptedNode(index)) if index == leaf)
+        );
+        assert!(tampered(4, 0).is_err());
+        assert!(tampered(7, 3).is_err());
+        // The nodes inside the occupied 1024 block are never read
+        assert!(tampered(8, 0x33).is_ok());
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod shrink_arena {
+    use super::*;
+    const ARENA_SIZE: usize = 64 * 1024;
+    #[test]
+    fn release_the_tail() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let arena = aligned_slice(&mut memory, ARENA_SIZE);
+        let base = arena.as_mut_ptr();
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(arena, None);
+        let layout = Layout::from_size_align(4096, 8).unwrap();
+        let blocks: Vec<_> = (0..8)
+            .map(|_| alloc.alloc(layout).unwrap().as_non_null_ptr())
+            .collect();
+        // The metadata block (16 KiB) and 8 blocks: the upper half is used
+        assert!(alloc.try_shrink_arena().is_none());
+        let (lower, upper): (Vec<_>, Vec<_>) = blocks
+            .into_iter()
+            .partition(|ptr| ptr.as_ptr() < unsafe { base.add(ARENA_SIZE / 2) });
+        for ptr in upper {
+            alloc.dealloc(ptr, layout).unwrap();
+        }
+        let (tail, len) = alloc.try_shrink_arena().unwrap();
+        assert_eq!(
+            (tail, len),
+            (unsafe { base.add(ARENA_SIZE / 2) }, ARENA_SIZE / 2)
+        );
+        alloc.assert_no_overlaps().unwrap();
+        // The lower half is full, the tail is never given again
+        assert!(matches!(alloc.alloc(layout), Err(BuddyError::NoMoreSpace)));
+        assert!(matches!(
+            alloc.alloc(Layout::from_size_align(ARENA_SIZE / 2, 8).unwrap()),
+            Err(BuddyError::CannotFit)
+        ));
+        for ptr in lower.iter().skip(1) {
+            alloc.dealloc(*ptr, layout).unwrap();
+        }
+        let mut given = Vec::new();
+        while let Ok(block) = alloc.alloc(layout) {
+            assert!(block.as_mut_ptr() < tail);
+            given.push(block);
+        }
+        assert_eq!(given.len(), lower.len() - 1);
+        // Shrink again: the 16 KiB of the lower quarter are the metadata block
+        for block in given {
+            alloc.dealloc(block.as_non_null_ptr(), layout).unwrap();
+        }
+        alloc.dealloc(lower[0], layout).unwrap();
+        let (tail, len) = alloc.try_shrink_arena().unwrap();
+        assert_eq!(
+            (tail, len),
+            (unsafe { base.add(ARENA_SIZE / 4) }, ARENA_SIZE / 4)
+        );
+        assert!(alloc.try_shrink_arena().is_none());
+        // The metadata block is now the whole address space, nothing can ever fit
+        assert!(matches!(alloc.alloc(layout), Err(BuddyError::CannotFit)));
+    }
+}
+
+#[cfg(not(feature = "no-std"))]
+mod retention {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    fn churn(limits: [u8; RETAIN_ORDERS]) -> usize {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(meta.as_mut_slice()),
+        );
+        alloc.set_retain_per_order(limits);
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        for _ in 0..100 {
+            let ptr = alloc.alloc(layout).unwrap();
+            alloc.dealloc(ptr.as_non_null_ptr(), layout).unwrap();
+        }
+        // The retained pairs do not prevent the biggest allocation
+        let whole = Layout::from_size_align(ARENA_SIZE, 8).unwrap();
+        let ptr = alloc.alloc(whole).unwrap();
+        alloc.dealloc(ptr.as_non_null_ptr(), whole).unwrap();
+        alloc.split_count()
+    }
+    #[test]
+    fn churn_one_size() {
+        // 64 bytes blocks are order 6: each allocation splits 6 free blocks from the root
+        assert_eq!(churn([0; RETAIN_ORDERS]), 100 * 6);
+        let mut limits = [0; RETAIN_ORDERS];
+        limits[6] = 1;
+        // Only the first allocation splits, then the freed pair stays uncoalesced
+        assert_eq!(churn(limits), 6);
+    }
+    #[test]
+    fn coalesce_the_excess() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(meta.as_mut_slice()),
+        );
+        let mut limits = [0; RETAIN_ORDERS];
+        limits[6] = 1;
+        alloc.set_retain_per_order(limits);
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let blocks: Vec<_> = (0..4).map(|_| alloc.alloc(layout).unwrap()).collect();
+        for block in blocks {
+            alloc.dealloc(block.as_non_null_ptr(), layout).unwrap();
+        }
+        // One pair is retained, the other one is coalesced: the other half of the 256 block
+        // is free again
+        assert_eq!(alloc.largest_free_block(), ARENA_SIZE / 2);
+        let meta = alloc.raw_meta().to_vec();
+        assert!(InnerAllocator::<MIN_CELL_LEN>::validate_metadata(&meta, ARENA_SIZE).is_ok());
+        let half = Layout::from_size_align(ARENA_SIZE / 2, 8).unwrap();
+        alloc.alloc(half).unwrap();
+        assert_eq!(alloc.live_blocks().count(), 1);
+    }
+    #[test]
+    fn pending_merges() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(meta.as_mut_slice()),
+        );
+        let mut limits = [0; RETAIN_ORDERS];
+        limits[6] = 2;
+        alloc.set_retain_per_order(limits);
+        assert_eq!(alloc.uncoalesced_pairs().count(), 0);
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let blocks: Vec<_> = (0..4).map(|_| alloc.alloc(layout).unwrap()).collect();
+        for block in blocks {
+            alloc.dealloc(block.as_non_null_ptr(), layout).unwrap();
+        }
+        // The two 128 bytes parents of the four order 6 blocks (64..68)
+        assert_eq!(alloc.uncoalesced_pairs().collect::<Vec<_>>(), [32, 33]);
+        alloc.coalesce_all();
+        assert_eq!(alloc.uncoalesced_pairs().count(), 0);
+        assert_eq!(alloc.largest_free_block(), ARENA_SIZE);
+    }
+    #[test]
+    fn can_alloc_near_capacity() {
+        srand_init(11);
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(meta.as_mut_slice()),
+        );
+        alloc.set_retain_per_order([2; RETAIN_ORDERS]);
+        alloc.lazy_init().unwrap();
+        let mut v = Vec::new();
+        for _ in 0..2048 {
+            if bool::srand(true) && !v.is_empty() {
+                let (block, layout): (NonNull<[u8]>, Layout) = v.remove(usize::srand(v.len() - 1));
+                alloc.dealloc(block.as_non_null_ptr(), layout).unwrap();
+            } else {
+                let layout = Layout::from_size_align(usize::srand(1024) + 1, 8).unwrap();
+                let before = alloc.raw_meta().to_vec();
+                let can = alloc.can_alloc(layout).is_ok();
+                assert_eq!(alloc.raw_meta(), before);
+                let result = alloc.alloc(layout);
+                assert_eq!(can, result.is_ok());
+                if let Ok(block) = result {
+                    v.push((block, layout));
+                }
+            }
+        }
+    }
+    #[test]
+    fn can_alloc_before_first_use() {
+        for size in [
+            8,
+            512,
+            ARENA_SIZE / 4,
+            ARENA_SIZE / 2,
+            ARENA_SIZE,
+            2 * ARENA_SIZE,
+        ] {
+            let layout = Layout::from_size_align(size, 8).unwrap();
+            let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+            let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            );
+            // The metadata are not written by can_alloc()
+            let before = alloc.raw_meta().to_vec();
+            let can = alloc.can_alloc(layout);
+            assert_eq!(alloc.raw_meta(), before);
+            match (can, alloc.alloc(layout)) {
+                (Ok(()), Ok(_)) => {}
+                (Err(BuddyError::CannotFit), Err(BuddyError::CannotFit)) => {}
+                (can, result) => panic!("{:?} and {:?} for {}", can, result, size),
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "no-std"))]
+mod try_new {
+    use super::*;
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn invalid_address_space() {
+        let mut memory = vec![0; 2 * ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let address = aligned_slice(&mut memory, 2 * ARENA_SIZE);
+        assert!(matches!(
+            InnerAllocator::<MIN_CELL_LEN>::try_new_from_refs(&mut address[..3000], None),
+            Err(BuddyError::InvalidAddressSpace)
+        ));
+        assert!(matches!(
+            InnerAllocator::<MIN_CELL_LEN>::try_new_from_refs(&mut address[..16], None),
+            Err(BuddyError::InvalidAddressSpace)
+        ));
+        let (arena, meta) = address.split_at_mut(ARENA_SIZE);
+        assert!(matches!(
+            InnerAllocator::<MIN_CELL_LEN>::try_new_from_refs(arena, Some(&mut meta[..64])),
+            Err(BuddyError::InvalidAddressSpace)
+        ));
+    }
+    #[test]
+    fn valid_address_space() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let inner = InnerAllocator::<MIN_CELL_LEN>::try_new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            None,
+        )
+        .unwrap();
+        // The metadata are already written
+        assert!(inner.is_pristine());
+        let alloc = ProtectedAllocator::try_new(Mutex::new(inner), None).unwrap();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let block = alloc.allocate(layout).unwrap();
+        alloc.deallocate(block.as_non_null_ptr(), layout).unwrap();
+    }
+    #[test]
+    fn lazy_init_error() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; 64];
+        let mut inner = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(meta.as_mut_slice()),
+        );
+        assert!(matches!(
+            inner.lazy_init(),
+            Err(BuddyError::InvalidAddressSpace)
+        ));
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        assert!(matches!(
+            inner.alloc(layout),
+            Err(BuddyError::InvalidAddressSpace)
+        ));
+        // Without the feature, the paths which cannot return an error panic
+        #[cfg(feature = "no-panic")]
+        let _ = inner.largest_free_block();
+    }
+    /// Walk every path which cannot return an error, the allocator has no valid metadata
+    #[cfg(feature = "no-panic")]
+    fn walk_every_path(mut inner: InnerAllocator<MIN_CELL_LEN>) {
+        let ptr = NonNull::dangling();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        assert!(matches!(
+            inner.alloc(layout),
+            Err(BuddyError::InvalidAddressSpace)
+        ));
+        assert_eq!(inner.largest_free_block(), 0);
+        assert_eq!(inner.largest_free_aligned(8), 0);
+        assert!(inner.free_histogram().iter().all(|count| *count == 0));
+        assert_eq!(inner.live_blocks().count(), 0);
+        assert_eq!(inner.leaked_locations().count(), 0);
+        assert_eq!(inner.uncoalesced_pairs().count(), 0);
+        assert_eq!(inner.internal_waste(), 0);
+        assert_eq!(inner.total_used(), 0);
+        assert_eq!(inner.max_active_depth(), 0);
+        assert_eq!(inner.size_class_report::<8>(), [(0, 0); 8]);
+        assert_eq!(inner.snapshot_stats().live_allocations, 0);
+        assert!(inner.order_at(ptr).is_err());
+        assert!(inner.layout_of(ptr).is_err());
+        assert!(inner.touch(ptr).is_err());
+        assert!(inner.suggest_victim().is_none());
+        assert!(inner.check_integrity().is_err());
+        assert!(inner.try_shrink_arena().is_none());
+        inner.base_ptr();
+        assert!(matches!(
+            inner.alloc_hugepage(4096),
+            Err(BuddyError::InvalidAddressSpace)
+        ));
+        inner.to_dot(&mut String::new()).unwrap();
+        inner.coalesce_all();
+        inner.prefault();
+        inner.reset();
+        assert!(inner.lazy_init().is_err());
+        let frozen = inner.freeze();
+        assert_eq!(frozen.live_blocks().count(), 0);
+        assert!(frozen.block(ptr).is_err());
+        frozen.to_dot(&mut String::new()).unwrap();
+    }
+    #[cfg(feature = "no-panic")]
+    #[test]
+    fn no_panicking_path() {
+        let mut memory = vec![0; 2 * ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let address = aligned_slice(&mut memory, 2 * ARENA_SIZE);
+        // Not a power of two, then too small
+        walk_every_path(InnerAllocator::new_from_refs(&mut address[..3000], None));
+        walk_every_path(InnerAllocator::new_from_refs(&mut address[..16], None));
+        // Too short, then empty metadata buffer
+        let (arena, meta) = address.split_at_mut(ARENA_SIZE);
+        walk_every_path(InnerAllocator::new_from_refs(arena, Some(&mut meta[..64])));
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        walk_every_path(InnerAllocator::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(&mut []),
+        ));
+    }
+}
+
+#[cfg(not(feature = "no-std"))]
+mod swap_arena {
+    use super::*;
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn double_buffering() {
+        let mut memory_a = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut memory_b = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let arena_b = aligned_slice(&mut memory_b, ARENA_SIZE);
+        let range_b = arena_b.as_ptr_range();
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory_a, ARENA_SIZE),
+                None,
+            )),
+            None,
+        );
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        let old_block = alloc.allocate(layout).unwrap();
+        let mut old = alloc
+            .swap_arena(InnerAllocator::new_from_refs(arena_b, None))
+            .map_err(|(e, _)| e)
+            .unwrap();
+        let block = alloc.allocate(layout).unwrap();
+        assert!(range_b.contains(&(block.as_mut_ptr() as *const u8)));
+        // The old arena still holds the metadata block and 'old_block'
+        assert!(old.owns(old_block.as_non_null_ptr()));
+        assert_eq!(old.live_blocks().count(), 2);
+        old.dealloc(old_block.as_non_null_ptr(), layout).unwrap();
+        assert_eq!(alloc.snapshot_stats().unwrap().live_allocations, 2);
+    }
+}
+
+#[cfg(not(feature = "no-std"))]
+mod sub_arena {
+    use super::*;
+    const ARENA_SIZE: usize = 256 * 1024;
+    const SUB_ARENA_SIZE: usize = 64 * 1024;
+    #[test]
+    fn carve_and_release() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut parent = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(meta.as_mut_slice()),
+        );
+        let layout = Layout::from_size_align(1024, 8).unwrap();
+        let sub = parent.carve_sub_arena(SUB_ARENA_SIZE - 100).unwrap();
+        assert_eq!(sub.len(), SUB_ARENA_SIZE);
+        assert_eq!(sub.as_mut_ptr().align_offset(MAX_SUPPORTED_ALIGN), 0);
+        let base = sub.as_non_null_ptr();
+        // SAFETY: The parent does not touch the block until it is released
+        let sub = unsafe { &mut *sub.as_ptr() };
+        let range = sub.as_ptr_range();
+        let mut child = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(sub, None);
+        // The parent and the child are used side by side
+        let outside = parent.alloc(layout).unwrap();
+        assert!(!range.contains(&(outside.as_mut_ptr() as *const u8)));
+        for _ in 0..8 {
+            let block = child.alloc(layout).unwrap();
+            assert!(range.contains(&(block.as_mut_ptr() as *const u8)));
+        }
+        parent.dealloc(outside.as_non_null_ptr(), layout).unwrap();
+        drop(child);
+        unsafe { parent.release_sub_arena(base) }.unwrap();
+        // The sub arena is back, the parent is empty again
+        assert_eq!(parent.largest_free_block(), ARENA_SIZE);
+        assert_eq!(parent.live_blocks().count(), 0);
+    }
+}
+
+#[cfg(not(feature = "no-std"))]
+mod metadata_overlap {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn fill_around_the_metadata() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            None,
+        );
+        let base = alloc.base_ptr().as_ptr() as usize;
+        let metadata_len = alloc.raw_meta().len();
+        assert_eq!(metadata_len, ARENA_SIZE / MIN_CELL_LEN * 2);
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let mut count = 0;
+        while let Ok(block) = alloc.alloc(layout) {
+            let offset = block.as_mut_ptr() as usize - base;
+            assert!(offset >= metadata_len && offset + block.len() <= ARENA_SIZE);
+            count += 1;
+        }
+        assert_eq!(count, (ARENA_SIZE - metadata_len) / 64);
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod allocate_retry {
+    use super::*;
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn succeed_after_concurrent_free() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        );
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        let mut blocks = Vec::new();
+        while let Ok(block) = alloc.allocate(layout) {
+            blocks.push(block.as_mut_ptr() as usize);
+        }
+        assert!(matches!(
+            alloc.allocate_retry(layout, 16),
+            Err(BuddyError::NoMoreSpace)
+        ));
+        let freed = blocks.pop().unwrap();
+        let block = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                let ptr = NonNull::new(freed as *mut u8).unwrap();
+                alloc.deallocate(ptr, layout).unwrap();
+            });
+            alloc.allocate_retry(layout, u32::MAX).unwrap()
+        });
+        assert_eq!(block.as_mut_ptr() as usize, freed);
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod prefault {
+    use super::*;
+    const ARENA_SIZE: usize = 8 * 1024 * 1024;
+    const BLOCK_LEN: usize = 4096;
+    /// Time to allocate and write every block of a fresh arena
+    fn first_touch(prefault: bool) -> std::time::Duration {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            None,
+        );
+        if prefault {
+            alloc.prefault();
+        }
+        let layout = Layout::from_size_align(BLOCK_LEN, 8).unwrap();
+        let start = std::time::Instant::now();
+        while let Ok(block) = alloc.alloc(layout) {
+            unsafe { block.as_mut_ptr().write_bytes(0x42, BLOCK_LEN) };
+        }
+        start.elapsed()
+    }
+    #[test]
+    fn occupied_blocks_are_preserved() {
+        let mut memory = vec![0; 64 * 1024 + MAX_SUPPORTED_ALIGN];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, 64 * 1024),
+            None,
+        );
+        let layout = Layout::from_size_align(BLOCK_LEN, 8).unwrap();
+        let block = alloc.alloc(layout).unwrap();
+        unsafe { block.as_mut_ptr().write_bytes(0x42, BLOCK_LEN) };
+        alloc.prefault();
+        let content = unsafe { std::slice::from_raw_parts(block.as_mut_ptr(), BLOCK_LEN) };
+        assert!(content.iter().all(|byte| *byte == 0x42));
+        assert_eq!(alloc.live_blocks().count(), 2);
+        alloc.dealloc(block.as_non_null_ptr(), layout).unwrap();
+        assert_eq!(alloc.largest_free_block(), 32 * 1024);
+    }
+    /// Benchmark: cargo test --release prefault -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn first_touch_latency() {
+        let cold = first_touch(false);
+        let warm = first_touch(true);
+        println!("cold arena: {:?}, prefaulted arena: {:?}", cold, warm);
+        // Best effort: the page faults were taken by prefault()
+        assert!(warm <= cold);
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod grow_shrink {
+    use super::*;
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 64 * 1024;
+    #[test]
+    fn vec_growth() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        );
+        // 24 bytes then 32 bytes: the buddy size does not change
+        let mut v: Vec<u64, _> = Vec::with_capacity_in(3, &alloc);
+        v.extend([0, 1, 2]);
+        let ptr = v.as_ptr();
+        v.reserve_exact(1);
+        assert_eq!(v.as_ptr(), ptr);
+        for i in 3..2048 {
+            v.push(i);
+        }
+        assert!(v.iter().enumerate().all(|(i, x)| *x == i as u64));
+        v.truncate(100);
+        v.shrink_to_fit();
+        assert!(v.iter().enumerate().all(|(i, x)| *x == i as u64));
+        drop(v);
+        assert_eq!(alloc.snapshot_stats().unwrap().live_allocations, 1);
+    }
+    #[test]
+    fn in_place_or_moved() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            None,
+        );
+        let small = Layout::from_size_align(64, 8).unwrap();
+        let big = Layout::from_size_align(256, 8).unwrap();
+        let block = alloc.alloc(small).unwrap();
+        unsafe { block.as_mut_ptr().write_bytes(0x42, 64) };
+        // The buddies on the way are free: grow in place, only the new bytes are zeroed
+        let grown = alloc
+            .grow(block.as_non_null_ptr(), small, big, true)
+            .unwrap();
+        assert_eq!(grown.as_mut_ptr(), block.as_mut_ptr());
+        assert_eq!(grown.len(), 256);
+        let content = unsafe { std::slice::from_raw_parts(grown.as_mut_ptr(), 256) };
+        assert!(content[..64].iter().all(|byte| *byte == 0x42));
+        assert!(content[64..].iter().all(|byte| *byte == 0));
+        // Shrink in place, then the freed buddy is taken: growing again moves the block
+        let shrinked = alloc.shrink(grown.as_non_null_ptr(), big, small).unwrap();
+        assert_eq!(shrinked.as_mut_ptr(), block.as_mut_ptr());
+        let neighbour = alloc.alloc(small).unwrap();
+        assert_eq!(
+            neighbour.as_mut_ptr() as usize,
+            block.as_mut_ptr() as usize + 64
+        );
+        let moved = alloc
+            .grow(shrinked.as_non_null_ptr(), small, big, false)
+            .unwrap();
+        assert_ne!(moved.as_mut_ptr(), block.as_mut_ptr());
+        let content = unsafe { std::slice::from_raw_parts(moved.as_mut_ptr(), 64) };
+        assert!(content.iter().all(|byte| *byte == 0x42));
+        alloc.dealloc(moved.as_non_null_ptr(), big).unwrap();
+        alloc.dealloc(neighbour.as_non_null_ptr(), small).unwrap();
+        // Metadata only
+        assert_eq!(alloc.live_blocks().count(), 1);
+        assert_eq!(alloc.largest_free_block(), ARENA_SIZE / 2);
+    }
+    #[test]
+    fn grow_zeroed_exact_range() {
+        let mut memory = vec![0xaa; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(meta.as_mut_slice()),
+        );
+        let old = Layout::from_size_align(40, 8).unwrap();
+        let new = Layout::from_size_align(200, 8).unwrap();
+        let check = |block: NonNull<[u8]>| {
+            let content = unsafe { std::slice::from_raw_parts(block.as_mut_ptr(), 256) };
+            assert!(content[..40].iter().all(|byte| *byte == 0x42));
+            assert!(content[40..200].iter().all(|byte| *byte == 0));
+            // Past the new size, the bytes are not touched (but new blocks are poisoned)
+            #[cfg(not(feature = "poison"))]
+            assert!(content[200..].iter().all(|byte| *byte == 0xaa));
+        };
+        // In place: the stale bytes of the old block after 40 are cleared too
+        let block = alloc.alloc(old).unwrap();
+        unsafe { block.as_mut_ptr().write_bytes(0x42, 40) };
+        let grown = alloc.grow(block.as_non_null_ptr(), old, new, true).unwrap();
+        assert_eq!(grown.as_mut_ptr(), block.as_mut_ptr());
+        check(grown);
+        // Moved: the neighbour is taken
+        let block = alloc.alloc(old).unwrap();
+        let _neighbour = alloc.alloc(old).unwrap();
+        unsafe { block.as_mut_ptr().write_bytes(0x42, 40) };
+        let moved = alloc.grow(block.as_non_null_ptr(), old, new, true).unwrap();
+        assert_ne!(moved.as_mut_ptr(), block.as_mut_ptr());
+        check(moved);
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod order_at {
+    use super::*;
+    const ARENA_SIZE: usize = 64 * 1024;
+    #[test]
+    fn interior_pointers() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(meta.as_mut_slice()),
+        );
+        let blocks: Vec<(NonNull<[u8]>, Layout)> = [8, 100, 1000, 4000, 20000]
+            .iter()
+            .map(|size| {
+                let layout = Layout::from_size_align(*size, 8).unwrap();
+                (alloc.alloc(layout).unwrap(), layout)
+            })
+            .collect();
+        for (block, _) in blocks.iter() {
+            let interior = unsafe { block.as_mut_ptr().add(block.len() - 1) };
+            let order = alloc.order_at(NonNull::new(interior).unwrap()).unwrap();
+            assert_eq!(ARENA_SIZE >> order.0, block.len());
+        }
+        let (block, layout) = blocks[2];
+        alloc.dealloc(block.as_non_null_ptr(), layout).unwrap();
+        assert!(matches!(
+            alloc.order_at(block.as_non_null_ptr()),
+            Err(BuddyError::DoubleFreeOrCorruption)
+        ));
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod last_error {
+    use super::*;
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn behind_try_reserve() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        );
+        assert!(alloc.last_error().is_none());
+        let mut v: Vec<u8, _> = Vec::new_in(&alloc);
+        assert!(v.try_reserve(isize::MAX as usize).is_err());
+        assert!(matches!(alloc.last_error(), Some(BuddyError::TooBigSize)));
+        assert!(v.try_reserve(ARENA_SIZE * 4).is_err());
+        assert!(matches!(alloc.last_error(), Some(BuddyError::CannotFit)));
+        // The metadata are in the left half, and the right half is taken
+        let _block = alloc
+            .allocate(Layout::from_size_align(ARENA_SIZE / 2, 8).unwrap())
+            .unwrap();
+        assert!(v.try_reserve(ARENA_SIZE / 2).is_err());
+        assert!(matches!(alloc.last_error(), Some(BuddyError::NoMoreSpace)));
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod total_used {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    const ARENA_SIZE: usize = 64 * 1024;
+    #[test]
+    fn rounded_up_sizes() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = ThreadSafeAllocator::new(Arc::new(ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        )));
+        let metadata = ARENA_SIZE / MIN_CELL_LEN * 2;
+        assert_eq!(alloc.total_used().unwrap(), metadata);
+        let mut blocks = Vec::new();
+        for size in [1, 100, 1000, 3000] {
+            let layout = Layout::from_size_align(size, 8).unwrap();
+            blocks.push((alloc.allocate(layout).unwrap(), layout));
+        }
+        assert_eq!(
+            alloc.total_used().unwrap(),
+            metadata + 8 + 128 + 1024 + 4096
+        );
+        assert_eq!(
+            alloc.total_free().unwrap(),
+            ARENA_SIZE - alloc.total_used().unwrap()
+        );
+        for (block, layout) in blocks {
+            alloc.deallocate(block.as_non_null_ptr(), layout).unwrap();
+        }
+        assert_eq!(alloc.total_free().unwrap(), ARENA_SIZE - metadata);
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod largest_free_block {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    const ARENA_SIZE: usize = 64 * 1024;
+    /// Biggest aligned block which does not meet any live block
+    fn scan(live: &[(usize, usize)]) -> usize {
+        let mut len = ARENA_SIZE;
+        while len >= MIN_CELL_LEN {
+            let free = (0..ARENA_SIZE / len).any(|i| {
+                let (start, end) = (i * len, (i + 1) * len);
+                live.iter()
+                    .all(|(offset, size)| offset + size <= start || *offset >= end)
+            });
+            if free {
+                return len;
+            }
+            len /= 2;
+        }
+        0
+    }
+    #[test]
+    fn fragmented_arena() {
+        srand_init(7);
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = ThreadSafeAllocator::new(Arc::new(ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        )));
+        let mut v = Vec::new();
+        for _ in 0..512 {
+            let layout = Layout::from_size_align(usize::srand(2048) + 1, 8).unwrap();
+            if let Ok(block) = alloc.allocate(layout) {
+                v.push((block, layout));
+            }
+            if v.len() > 1 && bool::srand(true) {
+                let (block, layout) = v.remove(usize::srand(v.len() - 1));
+                alloc.deallocate(block.as_non_null_ptr(), layout).unwrap();
+            }
+            let live: Vec<(usize, usize)> = alloc
+                .with_locked(|inner| inner.live_blocks().collect())
+                .unwrap();
+            assert_eq!(alloc.largest_free_block().unwrap(), scan(&live));
+        }
+        let layout = Layout::from_size_align(MIN_CELL_LEN, 8).unwrap();
+        while alloc.allocate(layout).is_ok() {}
+        assert_eq!(alloc.largest_free_block().unwrap(), 0);
+    }
+    #[test]
+    fn aligned() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(meta.as_mut_slice()),
+        );
+        // Only 1024 bytes blocks are left
+        let layout = Layout::from_size_align(1024, 8).unwrap();
+        for _ in 0..ARENA_SIZE / 1024 - 1 {
+            alloc.alloc(layout).unwrap();
+        }
+        let largest = alloc.largest_free_block();
+        assert_eq!(largest, 1024);
+        assert_eq!(alloc.largest_free_aligned(8), largest);
+        assert_eq!(alloc.largest_free_aligned(1024), largest);
+        assert_eq!(alloc.largest_free_aligned(2048), 0);
+        assert!(alloc
+            .can_alloc(Layout::from_size_align(8, 2048).unwrap())
+            .is_err());
+        assert_eq!(alloc.largest_free_aligned(MAX_SUPPORTED_ALIGN * 2), 0);
+        assert_eq!(alloc.largest_free_aligned(24), 0);
+    }
+}
+#[cfg(all(feature = "relax-overalignment", not(feature = "no-std")))]
+mod relax_overalignment {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn tiny_overaligned_request() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(meta.as_mut_slice()),
+        );
+        let layout = Layout::from_size_align(8, 64).unwrap();
+        let block = alloc.alloc(layout).unwrap();
+        assert_eq!(block.len(), 64);
+        alloc.dealloc(block.as_non_null_ptr(), layout).unwrap();
+        alloc.set_max_useful_align(Some(16));
+        let block = alloc.alloc(layout).unwrap();
+        assert_eq!(block.len(), 16);
+        assert_eq!(block.as_mut_ptr() as usize % 16, 0);
+        alloc.dealloc(block.as_non_null_ptr(), layout).unwrap();
+        // The size is bigger than the alignment: nothing is relaxed
+        let layout = Layout::from_size_align(128, 64).unwrap();
+        assert_eq!(alloc.alloc(layout).unwrap().len(), 128);
+        assert_eq!(alloc.config().max_useful_align, Some(16));
+    }
+}
+#[cfg(all(feature = "spin", not(feature = "no-std")))]
+mod spin_mutex {
+    use super::*;
+    use core::cell::Cell;
+    #[test]
+    fn mutual_exclusion() {
+        // A non atomic counter and an 'inside' flag: a race loses increments or sees the flag
+        let mutex = SpinMutex::new((0_usize, Cell::new(false)));
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..10_000 {
+                        mutex
+                            .lock_mut(|(counter, inside)| {
+                                assert!(!inside.replace(true));
+                                *counter += 1;
+                                inside.set(false);
+                            })
+                            .unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(mutex.lock_mut(|(counter, _)| *counter).unwrap(), 40_000);
+    }
+    #[test]
+    fn protected_allocator() {
+        const ARENA_SIZE: usize = 4096;
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = ProtectedAllocator::new(
+            SpinMutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        );
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let block = alloc.allocate(layout).unwrap();
+        alloc.deallocate(block.as_non_null_ptr(), layout).unwrap();
+    }
+}
+#[cfg(all(feature = "single-threaded", not(feature = "no-std")))]
+mod ref_cell_mutex {
+    use super::*;
+    use core::cell::RefCell;
+    #[test]
+    fn reentrant_lock() {
+        let cell = RefCell::new(0_usize);
+        let inner = cell.lock_mut(|_| cell.lock_mut(|_| ())).unwrap();
+        assert_eq!(inner, Err(RefCellError::Reentrant));
+        // The borrow is released
+        assert_eq!(cell.lock_mut(|v| *v).unwrap(), 0);
+    }
+    #[test]
+    fn protected_allocator() {
+        const ARENA_SIZE: usize = 4096;
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = ProtectedAllocator::new(
+            RefCell::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        );
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let block = alloc.allocate(layout).unwrap();
+        alloc.deallocate(block.as_non_null_ptr(), layout).unwrap();
+        // An allocation from inside the lock is not taken for a poisoned lock
+        let inner = alloc.with_locked(|_| alloc.allocate(layout)).unwrap();
+        assert!(matches!(inner, Err(BuddyError::Reentrant)));
+        // So are the other entry points, none of them panics
+        let inner = alloc
+            .with_locked(|_| {
+                [
+                    alloc.allocate_tracked(layout).map(|_| ()),
+                    alloc.allocate_avoid_splitting(layout).map(|_| ()),
+                    alloc.can_allocate(layout),
+                    alloc.allocate_no_cross(64, 4096).map(|_| ()),
+                    alloc.allocate_with(layout, |_| ()).map(|_| ()),
+                    alloc
+                        .allocate_adjacent(block.as_non_null_ptr(), layout)
+                        .map(|_| ()),
+                    alloc.layout_of(block.as_non_null_ptr()).map(|_| ()),
+                ]
+            })
+            .unwrap();
+        assert!(inner
+            .iter()
+            .all(|e| matches!(e, Err(BuddyError::Reentrant))));
+    }
+}
+#[cfg(all(feature = "paranoid", not(feature = "no-std")))]
+mod paranoid {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn corrupted_parent() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(meta.as_mut_slice()),
+        );
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let a = alloc.alloc(layout).unwrap();
+        let b = alloc.alloc(layout).unwrap();
+        // 'a' and 'b' are the order 6 nodes 64 and 65: their parent says it is entirely free
+        alloc.raw_meta()[32] = 5;
+        let before = alloc.raw_meta().to_vec();
+        assert!(matches!(
+            alloc.dealloc(a.as_non_null_ptr(), layout),
+            Err(BuddyError::DoubleFreeOrCorruption)
+        ));
+        assert_eq!(alloc.raw_meta(), before);
+        // Once repaired, the free goes on
+        alloc.raw_meta()[32] = 10;
+        alloc.dealloc(a.as_non_null_ptr(), layout).unwrap();
+        alloc.dealloc(b.as_non_null_ptr(), layout).unwrap();
+        assert_eq!(alloc.largest_free_block(), ARENA_SIZE);
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod lock_poisoned {
+    use super::*;
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn poisoned_mutex() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        );
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let bigger = Layout::from_size_align(128, 8).unwrap();
+        let block = alloc.allocate(layout).unwrap().as_non_null_ptr();
+        // A thread panics while holding the lock
+        std::thread::scope(|s| {
+            assert!(s
+                .spawn(|| alloc.with_locked(|_| panic!("poison the mutex")))
+                .join()
+                .is_err());
+        });
+        assert!(matches!(
+            alloc.allocate(layout),
+            Err(BuddyError::LockPoisoned)
+        ));
+        assert!(matches!(
+            alloc.deallocate(block, layout),
+            Err(BuddyError::LockPoisoned)
+        ));
+        assert!(matches!(
+            alloc.grow(block, layout, bigger, false),
+            Err(BuddyError::LockPoisoned)
+        ));
+        assert!(matches!(
+            alloc.shrink(block, bigger, layout),
+            Err(BuddyError::LockPoisoned)
+        ));
+        assert!(matches!(
+            alloc.reserve(32, 64),
+            Err(BuddyError::LockPoisoned)
+        ));
+        assert!(Allocator::allocate(&alloc, layout).is_err());
+        // The queries do not panic either
+        assert!(matches!(alloc.last_error(), Some(BuddyError::LockPoisoned)));
+        assert!(alloc.order_at(block).is_err());
+        assert!(alloc.prefault().is_err());
+        assert!(alloc.total_used().is_err());
+        assert!(alloc.total_free().is_err());
+        assert!(alloc.largest_free_block().is_err());
+        assert!(alloc.largest_free_aligned(64).is_err());
+        assert!(alloc.free_histogram().is_err());
+        assert!(alloc.internal_waste().is_err());
+        assert!(alloc.leaked_locations(|_, _, _| ()).is_err());
+        assert!(alloc.metadata_checksum().is_err());
+        assert!(!alloc.verify_checksum(0));
+        assert!(alloc.snapshot_stats().is_err());
+        assert!(alloc.write_prometheus(&mut String::new(), "buddy").is_err());
+        assert!(alloc.to_dot().is_err());
+        assert!(alloc.config().is_err());
+        #[cfg(feature = "stats")]
+        assert!(alloc.peak_used().is_err() && alloc.reset_peak().is_err());
+        // The replacement address space is given back
+        let mut other = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let replacement =
+            InnerAllocator::new_from_refs(aligned_slice(&mut other, ARENA_SIZE), None);
+        match alloc.swap_arena(replacement) {
+            Err((BuddyError::LockPoisoned, mut given_back)) => {
+                assert!(given_back.alloc(layout).is_ok())
+            }
+            _ => panic!("The lock is poisoned"),
+        }
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod lru {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn least_recently_touched() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut table = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            None,
+        );
+        assert_eq!(alloc.suggest_victim(), None);
+        alloc.set_lru_table(Some(table.as_mut_slice()));
+        let small = Layout::from_size_align(64, 8).unwrap();
+        let big = Layout::from_size_align(256, 8).unwrap();
+        let a = alloc.alloc(small).unwrap().as_non_null_ptr();
+        let b = alloc.alloc(big).unwrap().as_non_null_ptr();
+        let c = alloc.alloc(small).unwrap().as_non_null_ptr();
+        // Without any touch, the oldest allocation goes first
+        assert_eq!(
+            alloc.suggest_victim(),
+            Some((a, alloc.order_at(a).unwrap()))
+        );
+        alloc.touch(a).unwrap();
+        alloc.touch(c).unwrap();
+        assert_eq!(
+            alloc.suggest_victim(),
+            Some((b, alloc.order_at(b).unwrap()))
+        );
+        // A pointer inside the block touches it too
+        alloc
+            .touch(NonNull::new(b.as_ptr().wrapping_add(100)).unwrap())
+            .unwrap();
+        assert_eq!(
+            alloc.suggest_victim(),
+            Some((a, alloc.order_at(a).unwrap()))
+        );
+        alloc.dealloc(a, small).unwrap();
+        assert!(matches!(
+            alloc.touch(a),
+            Err(BuddyError::DoubleFreeOrCorruption)
+        ));
+        assert_eq!(
+            alloc.suggest_victim(),
+            Some((c, alloc.order_at(c).unwrap()))
+        );
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod display_error {
+    use super::*;
+    #[test]
+    fn messages() {
+        use BuddyError::*;
+        let messages = [
+            (
+                CannotFit,
+                "the bigger buddy is too small for the requested size",
+            ),
+            (TooBigAlignment, "Alignement too big"),
+            (TooBigSize, "Bad size"),
+            (DoubleFreeOrCorruption, "Double Free or corruption"),
+            (
+                NoMoreSpace,
+                "Not enough room to swing a cat, a cat, the animal !",
+            ),
+            (MisalignedMetadata, "Metadata are not aligned"),
+            (InvalidAddressSpace, "Invalid address space"),
+            (LockPoisoned, "Lock poisoned"),
+            (Reentrant, "Reentrant lock"),
+        ];
+        for (error, message) in messages {
+            assert_eq!(error.to_string(), message);
+            assert_eq!(<BuddyError as Into<&str>>::into(error), message);
+        }
+        // The node index is displayed, but cannot be in a static message
+        assert_eq!(CorruptedNode(5).to_string(), "Corrupted metadata node 5");
+        let message: &str = CorruptedNode(5).into();
+        assert_eq!(message, "Corrupted metadata node");
+        // Usable as a boxed error
+        let boxed: Box<dyn std::error::Error> = Box::new(NoMoreSpace);
+        assert_eq!(
+            boxed.to_string(),
+            "Not enough room to swing a cat, a cat, the animal !"
+        );
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod ensure_contiguous {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn defragment_for_a_big_block() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(meta.as_mut_slice()),
+        );
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let mut blocks: Vec<_> = (0..ARENA_SIZE / 64)
+            .map(|_| alloc.alloc(layout).unwrap().as_non_null_ptr())
+            .collect();
+        // Free one block out of two, every byte of the others tells its rank
+        for (i, block) in blocks.iter().enumerate() {
+            if i % 2 == 0 {
+                alloc.dealloc(*block, layout).unwrap();
+            } else {
+                unsafe { block.as_ptr().write_bytes(i as u8, 64) };
+            }
+        }
+        blocks = blocks.into_iter().skip(1).step_by(2).collect();
+        assert_eq!(alloc.largest_free_block(), 64);
+        let big = Layout::from_size_align(1024, 8).unwrap();
+        assert!(alloc.can_alloc(big).is_err());
+        let mut vacated = Vec::new();
+        alloc
+            .ensure_contiguous(1024, |old, new, moved| {
+                assert_eq!(moved.size(), 64);
+                let block = blocks.iter_mut().find(|block| **block == old).unwrap();
+                *block = new;
+                vacated.push(old);
+            })
+            .unwrap();
+        // Only the live blocks of one 1024 bytes region move
+        assert_eq!(vacated.len(), 1024 / 64 / 2);
+        // The old copies are poisoned like any freed block
+        #[cfg(feature = "poison")]
+        for old in vacated.iter() {
+            let content = unsafe { core::slice::from_raw_parts(old.as_ptr(), 64) };
+            assert!(content.iter().all(|byte| *byte == POISON_FREE));
+        }
+        for (i, block) in blocks.iter().enumerate() {
+            let rank = (2 * i + 1) as u8;
+            let content = unsafe { core::slice::from_raw_parts(block.as_ptr(), 64) };
+            assert!(content.iter().all(|byte| *byte == rank));
+        }
+        let region = alloc.alloc(big).unwrap();
+        assert_eq!(region.len(), 1024);
+        alloc.assert_no_overlaps().unwrap();
+        // Already contiguous: nothing moves
+        alloc.dealloc(region.as_non_null_ptr(), big).unwrap();
+        alloc
+            .ensure_contiguous(1024, |_, _, _| panic!("nothing to move"))
+            .unwrap();
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod allocate_zeroed {
+    use super::*;
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn zeroed_after_reuse() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        );
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let block = alloc.allocate(layout).unwrap();
+        unsafe { block.as_mut_ptr().write_bytes(0xaa, block.len()) };
+        alloc.deallocate(block.as_non_null_ptr(), layout).unwrap();
+        // The same block is given again, cleared up to its end
+        let zeroed = alloc.allocate_zeroed(layout).unwrap();
+        assert_eq!(zeroed.as_mut_ptr(), block.as_mut_ptr());
+        assert!(unsafe { zeroed.as_ref() }.iter().all(|byte| *byte == 0));
+        alloc.deallocate(zeroed.as_non_null_ptr(), layout).unwrap();
+        // Through the Allocator trait too
+        unsafe { block.as_mut_ptr().write_bytes(0xaa, block.len()) };
+        let zeroed = Allocator::allocate_zeroed(&alloc, layout).unwrap();
+        assert!(unsafe { zeroed.as_ref() }.iter().all(|byte| *byte == 0));
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod zero_on_free {
+    use super::*;
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn wiped_block() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        )
+        .with_zero_on_free(true);
+        assert!(alloc.config().unwrap().zero_on_free);
+        // 100 bytes are requested, the block holds 128
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let block = alloc.allocate(layout).unwrap();
+        assert_eq!(block.len(), 128);
+        unsafe { block.as_mut_ptr().write_bytes(0x5a, block.len()) };
+        alloc.deallocate(block.as_non_null_ptr(), layout).unwrap();
+        // Read before the block is given again, new blocks are poisoned with the poison feature
+        let freed = unsafe { core::slice::from_raw_parts(block.as_mut_ptr(), block.len()) };
+        assert!(freed.iter().all(|byte| *byte == 0));
+        let again = alloc.allocate(layout).unwrap();
+        assert_eq!(again.as_mut_ptr(), block.as_mut_ptr());
+        // A double free is refused before any write
+        alloc.deallocate(again.as_non_null_ptr(), layout).unwrap();
+        let other = Layout::from_size_align(256, 8).unwrap();
+        let bigger = alloc.allocate(other).unwrap();
+        unsafe { bigger.as_mut_ptr().write_bytes(0x5a, bigger.len()) };
+        assert!(alloc.deallocate(again.as_non_null_ptr(), layout).is_err());
+        assert!(unsafe { bigger.as_ref() }.iter().all(|byte| *byte == 0x5a));
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod thread_cache {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    const ARENA_SIZE: usize = 1 << 20;
+    /// Mutex which counts its locks
+    struct CountingMutex<T> {
+        mutex: Mutex<T>,
+        locks: AtomicUsize,
+    }
+    impl<T> RwMutex<T> for CountingMutex<T> {
+        type Error = ();
+        fn lock_mut<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, Self::Error> {
+            self.locks.fetch_add(1, Ordering::Relaxed);
+            self.mutex.lock_mut(f)
+        }
+    }
+    type Counted = ProtectedAllocator<
+        'static,
+        CountingMutex<InnerAllocator<'static, MIN_CELL_LEN>>,
+        MIN_CELL_LEN,
+    >;
+    fn counted() -> Arc<Counted> {
+        let memory = Box::leak(Box::new(vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN]));
+        Arc::new(ProtectedAllocator::new(
+            CountingMutex {
+                mutex: Mutex::new(InnerAllocator::new_from_refs(
+                    aligned_slice(memory, ARENA_SIZE),
+                    None,
+                )),
+                locks: AtomicUsize::new(0),
+            },
+            None,
+        ))
+    }
+    /// Allocate blocks of several classes, each filled with its own value, check and free them
+    /// Threads are joined, not scoped: their caches are given back before the join returns
+    fn workload<A: Allocator + Send + Sync + 'static>(alloc: Arc<A>) {
+        let thread_list: Vec<_> = (0..4_u8)
+            .map(|thread| {
+                let alloc = alloc.clone();
+                std::thread::spawn(move || {
+                    for round in 0..200_u8 {
+                        let blocks: Vec<_> = (0..32_u8)
+                            .map(|i| {
+                                let layout = Layout::from_size_align(8 << (i % 6), 8).unwrap();
+                                let block = alloc.allocate(layout).unwrap();
+                                let value = thread ^ round ^ i;
+                                unsafe { block.as_mut_ptr().write_bytes(value, layout.size()) };
+                                (block, layout, value)
+                            })
+                            .collect();
+                        for (block, layout, value) in blocks {
+                            let content = unsafe {
+                                core::slice::from_raw_parts(block.as_mut_ptr(), layout.size())
+                            };
+                            assert!(content.iter().all(|byte| *byte == value));
+                            unsafe { alloc.deallocate(block.as_non_null_ptr(), layout) };
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in thread_list.into_iter() {
+            thread.join().unwrap();
+        }
+    }
+    #[test]
+    fn fewer_locks() {
+        let direct = counted();
+        workload(direct.clone());
+        let direct_locks = direct.inner_allocator.locks.load(Ordering::Relaxed);
+        let cached = counted();
+        // The metadata are inside the address space
+        let idle = cached.total_used().unwrap();
+        workload(Arc::new(ThreadCache::new(cached.clone())));
+        let cached_locks = cached.inner_allocator.locks.load(Ordering::Relaxed);
+        assert!(cached_locks * 50 < direct_locks);
+        // The threads gave back their blocks when they exited
+        assert_eq!(cached.total_used().unwrap(), idle);
+    }
+    #[test]
+    fn zero_on_free() {
+        let memory = Box::leak(Box::new(vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN]));
+        let protected = Arc::new(
+            ProtectedAllocator::new(
+                Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                    aligned_slice(memory, ARENA_SIZE),
+                    None,
+                )),
+                None,
+            )
+            .with_zero_on_free(true),
+        );
+        let cache = ThreadCache::new(protected.clone());
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let block = cache.allocate(layout).unwrap();
+        unsafe { block.as_mut_ptr().write_bytes(0x42, block.len()) };
+        cache.deallocate(block.as_non_null_ptr(), layout).unwrap();
+        // The block stays in the cache, but the secret is already gone
+        let cached = unsafe { core::slice::from_raw_parts(block.as_mut_ptr(), block.len()) };
+        assert!(cached.iter().all(|byte| *byte == 0));
+        cache.flush().unwrap();
+    }
+    #[test]
+    fn poisoned_lock_on_free() {
+        let memory = Box::leak(Box::new(vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN]));
+        let protected = Arc::new(ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        ));
+        let cache = ThreadCache::new(protected.clone());
+        // Too big to be cached, it goes straight to the allocator
+        let layout = Layout::from_size_align(2 * CACHE_MAX_LEN, 8).unwrap();
+        let block = Allocator::allocate(&cache, layout).unwrap();
+        std::thread::scope(|s| {
+            assert!(s
+                .spawn(|| protected.with_locked(|_| panic!("poison the mutex")))
+                .join()
+                .is_err());
+        });
+        // The error is reported, not raised as a panic
+        unsafe { Allocator::deallocate(&cache, block.as_non_null_ptr(), layout) };
+        assert!(matches!(
+            protected.last_error(),
+            Some(BuddyError::LockPoisoned)
+        ));
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod metadata_checksum {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn changes_with_allocations() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(meta.as_mut_slice()),
+        );
+        alloc.lazy_init().unwrap();
+        let checksum = alloc.metadata_checksum();
+        // Queries do not touch the metadata
+        assert_eq!(alloc.largest_free_block(), ARENA_SIZE);
+        assert!(alloc.verify_checksum(checksum));
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let block = alloc.alloc(layout).unwrap();
+        assert!(!alloc.verify_checksum(checksum));
+        alloc.dealloc(block.as_non_null_ptr(), layout).unwrap();
+        // Same tree, same checksum
+        assert!(alloc.verify_checksum(checksum));
+    }
+}
+#[cfg(all(feature = "poison", not(feature = "no-std")))]
+mod poison {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn poisoned_blocks() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(meta.as_mut_slice()),
+        );
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let block = alloc.alloc(layout).unwrap();
+        assert!(unsafe { block.as_ref() }
+            .iter()
+            .all(|byte| *byte == POISON_ALLOC));
+        unsafe { block.as_mut_ptr().write_bytes(0x42, layout.size()) };
+        alloc.dealloc(block.as_non_null_ptr(), layout).unwrap();
+        // The whole block is poisoned, not only the requested size
+        let freed = unsafe { core::slice::from_raw_parts(block.as_mut_ptr(), block.len()) };
+        assert!(freed.iter().all(|byte| *byte == POISON_FREE));
+        // A refused free writes nothing
+        let other = alloc.alloc(layout).unwrap();
+        unsafe { other.as_mut_ptr().write_bytes(0x42, other.len()) };
+        alloc.dealloc(other.as_non_null_ptr(), layout).unwrap();
+        unsafe { other.as_mut_ptr().write_bytes(0x42, other.len()) };
+        assert!(alloc.dealloc(other.as_non_null_ptr(), layout).is_err());
+        assert!(unsafe { other.as_ref() }.iter().all(|byte| *byte == 0x42));
+    }
+    #[test]
+    fn resized_blocks() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(meta.as_mut_slice()),
+        );
+        let big = Layout::from_size_align(256, 8).unwrap();
+        let small = Layout::from_size_align(64, 8).unwrap();
+        // In place: the released tail is poisoned, the kept bytes are not
+        let block = alloc.alloc(big).unwrap();
+        unsafe { block.as_mut_ptr().write_bytes(0x42, block.len()) };
+        let shrunk = alloc.shrink(block.as_non_null_ptr(), big, small).unwrap();
+        assert_eq!(shrunk.as_mut_ptr(), block.as_mut_ptr());
+        let content = unsafe { core::slice::from_raw_parts(block.as_mut_ptr(), block.len()) };
+        assert!(content[..64].iter().all(|byte| *byte == 0x42));
+        assert!(content[64..].iter().all(|byte| *byte == POISON_FREE));
+        // Moved: the old block is poisoned
+        let _neighbour = alloc.alloc(small).unwrap();
+        let grown = alloc
+            .grow(shrunk.as_non_null_ptr(), small, big, false)
+            .unwrap();
+        assert_ne!(grown.as_mut_ptr(), shrunk.as_mut_ptr());
+        let old = unsafe { core::slice::from_raw_parts(shrunk.as_mut_ptr(), shrunk.len()) };
+        assert!(old.iter().all(|byte| *byte == POISON_FREE));
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod owns {
+    use super::*;
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn membership() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        // The metadata are inside the address space
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        );
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let block = alloc.allocate(layout).unwrap().as_non_null_ptr();
+        assert!(alloc.owns(block));
+        assert!(alloc.owns(NonNull::new(block.as_ptr().wrapping_add(63)).unwrap()));
+        let base = alloc.with_locked(|r| r.base_ptr()).unwrap();
+        let past_end = NonNull::new(base.as_ptr().wrapping_add(ARENA_SIZE)).unwrap();
+        assert!(!alloc.owns(past_end));
+        assert!(alloc.owns(NonNull::new(past_end.as_ptr().wrapping_sub(1)).unwrap()));
+        // The metadata are not allocable
+        assert!(!alloc.owns(base));
+        let elsewhere = Box::new(0_u64);
+        assert!(!alloc.owns(NonNull::from(&*elsewhere).cast::<u8>()));
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod prefer_high {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn low_region_stays_free() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(meta.as_mut_slice()),
+        );
+        alloc.set_prefer_high(true);
+        assert!(alloc.config().prefer_high);
+        let base = alloc.base_ptr().as_ptr() as usize;
+        let sizes = [64, 200, 8, 512, 100, 64, 32, 16];
+        let blocks: Vec<_> = sizes
+            .iter()
+            .map(|size| {
+                let layout = Layout::from_size_align(*size, 8).unwrap();
+                (alloc.alloc(layout).unwrap(), layout)
+            })
+            .collect();
+        // Everything went into the upper half, the first block at the very top
+        assert!(blocks
+            .iter()
+            .all(|(block, _)| block.as_mut_ptr() as usize - base >= ARENA_SIZE / 2));
+        assert_eq!(blocks[0].0.as_mut_ptr() as usize - base, ARENA_SIZE - 64);
+        // The low half is one block, for a later big allocation at the bottom
+        let low = Layout::from_size_align(ARENA_SIZE / 2, 8).unwrap();
+        assert_eq!(alloc.alloc(low).unwrap().as_mut_ptr() as usize, base);
+        for (block, layout) in blocks {
+            alloc.dealloc(block.as_non_null_ptr(), layout).unwrap();
+        }
+        // Back to the default policy, the lowest free block comes first
+        alloc.set_prefer_high(false);
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let block = alloc.alloc(layout).unwrap();
+        assert_eq!(block.as_mut_ptr() as usize - base, ARENA_SIZE / 2);
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod layout_of {
+    use super::*;
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn deallocate_with_recovered_layout() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        );
+        let idle = alloc.total_used().unwrap();
+        let blocks: Vec<NonNull<[u8]>> = [1, 8, 24, 100, 513]
+            .iter()
+            .map(|size| {
+                let layout = Layout::from_size_align(*size, 1).unwrap();
+                alloc.allocate(layout).unwrap()
+            })
+            .collect();
+        for block in blocks.iter() {
+            let layout = alloc.layout_of(block.as_non_null_ptr()).unwrap();
+            assert_eq!(layout.size(), block.len());
+            assert_eq!(layout.align(), 1);
+            let interior = NonNull::new(block.as_mut_ptr().wrapping_add(1)).unwrap();
+            assert!(matches!(
+                alloc.layout_of(interior),
+                Err(BuddyError::DoubleFreeOrCorruption)
+            ));
+        }
+        for block in blocks {
+            let layout = alloc.layout_of(block.as_non_null_ptr()).unwrap();
+            alloc.deallocate(block.as_non_null_ptr(), layout).unwrap();
+            assert!(matches!(
+                alloc.layout_of(block.as_non_null_ptr()),
+                Err(BuddyError::DoubleFreeOrCorruption)
+            ));
+        }
+        assert_eq!(alloc.total_used().unwrap(), idle);
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod deallocate_no_layout {
+    use super::*;
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 4096;
+    fn new_allocator(
+        memory: &mut Vec<u8>,
+    ) -> ProtectedAllocator<'_, Mutex<InnerAllocator<'_, MIN_CELL_LEN>>, MIN_CELL_LEN> {
+        ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        )
+    }
+    #[test]
+    fn normal_free() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = new_allocator(&mut memory);
+        let idle = alloc.total_used().unwrap();
+        let blocks: Vec<NonNull<[u8]>> = [8, 33, 200, 1000]
+            .iter()
+            .map(|size| {
+                let layout = Layout::from_size_align(*size, 8).unwrap();
+                alloc.allocate(layout).unwrap()
+            })
+            .collect();
+        for block in blocks.iter().rev() {
+            alloc.deallocate_no_layout(block.as_non_null_ptr()).unwrap();
+        }
+        assert_eq!(alloc.total_used().unwrap(), idle);
+    }
+    #[test]
+    fn double_free() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = new_allocator(&mut memory);
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let block = alloc.allocate(layout).unwrap().as_non_null_ptr();
+        alloc.deallocate_no_layout(block).unwrap();
+        assert!(matches!(
+            alloc.deallocate_no_layout(block),
+            Err(BuddyError::DoubleFreeOrCorruption)
+        ));
+        assert!(matches!(
+            alloc.last_error(),
+            Some(BuddyError::DoubleFreeOrCorruption)
+        ));
+    }
+    #[test]
+    fn mid_arena_pointer() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = new_allocator(&mut memory);
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        let block = alloc.allocate(layout).unwrap();
+        let used = alloc.total_used().unwrap();
+        let interior = NonNull::new(block.as_mut_ptr().wrapping_add(128)).unwrap();
+        assert!(matches!(
+            alloc.deallocate_no_layout(interior),
+            Err(BuddyError::DoubleFreeOrCorruption)
+        ));
+        assert_eq!(alloc.total_used().unwrap(), used);
+        alloc.deallocate_no_layout(block.as_non_null_ptr()).unwrap();
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod check_integrity {
+    use super::*;
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn corrupted_byte() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        );
+        assert!(alloc.check_integrity().is_ok());
+        for size in [8, 100, 700] {
+            alloc
+                .allocate(Layout::from_size_align(size, 8).unwrap())
+                .unwrap();
+        }
+        assert!(alloc.check_integrity().is_ok());
+        // The root tells a smaller free order than its children have
+        alloc
+            .with_locked(|r| {
+                let root = r.raw_meta()[1];
+                r.raw_meta()[1] = root - 1;
+            })
+            .unwrap();
+        assert!(matches!(
+            alloc.check_integrity(),
+            Err(BuddyError::CorruptedNode(1))
+        ));
+        assert!(matches!(
+            alloc.last_error(),
+            Some(BuddyError::CorruptedNode(1))
+        ));
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod allocate_hugepage {
+    use super::*;
+    use std::sync::Mutex;
+    const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+    const ARENA_SIZE: usize = 4 * HUGE_PAGE_SIZE;
+    #[test]
+    fn two_mib_aligned() {
+        let mut memory = vec![0; ARENA_SIZE + HUGE_PAGE_SIZE];
+        let offset = memory.as_ptr().align_offset(HUGE_PAGE_SIZE);
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                &mut memory[offset..offset + ARENA_SIZE],
+                Some(&mut meta),
+            )),
+            None,
+        );
+        // A small block first, the huge page goes after it
+        let small = Layout::from_size_align(64, 8).unwrap();
+        alloc.allocate(small).unwrap();
+        let block = alloc.allocate_hugepage(HUGE_PAGE_SIZE).unwrap();
+        assert_eq!(block.len(), HUGE_PAGE_SIZE);
+        assert_eq!(block.as_mut_ptr() as usize % HUGE_PAGE_SIZE, 0);
+        assert!(matches!(
+            alloc.allocate_hugepage(HUGE_PAGE_SIZE + 1),
+            Err(BuddyError::TooBigSize)
+        ));
+        alloc
+            .deallocate(
+                block.as_non_null_ptr(),
+                Layout::from_size_align(HUGE_PAGE_SIZE, 1).unwrap(),
+            )
+            .unwrap();
+    }
+    #[test]
+    fn misaligned_base() {
+        let mut memory = vec![0; 2 * ARENA_SIZE];
+        let offset = memory.as_ptr().align_offset(HUGE_PAGE_SIZE) + MAX_SUPPORTED_ALIGN;
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                &mut memory[offset..offset + ARENA_SIZE],
+                Some(&mut meta),
+            )),
+            None,
+        );
+        assert!(matches!(
+            alloc.allocate_hugepage(HUGE_PAGE_SIZE),
+            Err(BuddyError::TooBigAlignment)
+        ));
+        assert!(alloc.allocate_hugepage(MAX_SUPPORTED_ALIGN).is_ok());
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod to_dot {
+    use super::*;
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn split_and_occupied_nodes() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                Some(&mut meta),
+            )),
+            None,
+        );
+        let dot = alloc.to_dot().unwrap();
+        assert_eq!(dot.matches("[label=").count(), 1);
+        assert!(dot.contains("fillcolor=green"));
+        alloc
+            .allocate(Layout::from_size_align(2048, 8).unwrap())
+            .unwrap();
+        alloc
+            .allocate(Layout::from_size_align(1024, 8).unwrap())
+            .unwrap();
+        // The root and its right half are split, into the two blocks and a free quarter
+        let dot = alloc.to_dot().unwrap();
+        assert!(dot.starts_with("digraph buddy {"));
+        assert_eq!(dot.matches("[label=").count(), 5);
+        assert_eq!(dot.matches(" -> ").count(), 4);
+        assert_eq!(dot.matches("fillcolor=red").count(), 2);
+        assert_eq!(dot.matches("fillcolor=green").count(), 1);
+        assert!(dot.contains("n3 -> n6;"));
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod freeze {
+    use super::*;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn read_back() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            None,
+        );
+        let blocks: Vec<NonNull<u8>> = [(16, 0x11), (100, 0x22), (500, 0x33)]
+            .iter()
+            .map(|(size, byte)| {
+                let block = alloc
+                    .alloc(Layout::from_size_align(*size, 8).unwrap())
+                    .unwrap();
+                unsafe { block.as_mut_ptr().write_bytes(*byte, *size) };
+                block.as_non_null_ptr()
+            })
+            .collect();
+        let stats = alloc.snapshot_stats();
+        let frozen = alloc.freeze();
+        assert_eq!(frozen.stats(), stats);
+        // The metadata chunk is a live block too
+        assert_eq!(frozen.live_blocks().count(), 4);
+        assert_eq!(frozen.block(blocks[0]).unwrap()[..16], [0x11; 16]);
+        assert_eq!(frozen.block(blocks[1]).unwrap()[..100], [0x22; 100]);
+        assert_eq!(frozen.block(blocks[2]).unwrap().len(), 512);
+        assert!(frozen.block(blocks[2]).unwrap()[..500]
+            .iter()
+            .all(|b| *b == 0x33));
+        let interior = NonNull::new(blocks[2].as_ptr().wrapping_add(8)).unwrap();
+        assert!(frozen.block(interior).is_err());
+        assert!(frozen.owns(interior));
+        // The metadata are at the begining of the address space
+        assert_eq!(
+            frozen.arena().len(),
+            ARENA_SIZE - ARENA_SIZE / MIN_CELL_LEN * 2
+        );
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod free_histogram {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn hand_computed() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let alloc = ThreadSafeAllocator::new(Arc::new(ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                Some(&mut meta),
+            )),
+            None,
+        )));
+        let mut expected = [0; usize::BITS as usize];
+        expected[0] = 1;
+        assert_eq!(alloc.free_histogram().unwrap(), expected);
+        for size in [1024, 64, 8] {
+            alloc
+                .allocate(Layout::from_size_align(size, 8).unwrap())
+                .unwrap();
+        }
+        // [0, 1024) and [1024, 1088) are taken, the 8 bytes block splits [1088, 1152)
+        let mut expected = [0; usize::BITS as usize];
+        for order in [1, 3, 4, 5, 7, 8, 9] {
+            expected[order] = 1;
+        }
+        let histogram = alloc.free_histogram().unwrap();
+        assert_eq!(histogram, expected);
+        let free: usize = (0..histogram.len())
+            .map(|order| histogram[order] * (ARENA_SIZE >> order))
+            .sum();
+        assert_eq!(free, alloc.total_free().unwrap());
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod split_mode {
+    use super::*;
+    const ARENA_SIZE: usize = 64 * 1024;
+    const BLOCK_LEN: usize = 64;
+    const LIVE: usize = 256;
+    const ROUNDS: usize = 1_000_000;
+    #[test]
+    fn eager_blocks() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(&mut meta),
+        );
+        alloc.set_split_mode(SplitMode::Eager(BLOCK_LEN));
+        assert_eq!(alloc.config().split_mode, SplitMode::Eager(BLOCK_LEN));
+        let layout = Layout::from_size_align(BLOCK_LEN, 8).unwrap();
+        let other = Layout::from_size_align(200, 8).unwrap();
+        // A whole batch comes from one 1024 bytes block, lowest address first
+        let first = alloc.alloc(layout).unwrap();
+        let base = alloc.base_ptr().addr().get();
+        assert_eq!(first.as_mut_ptr().addr() - base, 0);
+        let mut blocks = vec![first];
+        for i in 1..EAGER_BLOCKS {
+            let block = alloc.alloc(layout).unwrap();
+            assert_eq!(block.as_mut_ptr().addr() - base, i * BLOCK_LEN);
+            blocks.push(block);
+        }
+        // The other sizes still descend the tree
+        let big = alloc.alloc(other).unwrap();
+        assert_eq!(big.as_mut_ptr().addr() - base, EAGER_BLOCKS * BLOCK_LEN);
+        // The last freed block is given again
+        let freed = blocks.swap_remove(3);
+        alloc.dealloc(freed.as_non_null_ptr(), layout).unwrap();
+        let again = alloc.alloc(layout).unwrap();
+        assert_eq!(again.as_mut_ptr(), freed.as_mut_ptr());
+        blocks.push(again);
+        alloc.check_integrity().unwrap();
+        for block in blocks {
+            alloc.dealloc(block.as_non_null_ptr(), layout).unwrap();
+        }
+        alloc.dealloc(big.as_non_null_ptr(), other).unwrap();
+        alloc.check_integrity().unwrap();
+        assert_eq!(alloc.total_used(), 0);
+        // A ready block taken by a bigger allocation is not given
+        let whole = Layout::from_size_align(ARENA_SIZE, 8).unwrap();
+        let block = alloc.alloc(whole).unwrap();
+        assert!(matches!(alloc.alloc(layout), Err(BuddyError::NoMoreSpace)));
+        alloc.dealloc(block.as_non_null_ptr(), whole).unwrap();
+    }
+    fn churn(mode: SplitMode) -> f64 {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut meta = vec![0; ARENA_SIZE / MIN_CELL_LEN * 2];
+        let mut alloc = InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+            aligned_slice(&mut memory, ARENA_SIZE),
+            Some(&mut meta),
+        );
+        alloc.set_split_mode(mode);
+        let layout = Layout::from_size_align(BLOCK_LEN, 8).unwrap();
+        let mut live: Vec<NonNull<[u8]>> =
+            (0..LIVE).map(|_| alloc.alloc(layout).unwrap()).collect();
+        let start = std::time::Instant::now();
+        for round in 0..ROUNDS {
+            // ___ Pseudo random victim, the same sequence for both modes ___
+            let victim = round.wrapping_mul(0x9e37_79b9) % LIVE;
+            alloc
+                .dealloc(live[victim].as_non_null_ptr(), layout)
+                .unwrap();
+            live[victim] = alloc.alloc(layout).unwrap();
+        }
+        ROUNDS as f64 / start.elapsed().as_secs_f64() / 1e6
+    }
+    /// Benchmark: cargo test --release split_mode -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn churn_throughput() {
+        let lazy = churn(SplitMode::Lazy);
+        let eager = churn(SplitMode::Eager(BLOCK_LEN));
+        println!(
+            "lazy (tree descent): {:.1} Mallocs/s, eager (ready blocks): {:.1} Mallocs/s",
+            lazy, eager
+        );
+    }
+}
+#[cfg(not(feature = "no-std"))]
+mod named_error_hook {
+    use super::*;
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 4096;
+    static ERRORS: Mutex<Vec<(&'static str, &'static str)>> = Mutex::new(Vec::new());
+    fn record(error: BuddyError, name: &'static str) {
+        ERRORS.lock().unwrap().push((name, error.into()));
+    }
+    #[test]
+    fn two_arenas() {
+        let mut first_memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let mut second_memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let first = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut first_memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        )
+        .with_name("first")
+        .with_named_error_hook(Some(record));
+        let second = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut second_memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        )
+        .with_name("second")
+        .with_named_error_hook(Some(record));
+        assert_eq!(first.name(), "first");
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let block = first.allocate(layout).unwrap();
+        first.deallocate(block.as_non_null_ptr(), layout).unwrap();
+        assert!(first.deallocate(block.as_non_null_ptr(), layout).is_err());
+        assert!(second
+            .allocate(Layout::from_size_align(2 * ARENA_SIZE, 8).unwrap())
+            .is_err());
+        assert_eq!(
+            *ERRORS.lock().unwrap(),
+            [
+                ("first", BuddyError::DoubleFreeOrCorruption.into()),
+                ("second", BuddyError::CannotFit.into())
+            ]
+        );
+    }
+}
+#[cfg(all(not(feature = "no-std"), feature = "stats"))]
+mod peak_used {
+    use super::*;
+    use std::sync::Mutex;
+    const ARENA_SIZE: usize = 4096;
+    #[test]
+    fn high_water_mark() {
+        let mut memory = vec![0; ARENA_SIZE + MAX_SUPPORTED_ALIGN];
+        let alloc = ProtectedAllocator::new(
+            Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_refs(
+                aligned_slice(&mut memory, ARENA_SIZE),
+                None,
+            )),
+            None,
+        );
+        assert_eq!(alloc.peak_used().unwrap(), 0);
+        let layouts = [
+            Layout::from_size_align(512, 8).unwrap(),
+            Layout::from_size_align(100, 8).unwrap(),
+            Layout::from_size_align(8, 8).unwrap(),
+        ];
+        let blocks: Vec<NonNull<[u8]>> = layouts
+            .iter()
+            .map(|layout| alloc.allocate(*layout).unwrap())
+            .collect();
+        // The metadata block is inside the address space
+        let peak = alloc.total_used().unwrap();
+        assert_eq!(peak, ARENA_SIZE / MIN_CELL_LEN * 2 + 512 + 128 + 8);
+        assert_eq!(alloc.peak_used().unwrap(), peak);
+        // Grown in place then shrunk back
+        let grown = alloc
+            .grow(
+                blocks[2].as_non_null_ptr(),
+                layouts[2],
+                Layout::from_size_align(16, 8).unwrap(),
+                false,
+            )
+            .unwrap();
+        assert_eq!(alloc.peak_used().unwrap(), peak + 8);
+        alloc
+            .shrink(
+                grown.as_non_null_ptr(),
+                Layout::from_size_align(16, 8).unwrap(),
+                layouts[2],
+            )
+            .unwrap();
+        for (block, layout) in blocks.iter().zip(layouts.iter()).take(2) {
+            alloc.deallocate(block.as_non_null_ptr(), *layout).unwrap();
+        }
+        assert_eq!(
+            alloc.total_used().unwrap(),
+            ARENA_SIZE / MIN_CELL_LEN * 2 + 8
+        );
+        assert_eq!(alloc.peak_used().unwrap(), peak + 8);
+        alloc.reset_peak().unwrap();
+        assert_eq!(alloc.peak_used().unwrap(), alloc.total_used().unwrap());
+    }
+}
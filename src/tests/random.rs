@@ -1,7 +1,9 @@
 //! This module provides randomize functions
 
+mod lfsr;
 mod lfsr16;
-use lfsr16::{lfsr16_get_pseudo_number, lfsr16_set_seed};
+pub use lfsr::Lfsr;
+use lfsr16::{lfsr16_get_pseudo_number, lfsr16_set_seed, lfsr_set_generator};
 
 /// Has provide two methods
 /// rand is totally undetermined and use RDRAND cpu feature (ivybridge +)
@@ -16,6 +18,11 @@ pub fn srand_init(seed: u16) {
     lfsr16_set_seed(seed)
 }
 
+/// Seed with another polynomial or the 32 bits register
+pub fn srand_init_with(lfsr: Lfsr) {
+    lfsr_set_generator(lfsr)
+}
+
 /// f32 rand: -self..+self as f32
 impl Rand for f32 {
     /// [i32::MIN..i32::MAX] € Z -> [+1..~-1] € D -> [+self..-self] € D
@@ -116,7 +123,7 @@ impl Rand for bool {
 
 #[cfg(test)]
 mod test {
-    use super::{srand_init, Rand};
+    use super::{srand_init, srand_init_with, Lfsr, Rand};
 
     #[test]
     fn random_out_of_bound_i16_test() {
@@ -162,6 +169,21 @@ mod test {
         }
     }
     #[test]
+    fn random_out_of_bound_u32_lfsr32_test() {
+        srand_init_with(
+            Lfsr::builder()
+                .taps(Lfsr::TAPS_32)
+                .width(32)
+                .seed(42)
+                .build(),
+        );
+        for i in (0..u32::MAX).into_iter().step_by(4096) {
+            // test unsigned 32 with the 32 bits register
+            let x: u32 = i.srand();
+            assert!(x <= i);
+        }
+    }
+    #[test]
     fn random_out_of_bound_f32_test() {
         srand_init(42);
         for i in (0..u32::MAX).into_iter().step_by(4096) {
@@ -0,0 +1,122 @@
+//! Fibonacci LFSR with a configurable feedback polynomial
+//! see https://en.wikipedia.org/wiki/Linear-feedback_shift_register
+
+/// Shift register of 'width' bits: the feedback bit is the parity of the tapped bits
+pub struct Lfsr {
+    state: u32,
+    taps: u32,
+    width: u32,
+}
+
+/// Build a Lfsr, by default the lfsr16 one: taps 0, 2, 3, 5 on 16 bits
+pub struct LfsrBuilder {
+    taps: u32,
+    width: u32,
+    seed: u32,
+}
+
+impl Lfsr {
+    /// x^16 + x^14 + x^13 + x^11 + 1, used by lfsr16
+    pub const TAPS_16: u32 = 1 << 0 | 1 << 2 | 1 << 3 | 1 << 5;
+    /// x^16 + x^15 + x^13 + x^4 + 1
+    pub const TAPS_16_ALT: u32 = 1 << 0 | 1 << 1 | 1 << 3 | 1 << 12;
+    /// x^32 + x^22 + x^2 + x + 1
+    pub const TAPS_32: u32 = 1 << 0 | 1 << 10 | 1 << 30 | 1 << 31;
+
+    /// Start a builder with the lfsr16 defaults
+    pub fn builder() -> LfsrBuilder {
+        LfsrBuilder {
+            taps: Self::TAPS_16,
+            width: 16,
+            seed: 1,
+        }
+    }
+    /// Shift once and return the new bit
+    #[inline(always)]
+    pub fn next_bit(&mut self) -> u32 {
+        let bit = (self.state & self.taps).count_ones() & 0b1;
+        self.state = self.state >> 1 | bit << (self.width - 1);
+        bit
+    }
+    /// Shift 32 times, the first bit is the lowest
+    pub fn next_u32(&mut self) -> u32 {
+        (0..32).fold(0, |word, j| word | self.next_bit() << j)
+    }
+    /// Period of the sequence when the polynomial is maximal
+    pub fn period(&self) -> u64 {
+        (1 << self.width) - 1
+    }
+    /// Current register content, the last 'width' bits shifted in
+    pub fn state(&self) -> u32 {
+        self.state
+    }
+}
+
+impl LfsrBuilder {
+    /// Bits of the register which feed back
+    pub fn taps(mut self, taps: u32) -> Self {
+        self.taps = taps;
+        self
+    }
+    /// Register len, from 2 to 32 bits
+    pub fn width(mut self, width: u32) -> Self {
+        self.width = width;
+        self
+    }
+    /// Initial register content, only its 'width' low bits are kept
+    pub fn seed(mut self, seed: u32) -> Self {
+        self.seed = seed;
+        self
+    }
+    /// Check the parameters and make the register: the taps must fit into the width and
+    /// include bit 0, the kept seed bits cannot all be zero
+    pub fn build(self) -> Lfsr {
+        assert!((2..=32).contains(&self.width));
+        let mask = u32::MAX >> (32 - self.width);
+        assert!(self.taps & !mask == 0 && self.taps & 0b1 != 0);
+        assert!(self.seed & mask != 0, "Seed must be greater than 0");
+        Lfsr {
+            state: self.seed & mask,
+            taps: self.taps,
+            width: self.width,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Lfsr;
+
+    /// Shift until the seed comes back
+    fn measure_period(mut lfsr: Lfsr) -> u64 {
+        let seed = lfsr.state();
+        let mut steps = 0;
+        loop {
+            lfsr.next_bit();
+            steps += 1;
+            if lfsr.state() == seed || steps > lfsr.period() {
+                return steps;
+            }
+        }
+    }
+    #[test]
+    fn full_period_16() {
+        for taps in [Lfsr::TAPS_16, Lfsr::TAPS_16_ALT] {
+            let lfsr = Lfsr::builder().taps(taps).seed(0xace1).build();
+            assert_eq!(measure_period(lfsr), (1 << 16) - 1);
+        }
+    }
+    #[test]
+    fn non_maximal_polynomial() {
+        let lfsr = Lfsr::builder()
+            .taps(1 << 0 | 1 << 3 | 1 << 5 | 1 << 6)
+            .build();
+        assert!(measure_period(lfsr) < (1 << 16) - 1);
+    }
+    #[test]
+    #[ignore] // 2^32 shifts: run it with --release --ignored
+    fn full_period_32() {
+        let lfsr = Lfsr::builder().taps(Lfsr::TAPS_32).width(32).build();
+        assert_eq!(measure_period(lfsr), u32::MAX as u64);
+    }
+}
@@ -1,10 +1,12 @@
 //! see https://en.wikipedia.org/wiki/Linear-feedback_shift_register
+use super::lfsr::Lfsr;
+
 const SEQ_SIZE: usize = 1 << 11;
 
 struct LfsrFibonnaci {
     pub registers: [u32; SEQ_SIZE],
     pub current_offset: usize,
-    pub stored_seed: Option<u16>,
+    pub stored_seed: Option<u32>,
 }
 
 /// Main structure
@@ -37,13 +39,26 @@ pub fn lfsr16_set_seed(seed: u16) {
                     );
                 }
             }
-            LFSR_FIBONACCI.stored_seed = Some(seed);
+            LFSR_FIBONACCI.stored_seed = Some(seed as u32);
         }
         // partial check of algorythm calculation success
         assert!(lfsr << 1 == seed & 0xfffe);
     }
 }
 
+/// Fill the suite with another register
+pub fn lfsr_set_generator(mut lfsr: Lfsr) {
+    let seed = lfsr.state();
+    for offset in 0..SEQ_SIZE {
+        let word = lfsr.next_u32();
+        unsafe { LFSR_FIBONACCI.registers[offset] = word };
+    }
+    unsafe {
+        LFSR_FIBONACCI.current_offset = 0;
+        LFSR_FIBONACCI.stored_seed = Some(seed);
+    }
+}
+
 /// move offset into flsr
 #[inline(always)]
 fn move_offset(offset: usize) -> usize {
@@ -0,0 +1,246 @@
+//! Per thread cache of small blocks in front of a ProtectedAllocator, like the tcmalloc ones
+//!
+//! Each thread keeps the freed blocks of each size class up to CACHE_CAPACITY, and serves the
+//! next allocations of that class from them without taking the lock. On a miss, CACHE_BATCH
+//! blocks are taken under a single lock, and half the cache goes back the same way when it
+//! overflows. A thread gives back its blocks when it exits. With zero_on_free, a block is
+//! wiped as it enters the cache. The cache trusts its caller: a block freed twice is given
+//! twice, and the blocks taken from the cache are not tagged by MTE
+
+use super::inner_allocator::{BuddyError, InnerAllocator};
+use super::mutex::RwMutex;
+use super::ProtectedAllocator;
+
+use core::alloc::{AllocError, Allocator, Layout};
+use core::ops::Deref;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::cell::RefCell;
+
+/// Blocks up to this size are cached
+pub const CACHE_MAX_LEN: usize = 4096;
+/// Blocks of a size class kept by a thread
+pub const CACHE_CAPACITY: usize = 64;
+/// Blocks moved from or to the allocator under one lock
+pub const CACHE_BATCH: usize = 16;
+
+const CLASSES: usize = CACHE_MAX_LEN.trailing_zeros() as usize + 1;
+
+type Bins = [Vec<NonNull<[u8]>>; CLASSES];
+
+/// The blocks of one ThreadCache held by the current thread, given back on drop
+struct LocalCache {
+    owner: usize,
+    bins: Bins,
+    flush: Box<dyn Fn(&mut Bins)>,
+}
+
+impl Drop for LocalCache {
+    fn drop(&mut self) {
+        (self.flush)(&mut self.bins);
+    }
+}
+
+thread_local! {
+    static CACHES: RefCell<Vec<LocalCache>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Identity of each ThreadCache, an address may be reused after a drop
+static NEXT_OWNER: AtomicUsize = AtomicUsize::new(0);
+
+/// Allocator with a per thread cache of small blocks, to take the lock less often
+/// It allocates itself from the global allocator, so it cannot be the global allocator
+pub struct ThreadCache<T, X, const M: usize>
+where
+    T: Deref<Target = ProtectedAllocator<'static, X, M>> + Send + Sync + Clone + 'static,
+    X: RwMutex<InnerAllocator<'static, M>> + Send + Sync + 'static,
+{
+    protected_allocator: T,
+    owner: usize,
+}
+
+impl<T, X, const M: usize> ThreadCache<T, X, M>
+where
+    T: Deref<Target = ProtectedAllocator<'static, X, M>> + Send + Sync + Clone + 'static,
+    X: RwMutex<InnerAllocator<'static, M>> + Send + Sync + 'static,
+{
+    /// Put a cache in front of 'protected_allocator'
+    pub fn new(protected_allocator: T) -> Self {
+        Self {
+            protected_allocator,
+            owner: NEXT_OWNER.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+    /// Allocate memory, from the cache of the current thread for the small blocks
+    pub fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, BuddyError> {
+        let class = match class_of::<M>(layout) {
+            Some(class) => class,
+            None => return self.protected_allocator.allocate(layout),
+        };
+        let cached = CACHES.try_with(|caches| {
+            let mut caches = caches.borrow_mut();
+            let bin = &mut self.local(&mut caches).bins[class];
+            if bin.is_empty() {
+                refill(&self.protected_allocator, bin, class)?;
+            }
+            Ok(bin.pop().expect("Woot ? Just refilled"))
+        });
+        // ___ The thread is exiting, its cache is gone ___
+        cached.unwrap_or_else(|_| self.protected_allocator.allocate(layout))
+    }
+    /// Deallocate memory, into the cache of the current thread for the small blocks
+    pub fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) -> Result<(), BuddyError> {
+        let class = match class_of::<M>(layout) {
+            Some(class) => class,
+            None => return self.protected_allocator.deallocate(ptr, layout),
+        };
+        let cached = CACHES.try_with(|caches| {
+            let mut caches = caches.borrow_mut();
+            let bin = &mut self.local(&mut caches).bins[class];
+            // ___ The whole block is wiped, it is given back later without a second pass ___
+            if self.protected_allocator.zero_on_free {
+                unsafe { ptr.as_ptr().write_bytes(0, block_len(class)) };
+            }
+            bin.push(NonNull::slice_from_raw_parts(ptr, block_len(class)));
+            if bin.len() > CACHE_CAPACITY {
+                let kept = bin.len() - CACHE_CAPACITY / 2;
+                give_back(&self.protected_allocator, bin, class, kept)?;
+            }
+            Ok(())
+        });
+        cached.unwrap_or_else(|_| {
+            self.protected_allocator
+                .deallocate(ptr, block_layout(class))
+        })
+    }
+    /// Give back to the allocator the blocks cached by the current thread
+    pub fn flush(&self) -> Result<(), BuddyError> {
+        CACHES
+            .try_with(|caches| {
+                let mut caches = caches.borrow_mut();
+                let bins = &mut self.local(&mut caches).bins;
+                (0..CLASSES).try_for_each(|class| {
+                    give_back(&self.protected_allocator, &mut bins[class], class, 0)
+                })
+            })
+            .unwrap_or(Ok(()))
+    }
+    /// Cache of this allocator for the current thread, created on first use
+    fn local<'c>(&self, caches: &'c mut Vec<LocalCache>) -> &'c mut LocalCache {
+        let position = match caches.iter().position(|cache| cache.owner == self.owner) {
+            Some(position) => position,
+            None => {
+                let protected_allocator = self.protected_allocator.clone();
+                caches.push(LocalCache {
+                    owner: self.owner,
+                    bins: Default::default(),
+                    flush: Box::new(move |bins| {
+                        for (class, bin) in bins.iter_mut().enumerate() {
+                            let _ = give_back(&protected_allocator, bin, class, 0);
+                        }
+                    }),
+                });
+                caches.len() - 1
+            }
+        };
+        &mut caches[position]
+    }
+}
+
+impl<T, X, const M: usize> Drop for ThreadCache<T, X, M>
+where
+    T: Deref<Target = ProtectedAllocator<'static, X, M>> + Send + Sync + Clone + 'static,
+    X: RwMutex<InnerAllocator<'static, M>> + Send + Sync + 'static,
+{
+    /// The caches of the other threads are given back when they exit
+    fn drop(&mut self) {
+        let _ = CACHES.try_with(|caches| {
+            let cache = {
+                let mut caches = caches.borrow_mut();
+                caches
+                    .iter()
+                    .position(|cache| cache.owner == self.owner)
+                    .map(|position| caches.swap_remove(position))
+            };
+            drop(cache);
+        });
+    }
+}
+
+unsafe impl<T, X, const M: usize> Allocator for ThreadCache<T, X, M>
+where
+    T: Deref<Target = ProtectedAllocator<'static, X, M>> + Send + Sync + Clone + 'static,
+    X: RwMutex<InnerAllocator<'static, M>> + Send + Sync + 'static,
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate(layout).map_err(|e| e.into())
+    }
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // ___ The error hook is already called by deallocate() or by the flush ___
+        drop(self.deallocate(ptr, layout));
+    }
+}
+
+/// Size class of a layout: log2 of its block len, None when it is too big to be cached
+#[inline(always)]
+fn class_of<const M: usize>(layout: Layout) -> Option<usize> {
+    let len = layout.size().max(layout.align()).max(M).next_power_of_two();
+    (len <= CACHE_MAX_LEN).then(|| len.trailing_zeros() as usize)
+}
+
+#[inline(always)]
+fn block_len(class: usize) -> usize {
+    1 << class
+}
+
+/// Layout of the blocks of a class: a block of buddy size is aligned on its size
+#[inline(always)]
+fn block_layout(class: usize) -> Layout {
+    Layout::from_size_align(block_len(class), block_len(class)).expect("Woot ? A power of two")
+}
+
+/// Take CACHE_BATCH blocks of 'class' under one lock, at least one or the error
+fn refill<X, const M: usize>(
+    protected_allocator: &ProtectedAllocator<'static, X, M>,
+    bin: &mut Vec<NonNull<[u8]>>,
+    class: usize,
+) -> Result<(), BuddyError>
+where
+    X: RwMutex<InnerAllocator<'static, M>>,
+{
+    let layout = block_layout(class);
+    let error = protected_allocator.locked(|r| {
+        (0..CACHE_BATCH).find_map(|_| match r.alloc(layout) {
+            Ok(block) => {
+                bin.push(block);
+                None
+            }
+            Err(e) => Some(e),
+        })
+    })?;
+    match (bin.is_empty(), error) {
+        (true, Some(e)) => Err(protected_allocator.check(e)),
+        _ => Ok(()),
+    }
+}
+
+/// Give back the blocks of 'class' under one lock, until 'kept' are left. They were wiped on
+/// their way into the cache when zero_on_free is set
+fn give_back<X, const M: usize>(
+    protected_allocator: &ProtectedAllocator<'static, X, M>,
+    bin: &mut Vec<NonNull<[u8]>>,
+    class: usize,
+    kept: usize,
+) -> Result<(), BuddyError>
+where
+    X: RwMutex<InnerAllocator<'static, M>>,
+{
+    if bin.len() <= kept {
+        return Ok(());
+    }
+    let layout = block_layout(class);
+    protected_allocator.try_locked(|r| {
+        bin.drain(kept..)
+            .try_for_each(|block| r.dealloc(block.as_non_null_ptr(), layout))
+    })
+}
@@ -0,0 +1,62 @@
+//! Fixed size object pool, usable without std
+//!
+//! When every allocation has the same size, the buddy tree is overkill: one bit per cell is
+//! enough. M is both the cell len and the biggest size and alignment accepted.
+
+use super::inner_allocator::BuddyError;
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+/// Pool of M bytes cells, with an occupancy bitmap of one bit per cell
+pub struct UniformPool<'a, const M: usize> {
+    arena: &'a mut [u8],
+    bitmap: &'a mut [u8],
+}
+
+impl<'a, const M: usize> UniformPool<'a, M> {
+    /// Bitmap bytes needed for an address space of 'len' bytes
+    pub const fn bitmap_len(len: usize) -> usize {
+        (len / M + 7) >> 3
+    }
+    /// Create a pool over 'arena', which must be a multiple of M and aligned on M
+    pub fn new_from_refs(arena: &'a mut [u8], bitmap: &'a mut [u8]) -> Self {
+        assert!(M.is_power_of_two());
+        assert!(arena.len() & (M - 1) == 0);
+        assert!(arena.as_ptr().align_offset(M) == 0); // Check pointer alignement
+        assert!(bitmap.len() >= Self::bitmap_len(arena.len()));
+        bitmap.fill(0);
+        Self { arena, bitmap }
+    }
+    /// Take the first free cell
+    pub fn alloc(&mut self, layout: Layout) -> Result<NonNull<[u8]>, BuddyError> {
+        if layout.size() > M || layout.align() > M {
+            return Err(BuddyError::CannotFit);
+        }
+        let cells = self.arena.len() / M;
+        // ___ Find first zero bit ___
+        let cell = self
+            .bitmap
+            .iter()
+            .position(|byte| *byte != 0xff)
+            .map(|i| i * 8 + self.bitmap[i].trailing_ones() as usize)
+            .filter(|cell| *cell < cells)
+            .ok_or(BuddyError::NoMoreSpace)?;
+        self.bitmap[cell / 8] |= 1 << (cell % 8);
+        Ok(NonNull::from(&mut self.arena[cell * M..(cell + 1) * M]))
+    }
+    /// Give back a cell
+    pub fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) -> Result<(), BuddyError> {
+        let offset = ptr.addr().get().wrapping_sub(self.arena.as_ptr().addr());
+        if layout.size() > M || offset >= self.arena.len() || offset & (M - 1) != 0 {
+            return Err(BuddyError::DoubleFreeOrCorruption);
+        }
+        let cell = offset / M;
+        let mask = 1 << (cell % 8);
+        if self.bitmap[cell / 8] & mask == 0 {
+            return Err(BuddyError::DoubleFreeOrCorruption);
+        }
+        self.bitmap[cell / 8] &= !mask;
+        Ok(())
+    }
+}
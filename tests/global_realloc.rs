@@ -0,0 +1,44 @@
+//! The allocator as #[global_allocator]: it must own its test binary
+#![cfg(not(any(feature = "no-std", feature = "no-generic-std-mutex-impl")))]
+#![feature(const_mut_refs)]
+#![feature(generic_const_exprs)]
+
+use night_buddy_allocator::{InnerAllocator, ProtectedAllocator, StaticAddressSpace};
+use std::ptr::NonNull;
+use std::sync::Mutex;
+
+const ARENA_SIZE: usize = 16 * 1024 * 1024;
+const MIN_CELL_LEN: usize = 64;
+
+static mut STATIC_SPACE: StaticAddressSpace<ARENA_SIZE, MIN_CELL_LEN> = StaticAddressSpace::new();
+#[global_allocator]
+static ALLOCATOR: ProtectedAllocator<Mutex<InnerAllocator<MIN_CELL_LEN>>, MIN_CELL_LEN> =
+    ProtectedAllocator::new(
+        Mutex::new(InnerAllocator::<MIN_CELL_LEN>::new_from_static(unsafe {
+            &mut STATIC_SPACE
+        })),
+        None,
+    );
+
+#[test]
+fn grow_string_across_orders() {
+    // 40 then 64 bytes: the buddy size does not change, realloc keeps the block
+    let mut s = String::with_capacity(40);
+    let ptr = s.as_ptr();
+    s.reserve_exact(64);
+    assert_eq!(s.as_ptr(), ptr);
+    for i in 0..100_000 {
+        s.push(char::from(b'a' + (i % 26) as u8));
+    }
+    assert!(s
+        .bytes()
+        .enumerate()
+        .all(|(i, c)| c == b'a' + (i % 26) as u8));
+    let owned = ALLOCATOR
+        .with_locked(|r| r.owns(NonNull::new(s.as_ptr() as *mut u8).unwrap()))
+        .unwrap();
+    assert!(owned);
+    s.truncate(10);
+    s.shrink_to_fit();
+    assert_eq!(s, "abcdefghij");
+}